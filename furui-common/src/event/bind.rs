@@ -13,6 +13,9 @@ pub struct BindEvent {
     pub family: EthProtocol,
     pub lport: c_ushort,
     pub protocol: IpProtocol,
+    pub exe_ino: u64,
+    pub exe_dev: u32,
+    pub cgroup_id: u64,
 }
 
 #[cfg(feature = "user")]
@@ -16,6 +16,9 @@ pub struct ConnectEvent {
     pub dst_port: u16,
     pub family: EthProtocol,
     pub protocol: IpProtocol,
+    pub exe_ino: u64,
+    pub exe_dev: u32,
+    pub cgroup_id: u64,
 }
 
 #[cfg(feature = "user")]
@@ -49,6 +52,9 @@ pub struct Connect6Event {
     pub dst_port: u16,
     pub family: EthProtocol,
     pub protocol: IpProtocol,
+    pub exe_ino: u64,
+    pub exe_dev: u32,
+    pub cgroup_id: u64,
 }
 
 #[cfg(feature = "user")]
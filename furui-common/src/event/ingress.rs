@@ -4,7 +4,8 @@ use furui_macros::{SearchIcmpPolicyKey, SearchPolicyKey};
 #[cfg(feature = "user")]
 use crate::event::common;
 use crate::{
-    EthProtocol, IcmpPolicyKey, IcmpVersion, IpProtocol, PolicyKey, TcAction, CONTAINER_ID_LEN,
+    search_rank, EthProtocol, FqdnPolicyKey, IcmpPolicyCidrKey, IcmpPolicyKey, IcmpVersion,
+    IpProtocol, PolicyCidrKey, PolicyKey, PolicyList, PolicyMatch, TcAction, CONTAINER_ID_LEN,
     IPV6_LEN, TASK_COMM_LEN,
 };
 
@@ -23,11 +24,16 @@ pub struct IngressEvent {
     #[search_key(protocol = IpProtocol::default())]
     pub protocol: IpProtocol,
     pub action: TcAction,
+    pub would_drop: bool,
     pub comm: [u8; TASK_COMM_LEN],
 }
 
 #[cfg(feature = "user")]
 impl IngressEvent {
+    pub fn action_name(&self) -> &'static str {
+        common::action_name(self.action, self.would_drop)
+    }
+
     pub fn container_id(&self) -> String {
         common::c_char_array_to_str(self.container_id)
     }
@@ -60,11 +66,16 @@ pub struct Ingress6Event {
     #[search_key(protocol = IpProtocol::default())]
     pub protocol: IpProtocol,
     pub action: TcAction,
+    pub would_drop: bool,
     pub comm: [u8; TASK_COMM_LEN],
 }
 
 #[cfg(feature = "user")]
 impl Ingress6Event {
+    pub fn action_name(&self) -> &'static str {
+        common::action_name(self.action, self.would_drop)
+    }
+
     pub fn container_id(&self) -> String {
         common::c_char_array_to_str(self.container_id)
     }
@@ -97,10 +108,15 @@ pub struct IngressIcmpEvent {
     #[search_key(code = 255)]
     pub code: u8,
     pub action: TcAction,
+    pub would_drop: bool,
 }
 
 #[cfg(feature = "user")]
 impl IngressIcmpEvent {
+    pub fn action_name(&self) -> &'static str {
+        common::action_name(self.action, self.would_drop)
+    }
+
     pub fn container_id(&self) -> String {
         common::c_char_array_to_str(self.container_id)
     }
@@ -129,10 +145,15 @@ pub struct Ingress6IcmpEvent {
     #[search_key(code = 255)]
     pub code: u8,
     pub action: TcAction,
+    pub would_drop: bool,
 }
 
 #[cfg(feature = "user")]
 impl Ingress6IcmpEvent {
+    pub fn action_name(&self) -> &'static str {
+        common::action_name(self.action, self.would_drop)
+    }
+
     pub fn container_id(&self) -> String {
         common::c_char_array_to_str(self.container_id)
     }
@@ -8,10 +8,68 @@ mod connect;
 mod egress;
 mod ingress;
 
+/// An event of the eBPF programs, tagged with the kind of the event, as it is sent through the
+/// `EVENTS` ring buffer.
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub enum EbpfEvent {
+    Bind(BindEvent),
+    Connect(ConnectEvent),
+    Connect6(Connect6Event),
+    /// The pid of a process of a container that exited, sent when its thread group leader exits.
+    Close(u32),
+    Ingress(IngressEvent),
+    Ingress6(Ingress6Event),
+    IngressIcmp(IngressIcmpEvent),
+    Ingress6Icmp(Ingress6IcmpEvent),
+    Egress(EgressEvent),
+    Egress6(Egress6Event),
+    EgressIcmp(EgressIcmpEvent),
+    Egress6Icmp(Egress6IcmpEvent),
+}
+
+impl EbpfEvent {
+    /// Whether the event is about the life of a process rather than a packet. Userspace keeps its
+    /// maps by these, so room is left for them in the ring buffer when packets flood it.
+    pub fn is_lifecycle(&self) -> bool {
+        matches!(
+            self,
+            EbpfEvent::Bind(_)
+                | EbpfEvent::Connect(_)
+                | EbpfEvent::Connect6(_)
+                | EbpfEvent::Close(_)
+        )
+    }
+}
+
+/// The pid of the process of a `bpf_get_current_pid_tgid` value, the thread group id. The rules are
+/// kept by process, so the events of every thread of a process carry it.
+pub fn process_id(pid_tgid: u64) -> u32 {
+    (pid_tgid >> 32) as u32
+}
+
+/// Whether a `bpf_get_current_pid_tgid` value is of the thread group leader, whose exit is the exit
+/// of the process, while the other threads exit on their own.
+pub fn is_group_leader(pid_tgid: u64) -> bool {
+    pid_tgid as u32 == process_id(pid_tgid)
+}
+
 #[cfg(feature = "user")]
 mod common {
     use aya_ebpf::cty::c_char;
 
+    use crate::TcAction;
+
+    /// The action of a packet event, or `would-drop` for a packet that was only passed because its
+    /// container is audited.
+    pub(crate) fn action_name(action: TcAction, would_drop: bool) -> &'static str {
+        if would_drop {
+            "would-drop"
+        } else {
+            action.to_string()
+        }
+    }
+
     pub(crate) fn u8_array_to_str<const N: usize>(array: [u8; N]) -> String {
         array
             .iter()
@@ -34,3 +92,29 @@ mod common {
             .to_string()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_exit_of_a_thread_is_not_the_exit_of_its_process() {
+        let leader = (1234u64 << 32) | 1234;
+        let thread = (1234u64 << 32) | 1240;
+
+        assert_eq!(process_id(leader), 1234);
+        assert_eq!(process_id(thread), 1234);
+        assert!(is_group_leader(leader));
+        assert!(!is_group_leader(thread));
+    }
+
+    #[cfg(feature = "user")]
+    #[test]
+    fn audited_drop_is_a_would_drop() {
+        use crate::TcAction;
+
+        assert_eq!(common::action_name(TcAction::Drop, true), "would-drop");
+        assert_eq!(common::action_name(TcAction::Drop, false), "drop");
+        assert_eq!(common::action_name(TcAction::Pass, false), "pass");
+    }
+}
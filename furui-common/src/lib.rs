@@ -88,7 +88,7 @@ const IPPROTO_UDP: u8 = 17;
 const IPPROTO_ICMP: u8 = 1;
 const IPPROTO_ICMPV6: u8 = 58;
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 #[repr(C)]
 pub enum IpProtocol {
     Default,
@@ -137,10 +137,11 @@ impl IpProtocol {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
 #[repr(C)]
 pub enum TcAction {
     Pass,
+    #[default]
     Drop,
 }
 
@@ -153,8 +154,25 @@ impl TcAction {
     }
 }
 
+/// The direction of a packet seen by the classifiers, from the side of the container.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[repr(C)]
+pub enum Direction {
+    Ingress,
+    Egress,
+}
+
+impl Direction {
+    pub fn to_string(&self) -> &'static str {
+        match self {
+            Direction::Ingress => "ingress",
+            Direction::Egress => "egress",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[repr(C)]
 pub enum IcmpVersion {
     Default,
     V4,
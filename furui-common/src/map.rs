@@ -1,11 +1,113 @@
+use core::mem::size_of;
 #[cfg(feature = "user")]
 use std::net::IpAddr;
 
 use aya_ebpf::cty::c_char;
 
-use crate::{IcmpVersion, IpProtocol, CONTAINER_ID_LEN, IPV6_LEN, TASK_COMM_LEN};
+use crate::{
+    Direction, IcmpVersion, IpProtocol, TcAction, CONTAINER_ID_LEN, IPV6_LEN, TASK_COMM_LEN,
+};
 
-#[derive(Debug, Copy, Clone)]
+/// The kind of list a policy was found in. Between policies whose keys use the same fields, an
+/// address (or a domain name) is more specific than a network, and a network than a port range.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PolicyList {
+    Address,
+    Network,
+    PortRange,
+}
+
+/// A policy matching a packet, of which the most specific one decides.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PolicyMatch {
+    /// Lower is more specific, see [`search_rank`].
+    pub rank: u32,
+    pub action: TcAction,
+}
+
+impl PolicyMatch {
+    /// The more specific of two matches. A deny wins over an allow only when they are exactly as
+    /// specific.
+    #[inline]
+    pub fn most_specific(
+        found: Option<PolicyMatch>,
+        other: Option<PolicyMatch>,
+    ) -> Option<PolicyMatch> {
+        match (found, other) {
+            (Some(found), Some(other)) => {
+                if other.rank < found.rank
+                    || (other.rank == found.rank && other.action == TcAction::Drop)
+                {
+                    Some(other)
+                } else {
+                    Some(found)
+                }
+            }
+            (found, None) => found,
+            (None, other) => other,
+        }
+    }
+}
+
+/// The rank of a key using the fields marked in `used`, which are listed from the most to the
+/// least specific as the search order of `furui-macros` has them. Keys using more fields come
+/// first, then among keys using as many fields those using the more specific ones, and then the
+/// kind of list breaks the tie.
+///
+/// This is the position of the key in the order the classifiers search, so that they can stop
+/// at the first key found in a list.
+pub const fn search_rank(used: &[bool], list: PolicyList) -> u32 {
+    let fields = used.len() as u32;
+
+    let mut used_fields = 0;
+    let mut i = 0;
+    while i < used.len() {
+        if used[i] {
+            used_fields += 1;
+        }
+        i += 1;
+    }
+
+    // Every key using more fields.
+    let mut position = 0;
+    let mut count = used_fields + 1;
+    while count <= fields {
+        position += binomial(fields, count);
+        count += 1;
+    }
+
+    // Every key using as many fields that comes before in lexicographic order, which is the
+    // order of `Itertools::combinations`.
+    let mut remaining = used_fields;
+    i = 0;
+    while i < used.len() && remaining > 0 {
+        if used[i] {
+            remaining -= 1;
+        } else {
+            position += binomial(fields - 1 - i as u32, remaining - 1);
+        }
+        i += 1;
+    }
+
+    position * 3 + list as u32
+}
+
+const fn binomial(n: u32, k: u32) -> u32 {
+    if k > n {
+        return 0;
+    }
+
+    let mut result = 1;
+    let mut i = 0;
+    while i < k {
+        result = result * (n - i) / (i + 1);
+        i += 1;
+    }
+
+    result
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 #[repr(C)]
 pub struct PolicyKey {
     pub container_id: [c_char; CONTAINER_ID_LEN],
@@ -17,7 +119,7 @@ pub struct PolicyKey {
     pub protocol: IpProtocol,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[repr(C)]
 pub struct PolicyValue {
     pub comm: [u8; TASK_COMM_LEN],
@@ -26,9 +128,152 @@ pub struct PolicyValue {
     pub local_port: u16,
     pub remote_port: u16,
     pub protocol: IpProtocol,
+    pub action: TcAction,
 }
 
-#[derive(Debug, Copy, Clone)]
+/// Key of the CIDR policy list, an LPM trie next to the exact-match policy list.
+///
+/// `remote_ip` must stay the last field: every field before it is matched exactly and the
+/// address is matched by the prefix length stored with the key. IPv4 addresses are stored
+/// in their IPv4-mapped IPv6 form.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct PolicyCidrKey {
+    pub container_id: [c_char; CONTAINER_ID_LEN],
+    pub comm: [u8; TASK_COMM_LEN],
+    pub local_port: u16,
+    pub remote_port: u16,
+    pub protocol: IpProtocol,
+    pub remote_ip: [u8; IPV6_LEN],
+}
+
+impl PolicyCidrKey {
+    /// Prefix length of a lookup key, which covers every field.
+    pub const LOOKUP_PREFIX_LEN: u32 = (size_of::<PolicyCidrKey>() * 8) as u32;
+
+    #[cfg(feature = "user")]
+    pub fn prefix_len(remote_ip: IpAddr, remote_prefix_len: u8) -> u32 {
+        cidr_prefix_len(
+            core::mem::offset_of!(PolicyCidrKey, remote_ip),
+            remote_ip,
+            remote_prefix_len,
+        )
+    }
+}
+
+/// Maximum number of port range policies per container and executable.
+pub const PORT_RANGE_POLICIES_MAX: usize = 16;
+
+/// The rules with a remote address are kept under that address, in its IPv4-mapped IPv6 form,
+/// so that a host resolving to more addresses takes no more room in any one entry. The rules
+/// without one, or with a network, are kept under the all-zero address.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct PortRangePolicyKey {
+    pub container_id: [c_char; CONTAINER_ID_LEN],
+    pub comm: [u8; TASK_COMM_LEN],
+    pub remote_ip: [u8; IPV6_LEN],
+}
+
+impl PortRangePolicyKey {
+    /// The addresses the rules for a packet from or to `remote_ip` are kept under.
+    #[inline]
+    pub fn remote_ips(remote_ip: &[u8; IPV6_LEN]) -> [[u8; IPV6_LEN]; 2] {
+        [[0; IPV6_LEN], *remote_ip]
+    }
+}
+
+/// A socket policy with a local or remote port range.
+///
+/// An unspecified port is stored as the full range, and an unspecified remote host as an
+/// all-zero mask. `remote_ip` is in its IPv4-mapped IPv6 form and already masked.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(C)]
+pub struct PortRangePolicy {
+    pub local_port_min: u16,
+    pub local_port_max: u16,
+    pub remote_port_min: u16,
+    pub remote_port_max: u16,
+    pub protocol: IpProtocol,
+    pub remote_ip: [u8; IPV6_LEN],
+    pub remote_ip_mask: [u8; IPV6_LEN],
+    pub action: TcAction,
+    /// See [`search_rank`], counting a field with a range as used.
+    pub rank: u32,
+}
+
+impl PortRangePolicy {
+    #[inline]
+    pub fn matches(
+        &self,
+        protocol: IpProtocol,
+        local_port: u16,
+        remote_port: u16,
+        remote_ip: &[u8; IPV6_LEN],
+    ) -> bool {
+        if self.protocol != IpProtocol::Default && self.protocol != protocol {
+            return false;
+        }
+
+        if local_port < self.local_port_min || local_port > self.local_port_max {
+            return false;
+        }
+
+        if remote_port < self.remote_port_min || remote_port > self.remote_port_max {
+            return false;
+        }
+
+        remote_ip
+            .iter()
+            .zip(self.remote_ip_mask.iter().zip(self.remote_ip.iter()))
+            .all(|(ip, (mask, policy_ip))| ip & mask == *policy_ip)
+    }
+}
+
+/// All port range policies of a container and executable, searched linearly for the most
+/// specific one that matches.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(C)]
+pub struct PortRangePolicies {
+    pub len: u32,
+    pub policies: [PortRangePolicy; PORT_RANGE_POLICIES_MAX],
+}
+
+impl PortRangePolicies {
+    #[inline]
+    pub fn search(
+        &self,
+        protocol: IpProtocol,
+        local_port: u16,
+        remote_port: u16,
+        remote_ip: &[u8; IPV6_LEN],
+    ) -> Option<PolicyMatch> {
+        let mut found = None;
+
+        for i in 0..PORT_RANGE_POLICIES_MAX {
+            if i >= self.len as usize {
+                break;
+            }
+
+            let policy = &self.policies[i];
+            if !policy.matches(protocol, local_port, remote_port, remote_ip) {
+                continue;
+            }
+
+            found = PolicyMatch::most_specific(
+                found,
+                Some(PolicyMatch {
+                    rank: policy.rank,
+                    action: policy.action,
+                }),
+            );
+        }
+
+        found
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 #[repr(C)]
 pub struct IcmpPolicyKey {
     pub container_id: [c_char; CONTAINER_ID_LEN],
@@ -39,7 +284,7 @@ pub struct IcmpPolicyKey {
     pub remote_ipv6: [u8; IPV6_LEN],
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[repr(C)]
 pub struct IcmpPolicyValue {
     pub version: IcmpVersion,
@@ -47,6 +292,230 @@ pub struct IcmpPolicyValue {
     pub code: u8,
     pub remote_ip: u32,
     pub remote_ipv6: [u8; IPV6_LEN],
+    pub action: TcAction,
+}
+
+/// Key of the ICMP CIDR policy list. See [`PolicyCidrKey`] for the layout requirements.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct IcmpPolicyCidrKey {
+    pub container_id: [c_char; CONTAINER_ID_LEN],
+    pub version: IcmpVersion,
+    pub type_: u8,
+    pub code: u8,
+    pub remote_ip: [u8; IPV6_LEN],
+}
+
+impl IcmpPolicyCidrKey {
+    /// Prefix length of a lookup key, which covers every field.
+    pub const LOOKUP_PREFIX_LEN: u32 = (size_of::<IcmpPolicyCidrKey>() * 8) as u32;
+
+    #[cfg(feature = "user")]
+    pub fn prefix_len(remote_ip: IpAddr, remote_prefix_len: u8) -> u32 {
+        cidr_prefix_len(
+            core::mem::offset_of!(IcmpPolicyCidrKey, remote_ip),
+            remote_ip,
+            remote_prefix_len,
+        )
+    }
+}
+
+/// Maximum length of a domain name in the DNS wire format, terminating zero included. Longer
+/// names are never matched.
+pub const FQDN_LEN: usize = 128;
+
+/// The first label of a wildcard domain name such as `*.example.com` in the DNS wire format.
+pub const FQDN_WILDCARD: [u8; 2] = [1, b'*'];
+
+/// Key of the domain names in the rules of a container, which the DNS responses to that container
+/// are matched against. The value is the ID of the name, see [`fqdn_id`].
+///
+/// `name` is in the DNS wire format and in lower case, see [`encode_fqdn`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct FqdnKey {
+    pub container_id: [c_char; CONTAINER_ID_LEN],
+    pub name: [u8; FQDN_LEN],
+}
+
+/// Key of the addresses a container was answered for the domain names in its rules. The value is
+/// the ID of the name. IPv4 addresses are stored in their IPv4-mapped IPv6 form.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct FqdnIpKey {
+    pub container_id: [c_char; CONTAINER_ID_LEN],
+    pub ip: [u8; IPV6_LEN],
+}
+
+/// Maximum number of domain names an address is remembered for in a container. CDNs and shared
+/// hosting answer the same address for many names, and the rules of each of them apply to it.
+pub const FQDN_IP_NAMES_MAX: usize = 4;
+
+/// The ID of a name an address was answered for, and when the answer expires by its TTL, in
+/// nanoseconds of `CLOCK_MONOTONIC` as `bpf_ktime_get_ns` counts them. Unused while `expires_at`
+/// is 0.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct FqdnIpName {
+    pub expires_at: u64,
+    pub fqdn_id: u32,
+}
+
+/// Value of [`FqdnIpKey`]: the names the address was answered for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct FqdnIpValue {
+    pub names: [FqdnIpName; FQDN_IP_NAMES_MAX],
+}
+
+impl FqdnIpValue {
+    /// Remembers that the address was answered for `fqdn_id` until `expires_at`. The name keeps
+    /// its entry when it has one, otherwise it takes the one expiring first, which is an unused
+    /// or an expired one when there is any.
+    #[inline]
+    pub fn answer(&mut self, fqdn_id: u32, expires_at: u64) {
+        let mut slot = 0;
+
+        for i in 0..FQDN_IP_NAMES_MAX {
+            let name = &self.names[i];
+
+            if name.expires_at != 0 && name.fqdn_id == fqdn_id {
+                slot = i;
+                break;
+            }
+
+            if name.expires_at < self.names[slot].expires_at {
+                slot = i;
+            }
+        }
+
+        self.names[slot] = FqdnIpName {
+            expires_at,
+            fqdn_id,
+        };
+    }
+
+    /// The IDs of the names whose answer has not expired at `now`.
+    #[inline]
+    pub fn fqdn_ids(&self, now: u64) -> [Option<u32>; FQDN_IP_NAMES_MAX] {
+        let mut fqdn_ids = [None; FQDN_IP_NAMES_MAX];
+
+        for i in 0..FQDN_IP_NAMES_MAX {
+            if self.names[i].expires_at > now {
+                fqdn_ids[i] = Some(self.names[i].fqdn_id);
+            }
+        }
+
+        fqdn_ids
+    }
+}
+
+/// Key of the DNS queries a container sent for the domain names in its rules and that have not
+/// been answered yet. The value is the ID of the name asked for. `id` is the ID of the query in
+/// network byte order, `port` the port it was sent from and `server` the address it was sent to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct DnsQueryKey {
+    pub container_id: [c_char; CONTAINER_ID_LEN],
+    pub id: u16,
+    pub port: u16,
+    pub server: [u8; IPV6_LEN],
+}
+
+/// Key of the rules naming a domain, where the ID of the name takes the place of the remote
+/// address.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct FqdnPolicyKey {
+    pub container_id: [c_char; CONTAINER_ID_LEN],
+    pub comm: [u8; TASK_COMM_LEN],
+    pub fqdn_id: u32,
+    pub local_port: u16,
+    pub remote_port: u16,
+    pub protocol: IpProtocol,
+}
+
+/// Encodes a domain name as its labels, each preceded by its length, as it appears in DNS
+/// messages. A wildcard name keeps `*` as its first label, see [`FQDN_WILDCARD`]. Returns `None`
+/// for names that are not valid or longer than [`FQDN_LEN`].
+#[cfg(feature = "user")]
+pub fn encode_fqdn(name: &str) -> Option<[u8; FQDN_LEN]> {
+    let mut encoded = [0; FQDN_LEN];
+    let mut len = 0;
+
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 || len + 1 + label.len() >= FQDN_LEN {
+            return None;
+        }
+
+        encoded[len] = label.len() as u8;
+        encoded[len + 1..len + 1 + label.len()]
+            .copy_from_slice(label.to_ascii_lowercase().as_bytes());
+        len += 1 + label.len();
+    }
+
+    Some(encoded)
+}
+
+/// ID of a domain name, which stays the same while furui is running so that the addresses
+/// learned for a name survive policy reloads.
+#[cfg(feature = "user")]
+pub fn fqdn_id(name: &str) -> u32 {
+    let mut id: u32 = 0x811c9dc5;
+
+    // FNV-1a
+    for byte in name.trim_end_matches('.').to_ascii_lowercase().bytes() {
+        id ^= byte as u32;
+        id = id.wrapping_mul(0x01000193);
+    }
+
+    id
+}
+
+/// Converts a host byte order IPv4 address into its IPv4-mapped IPv6 form (`::ffff:a.b.c.d`).
+#[inline]
+pub fn ipv4_mapped_ipv6(ip: u32) -> [u8; IPV6_LEN] {
+    let octets = ip.to_be_bytes();
+
+    let mut mapped = [0; IPV6_LEN];
+    mapped[10] = 0xff;
+    mapped[11] = 0xff;
+    mapped[12] = octets[0];
+    mapped[13] = octets[1];
+    mapped[14] = octets[2];
+    mapped[15] = octets[3];
+
+    mapped
+}
+
+#[cfg(feature = "user")]
+pub fn cidr_ip(ip: IpAddr) -> [u8; IPV6_LEN] {
+    match ip {
+        IpAddr::V4(ip) => ipv4_mapped_ipv6(ip.into()),
+        IpAddr::V6(ip) => ip.octets(),
+    }
+}
+
+/// Returns the mask of `remote_prefix_len` bits for the address returned by [`cidr_ip`].
+#[cfg(feature = "user")]
+pub fn cidr_mask(remote_ip: IpAddr, remote_prefix_len: u8) -> [u8; IPV6_LEN] {
+    let prefix_len = cidr_prefix_len(0, remote_ip, remote_prefix_len);
+
+    u128::MAX
+        .checked_shl(128 - prefix_len)
+        .unwrap_or(0)
+        .to_be_bytes()
+}
+
+#[cfg(feature = "user")]
+fn cidr_prefix_len(remote_ip_offset: usize, remote_ip: IpAddr, remote_prefix_len: u8) -> u32 {
+    let remote_prefix_len = match remote_ip {
+        // The IPv4-mapped prefix `::ffff:0:0/96` is always part of the match.
+        IpAddr::V4(_) => 96 + remote_prefix_len as u32,
+        IpAddr::V6(_) => remote_prefix_len as u32,
+    };
+
+    (remote_ip_offset * 8) as u32 + remote_prefix_len
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -61,6 +530,51 @@ pub struct PortKey {
 #[repr(C)]
 pub struct PortVal {
     pub comm: [u8; TASK_COMM_LEN],
+    /// The executable of the process, which unlike `comm` the process cannot change. Both are 0
+    /// when it is not known.
+    pub exe_ino: u64,
+    pub exe_dev: u32,
+    /// The process, as the kernel numbers it outside the container.
+    pub pid: u32,
+    /// The cgroup v2 ID of the process, 0 when it is not known.
+    pub cgroup_id: u64,
+}
+
+/// An executable in a container, by its inode and the device of its filesystem.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct ExeKey {
+    pub container_id: [c_char; CONTAINER_ID_LEN],
+    pub dev: u32,
+    pub ino: u64,
+}
+
+/// The name the rules of an executable are saved under, in place of the process name.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(C)]
+pub struct ExeValue {
+    pub comm: [u8; TASK_COMM_LEN],
+}
+
+/// A cgroup in a container, by its cgroup v2 ID. The value is an [`ExeValue`] with the name the
+/// rules of the cgroup are saved under.
+///
+/// The key has padding, so it must start zeroed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct CgroupKey {
+    pub container_id: [c_char; CONTAINER_ID_LEN],
+    pub cgroup_id: u64,
+}
+
+/// A process in a container that is, or descends from, a process running the executable of a
+/// `process_tree` rule. The value is an [`ExeValue`] with the name the rules of that executable
+/// are saved under.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct ProcessTreeKey {
+    pub container_id: [c_char; CONTAINER_ID_LEN],
+    pub pid: u32,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -86,12 +600,17 @@ impl ContainerIP {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 #[repr(C)]
 pub struct ContainerID {
     pub container_id: [c_char; CONTAINER_ID_LEN],
 }
 
+/// Key of `AUDIT_CONTAINERS` and `DEFAULT_ACTIONS` that applies to every container.
+pub const ALL_CONTAINERS: ContainerID = ContainerID {
+    container_id: [0; CONTAINER_ID_LEN],
+};
+
 #[cfg(feature = "user")]
 impl ContainerID {
     pub fn new(id: [c_char; CONTAINER_ID_LEN]) -> ContainerID {
@@ -99,16 +618,213 @@ impl ContainerID {
     }
 }
 
+/// Key of the packets the classifiers counted, by the action they took.
+///
+/// The key has padding, so it must start zeroed.
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct PacketCountKey {
+    pub container_id: [c_char; CONTAINER_ID_LEN],
+    pub direction: Direction,
+    pub protocol: IpProtocol,
+    pub action: TcAction,
+    pub would_drop: bool,
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+#[repr(C)]
+pub struct PacketCount {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
 #[cfg(feature = "user")]
 mod user {
     use super::*;
 
     unsafe impl aya::Pod for PolicyKey {}
     unsafe impl aya::Pod for PolicyValue {}
+    unsafe impl aya::Pod for PolicyCidrKey {}
+    unsafe impl aya::Pod for PortRangePolicyKey {}
+    unsafe impl aya::Pod for PortRangePolicies {}
     unsafe impl aya::Pod for IcmpPolicyKey {}
     unsafe impl aya::Pod for IcmpPolicyValue {}
+    unsafe impl aya::Pod for IcmpPolicyCidrKey {}
+    unsafe impl aya::Pod for FqdnKey {}
+    unsafe impl aya::Pod for FqdnIpKey {}
+    unsafe impl aya::Pod for FqdnIpValue {}
+    unsafe impl aya::Pod for DnsQueryKey {}
+    unsafe impl aya::Pod for FqdnPolicyKey {}
     unsafe impl aya::Pod for PortKey {}
     unsafe impl aya::Pod for PortVal {}
+    unsafe impl aya::Pod for ExeKey {}
+    unsafe impl aya::Pod for ExeValue {}
+    unsafe impl aya::Pod for CgroupKey {}
+    unsafe impl aya::Pod for ProcessTreeKey {}
     unsafe impl aya::Pod for ContainerIP {}
     unsafe impl aya::Pod for ContainerID {}
+    unsafe impl aya::Pod for PacketCountKey {}
+    unsafe impl aya::Pod for PacketCount {}
+
+    unsafe impl aya::Pod for TcAction {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_rank_follows_the_search_order() {
+        // The TCP and UDP table of `furui-ebpf/src/README.md`: remote_ip, local_port,
+        // remote_port and protocol.
+        let order = [
+            [true, true, true, true],
+            [true, true, true, false],
+            [true, true, false, true],
+            [true, false, true, true],
+            [false, true, true, true],
+            [true, true, false, false],
+            [true, false, true, false],
+            [true, false, false, true],
+            [false, true, true, false],
+            [false, true, false, true],
+            [false, false, true, true],
+            [true, false, false, false],
+            [false, true, false, false],
+            [false, false, true, false],
+            [false, false, false, true],
+            [false, false, false, false],
+        ];
+
+        for (position, used) in order.iter().enumerate() {
+            assert_eq!(
+                search_rank(used, PolicyList::Address),
+                position as u32 * 3,
+                "{:?}",
+                used
+            );
+        }
+    }
+
+    #[test]
+    fn an_address_is_more_specific_than_a_network() {
+        let used = [true, false, true, false];
+
+        assert!(search_rank(&used, PolicyList::Address) < search_rank(&used, PolicyList::Network));
+        assert!(
+            search_rank(&used, PolicyList::Network) < search_rank(&used, PolicyList::PortRange)
+        );
+        assert!(
+            search_rank(&used, PolicyList::PortRange)
+                < search_rank(&[true, false, false, false], PolicyList::Address)
+        );
+    }
+
+    #[test]
+    fn deny_wins_only_a_tie() {
+        let allow = PolicyMatch {
+            rank: 3,
+            action: TcAction::Pass,
+        };
+        let deny = PolicyMatch {
+            rank: 33,
+            action: TcAction::Drop,
+        };
+
+        assert_eq!(
+            PolicyMatch::most_specific(Some(deny), Some(allow)),
+            Some(allow)
+        );
+        assert_eq!(
+            PolicyMatch::most_specific(Some(allow), Some(deny)),
+            Some(allow)
+        );
+
+        let tied_deny = PolicyMatch { rank: 3, ..deny };
+        assert_eq!(
+            PolicyMatch::most_specific(Some(allow), Some(tied_deny)),
+            Some(tied_deny)
+        );
+        assert_eq!(
+            PolicyMatch::most_specific(Some(tied_deny), Some(allow)),
+            Some(tied_deny)
+        );
+    }
+
+    #[test]
+    fn address_answered_for_several_names_keeps_each_of_them() {
+        let mut value = FqdnIpValue {
+            names: [FqdnIpName {
+                expires_at: 0,
+                fqdn_id: 0,
+            }; FQDN_IP_NAMES_MAX],
+        };
+
+        value.answer(1, 100);
+        value.answer(2, 200);
+        value.answer(1, 300);
+
+        assert_eq!(value.fqdn_ids(50), [Some(1), Some(2), None, None]);
+        assert_eq!(value.fqdn_ids(250), [Some(1), None, None, None]);
+    }
+
+    #[test]
+    fn address_answered_for_too_many_names_forgets_the_first_to_expire() {
+        let mut value = FqdnIpValue {
+            names: [FqdnIpName {
+                expires_at: 0,
+                fqdn_id: 0,
+            }; FQDN_IP_NAMES_MAX],
+        };
+
+        value.answer(1, 400);
+        value.answer(2, 100);
+        value.answer(3, 300);
+        value.answer(4, 200);
+        value.answer(5, 500);
+
+        assert_eq!(value.fqdn_ids(0), [Some(1), Some(5), Some(3), Some(4)]);
+        assert_eq!(value.fqdn_ids(250), [Some(1), Some(5), Some(3), None]);
+
+        value.answer(6, 600);
+        assert_eq!(value.fqdn_ids(250), [Some(1), Some(5), Some(3), Some(6)]);
+    }
+
+    #[cfg(feature = "user")]
+    #[test]
+    fn fqdn_is_encoded_as_its_labels() {
+        let encoded = encode_fqdn("WWW.Example.com.").unwrap();
+
+        assert_eq!(&encoded[..17], b"\x03www\x07example\x03com\x00");
+        assert!(encoded[17..].iter().all(|&byte| byte == 0));
+    }
+
+    #[cfg(feature = "user")]
+    #[test]
+    fn fqdn_rejects_empty_and_long_labels() {
+        assert_eq!(encode_fqdn(""), None);
+        assert_eq!(encode_fqdn("example..com"), None);
+        assert_eq!(encode_fqdn(&format!("{}.com", "a".repeat(64))), None);
+
+        // The terminating zero has to fit as well.
+        let longest = format!("{}.{}", "a".repeat(63), "a".repeat(62));
+        assert!(encode_fqdn(&longest).is_some());
+        assert_eq!(encode_fqdn(&format!("{}a", longest)), None);
+    }
+
+    #[cfg(feature = "user")]
+    #[test]
+    fn wildcard_fqdn_keeps_its_star_label() {
+        let encoded = encode_fqdn("*.Example.com").unwrap();
+
+        assert_eq!(encoded[..2], FQDN_WILDCARD);
+        assert_eq!(&encoded[2..15], b"\x07example\x03com");
+    }
+
+    #[cfg(feature = "user")]
+    #[test]
+    fn fqdn_id_ignores_the_case_and_the_trailing_dot() {
+        assert_eq!(fqdn_id("WordPress.org."), fqdn_id("wordpress.org"));
+        assert_ne!(fqdn_id("wordpress.org"), fqdn_id("wordpress.com"));
+    }
 }
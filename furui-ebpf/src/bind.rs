@@ -1,23 +1,19 @@
 use aya_ebpf::{
     cty::c_long,
-    helpers::bpf_probe_read_kernel,
-    macros::{kprobe, map},
-    maps::PerfEventArray,
+    helpers::{bpf_get_current_cgroup_id, bpf_probe_read_kernel},
+    macros::kprobe,
     programs::ProbeContext,
     EbpfContext,
 };
 use aya_log_ebpf::warn;
-use furui_common::{BindEvent, EthProtocol, IpProtocol, PortKey, PortVal};
+use furui_common::{BindEvent, EbpfEvent, EthProtocol, IpProtocol, PortKey, PortVal};
 
 use crate::{
-    helpers::{get_container_id, is_container_process, ntohs},
+    helpers::{get_container_id, get_exe, is_container_process, ntohs, output_event},
     vmlinux::{sockaddr_in, sockaddr_in6, socket},
     PROC_PORTS,
 };
 
-#[map]
-static BIND_EVENTS: PerfEventArray<BindEvent> = PerfEventArray::<BindEvent>::new(0);
-
 #[kprobe]
 pub fn bind_v4(ctx: ProbeContext) -> u32 {
     match unsafe { try_bind_v4(&ctx) } {
@@ -36,11 +32,18 @@ unsafe fn try_bind_v4(ctx: &ProbeContext) -> Result<u32, c_long> {
         return Ok(0);
     }
 
+    let container_id = match get_container_id() {
+        Some(container_id) => container_id,
+        None => return Ok(0),
+    };
+
     let mut event: BindEvent = core::mem::zeroed();
 
-    event.container_id = get_container_id()?;
-    event.pid = ctx.pid();
+    event.container_id = container_id;
+    event.pid = ctx.tgid();
     event.comm = ctx.command()?;
+    (event.exe_ino, event.exe_dev) = get_exe()?;
+    event.cgroup_id = bpf_get_current_cgroup_id();
 
     let sock = &*bpf_probe_read_kernel(&ctx.arg::<*const socket>(0).ok_or(1)?)?;
     let sk = &*bpf_probe_read_kernel(&sock.sk)?;
@@ -51,7 +54,7 @@ unsafe fn try_bind_v4(ctx: &ProbeContext) -> Result<u32, c_long> {
         event.lport = ntohs(bpf_probe_read_kernel(&in_addr.sin_port)?);
         event.protocol = IpProtocol::new(bpf_probe_read_kernel(&sk.sk_protocol)? as u8);
 
-        finish_bind(&ctx, &event)?;
+        finish_bind(&event)?;
     }
 
     Ok(0)
@@ -75,11 +78,18 @@ unsafe fn try_bind_v6(ctx: &ProbeContext) -> Result<u32, c_long> {
         return Ok(0);
     }
 
+    let container_id = match get_container_id() {
+        Some(container_id) => container_id,
+        None => return Ok(0),
+    };
+
     let mut event: BindEvent = core::mem::zeroed();
 
-    event.container_id = get_container_id()?;
-    event.pid = ctx.pid();
+    event.container_id = container_id;
+    event.pid = ctx.tgid();
     event.comm = ctx.command()?;
+    (event.exe_ino, event.exe_dev) = get_exe()?;
+    event.cgroup_id = bpf_get_current_cgroup_id();
 
     let sock = &*bpf_probe_read_kernel(&ctx.arg::<*const socket>(0).ok_or(1)?)?;
     let sk = &*bpf_probe_read_kernel(&sock.sk)?;
@@ -90,24 +100,30 @@ unsafe fn try_bind_v6(ctx: &ProbeContext) -> Result<u32, c_long> {
         event.lport = ntohs(bpf_probe_read_kernel(&in_addr.sin6_port)?);
         event.protocol = IpProtocol::new(bpf_probe_read_kernel(&sk.sk_protocol)? as u8);
 
-        finish_bind(ctx, &event)?;
+        finish_bind(&event)?;
     }
 
     Ok(0)
 }
 
-unsafe fn finish_bind(ctx: &ProbeContext, event: &BindEvent) -> Result<(), c_long> {
+unsafe fn finish_bind(event: &BindEvent) -> Result<(), c_long> {
     PROC_PORTS.insert(
         &PortKey {
             container_id: event.container_id,
             port: event.lport,
             proto: event.protocol,
         },
-        &PortVal { comm: event.comm },
+        &PortVal {
+            comm: event.comm,
+            exe_ino: event.exe_ino,
+            exe_dev: event.exe_dev,
+            pid: event.pid,
+            cgroup_id: event.cgroup_id,
+        },
         0,
     )?;
 
-    BIND_EVENTS.output(ctx, event, 0);
+    output_event(EbpfEvent::Bind(*event));
 
     Ok(())
 }
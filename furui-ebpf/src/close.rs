@@ -1,16 +1,10 @@
 use aya_ebpf::{
-    cty::c_long,
-    macros::{map, tracepoint},
-    maps::PerfEventArray,
-    programs::TracePointContext,
-    EbpfContext,
+    cty::c_long, helpers::bpf_get_current_pid_tgid, macros::tracepoint, programs::TracePointContext,
 };
 use aya_log_ebpf::warn;
+use furui_common::{is_group_leader, process_id, EbpfEvent};
 
-use crate::helpers::is_container_process;
-
-#[map]
-static CLOSE_EVENTS: PerfEventArray<u32> = PerfEventArray::<u32>::new(0);
+use crate::helpers::{is_container_process, output_event};
 
 #[tracepoint]
 pub fn close(ctx: TracePointContext) -> u32 {
@@ -25,12 +19,19 @@ pub fn close(ctx: TracePointContext) -> u32 {
     }
 }
 
-unsafe fn try_close(ctx: &TracePointContext) -> Result<u32, c_long> {
+unsafe fn try_close(_ctx: &TracePointContext) -> Result<u32, c_long> {
     if !is_container_process()? {
         return Ok(0);
     }
 
-    CLOSE_EVENTS.output(ctx, &ctx.pid(), 0);
+    // `sched_process_exit` fires for every thread, while the process runs on until its thread
+    // group leader exits.
+    let pid_tgid = bpf_get_current_pid_tgid();
+    if !is_group_leader(pid_tgid) {
+        return Ok(0);
+    }
+
+    output_event(EbpfEvent::Close(process_id(pid_tgid)));
 
     Ok(0)
 }
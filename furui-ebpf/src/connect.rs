@@ -1,26 +1,21 @@
 use aya_ebpf::{
     cty::c_long,
-    helpers::bpf_probe_read_kernel,
-    macros::{kprobe, map},
-    maps::PerfEventArray,
+    helpers::{bpf_get_current_cgroup_id, bpf_probe_read_kernel},
+    macros::kprobe,
     programs::ProbeContext,
     EbpfContext,
 };
 use aya_log_ebpf::warn;
-use furui_common::{Connect6Event, ConnectEvent, EthProtocol, IpProtocol, PortKey, PortVal};
+use furui_common::{
+    Connect6Event, ConnectEvent, EbpfEvent, EthProtocol, IpProtocol, PortKey, PortVal,
+};
 
 use crate::{
-    helpers::{get_container_id, is_container_process, ntohl, ntohs},
+    helpers::{get_container_id, get_exe, is_container_process, ntohl, ntohs, output_event},
     vmlinux::{flowi4, flowi6, inet_sock, sock},
     PROC_PORTS,
 };
 
-#[map]
-static CONNECT_EVENTS: PerfEventArray<ConnectEvent> = PerfEventArray::<ConnectEvent>::new(0);
-
-#[map]
-static CONNECT6_EVENTS: PerfEventArray<Connect6Event> = PerfEventArray::<Connect6Event>::new(0);
-
 #[kprobe]
 pub fn tcp_connect(ctx: ProbeContext) -> u32 {
     match unsafe { try_tcp_connect(&ctx) } {
@@ -39,6 +34,11 @@ unsafe fn try_tcp_connect(ctx: &ProbeContext) -> Result<u32, c_long> {
         return Ok(0);
     }
 
+    let container_id = match get_container_id() {
+        Some(container_id) => container_id,
+        None => return Ok(0),
+    };
+
     let sk = &*bpf_probe_read_kernel(&ctx.arg::<*const sock>(0).ok_or(1)?)?;
     let family = EthProtocol::from_family(bpf_probe_read_kernel(&sk.__sk_common.skc_family)?);
 
@@ -52,14 +52,21 @@ unsafe fn try_tcp_connect(ctx: &ProbeContext) -> Result<u32, c_long> {
 
     let mut key: PortKey = core::mem::zeroed();
 
-    key.container_id = get_container_id()?;
+    key.container_id = container_id;
     key.port = sport;
     key.proto = IpProtocol::TCP;
 
+    let (exe_ino, exe_dev) = get_exe()?;
+    let cgroup_id = bpf_get_current_cgroup_id();
+
     PROC_PORTS.insert(
         &key,
         &PortVal {
             comm: ctx.command()?,
+            exe_ino,
+            exe_dev,
+            pid: ctx.tgid(),
+            cgroup_id,
         },
         0,
     )?;
@@ -67,9 +74,11 @@ unsafe fn try_tcp_connect(ctx: &ProbeContext) -> Result<u32, c_long> {
     if family.is_ip() {
         let mut event: ConnectEvent = core::mem::zeroed();
 
-        event.container_id = get_container_id()?;
-        event.pid = ctx.pid();
+        event.container_id = container_id;
+        event.pid = ctx.tgid();
         event.comm = ctx.command()?;
+        (event.exe_ino, event.exe_dev) = (exe_ino, exe_dev);
+        event.cgroup_id = cgroup_id;
         event.src_addr = ntohl(bpf_probe_read_kernel(
             &sk.__sk_common
                 .__bindgen_anon_1
@@ -86,15 +95,17 @@ unsafe fn try_tcp_connect(ctx: &ProbeContext) -> Result<u32, c_long> {
         event.family = family;
         event.protocol = IpProtocol::TCP;
 
-        CONNECT_EVENTS.output(ctx, &event, 0);
+        output_event(EbpfEvent::Connect(event));
     } else if family.is_ipv6() {
         let np = &*bpf_probe_read_kernel(&isk.pinet6)?;
 
         let mut event: Connect6Event = core::mem::zeroed();
 
-        event.container_id = get_container_id()?;
-        event.pid = ctx.pid();
+        event.container_id = container_id;
+        event.pid = ctx.tgid();
         event.comm = ctx.command()?;
+        (event.exe_ino, event.exe_dev) = (exe_ino, exe_dev);
+        event.cgroup_id = cgroup_id;
         event.src_addr = bpf_probe_read_kernel(&np.saddr.in6_u.u6_addr8)?;
         event.dst_addr = bpf_probe_read_kernel(&isk.sk.__sk_common.skc_v6_daddr.in6_u.u6_addr8)?;
         event.src_port = sport;
@@ -104,7 +115,7 @@ unsafe fn try_tcp_connect(ctx: &ProbeContext) -> Result<u32, c_long> {
         event.family = family;
         event.protocol = IpProtocol::TCP;
 
-        CONNECT6_EVENTS.output(ctx, &event, 0);
+        output_event(EbpfEvent::Connect6(event));
     }
 
     Ok(0)
@@ -128,28 +139,42 @@ unsafe fn try_udp_connect_v4(ctx: &ProbeContext) -> Result<u32, c_long> {
         return Ok(0);
     }
 
+    let container_id = match get_container_id() {
+        Some(container_id) => container_id,
+        None => return Ok(0),
+    };
+
     let flow4 = &*bpf_probe_read_kernel(&ctx.arg::<*const flowi4>(1).ok_or(1)?)?;
     let sport = ntohs(bpf_probe_read_kernel(&flow4.uli.ports.sport)?);
 
     let mut key: PortKey = core::mem::zeroed();
 
-    key.container_id = get_container_id()?;
+    key.container_id = container_id;
     key.port = sport;
     key.proto = IpProtocol::UDP;
 
+    let (exe_ino, exe_dev) = get_exe()?;
+    let cgroup_id = bpf_get_current_cgroup_id();
+
     PROC_PORTS.insert(
         &key,
         &PortVal {
             comm: ctx.command()?,
+            exe_ino,
+            exe_dev,
+            pid: ctx.tgid(),
+            cgroup_id,
         },
         0,
     )?;
 
     let mut event: ConnectEvent = core::mem::zeroed();
 
-    event.container_id = get_container_id()?;
-    event.pid = ctx.pid();
+    event.container_id = container_id;
+    event.pid = ctx.tgid();
     event.comm = ctx.command()?;
+    (event.exe_ino, event.exe_dev) = (exe_ino, exe_dev);
+    event.cgroup_id = cgroup_id;
     event.src_addr = ntohl(bpf_probe_read_kernel(&flow4.saddr)?);
     event.dst_addr = ntohl(bpf_probe_read_kernel(&flow4.daddr)?);
     event.src_port = sport;
@@ -157,7 +182,7 @@ unsafe fn try_udp_connect_v4(ctx: &ProbeContext) -> Result<u32, c_long> {
     event.protocol = IpProtocol::UDP;
     event.family = EthProtocol::IP;
 
-    CONNECT_EVENTS.output(ctx, &event, 0);
+    output_event(EbpfEvent::Connect(event));
 
     Ok(0)
 }
@@ -180,28 +205,42 @@ unsafe fn try_udp_connect_v6(ctx: &ProbeContext) -> Result<u32, c_long> {
         return Ok(0);
     }
 
+    let container_id = match get_container_id() {
+        Some(container_id) => container_id,
+        None => return Ok(0),
+    };
+
     let flow6 = &*bpf_probe_read_kernel(&ctx.arg::<*const flowi6>(1).ok_or(1)?)?;
     let sport = ntohs(bpf_probe_read_kernel(&flow6.uli.ports.sport)?);
 
     let mut key: PortKey = core::mem::zeroed();
 
-    key.container_id = get_container_id()?;
+    key.container_id = container_id;
     key.port = sport;
     key.proto = IpProtocol::UDP;
 
+    let (exe_ino, exe_dev) = get_exe()?;
+    let cgroup_id = bpf_get_current_cgroup_id();
+
     PROC_PORTS.insert(
         &key,
         &PortVal {
             comm: ctx.command()?,
+            exe_ino,
+            exe_dev,
+            pid: ctx.tgid(),
+            cgroup_id,
         },
         0,
     )?;
 
     let mut event: Connect6Event = core::mem::zeroed();
 
-    event.container_id = get_container_id()?;
-    event.pid = ctx.pid();
+    event.container_id = container_id;
+    event.pid = ctx.tgid();
     event.comm = ctx.command()?;
+    (event.exe_ino, event.exe_dev) = (exe_ino, exe_dev);
+    event.cgroup_id = cgroup_id;
     event.src_addr = bpf_probe_read_kernel(&flow6.saddr.in6_u.u6_addr8)?;
     event.dst_addr = bpf_probe_read_kernel(&flow6.daddr.in6_u.u6_addr8)?;
     event.src_port = sport;
@@ -209,7 +248,7 @@ unsafe fn try_udp_connect_v6(ctx: &ProbeContext) -> Result<u32, c_long> {
     event.protocol = IpProtocol::UDP;
     event.family = EthProtocol::IPv6;
 
-    CONNECT6_EVENTS.output(ctx, &event, 0);
+    output_event(EbpfEvent::Connect6(event));
 
     Ok(0)
 }
@@ -2,25 +2,24 @@ use aya_ebpf::{
     bindings::{TC_ACT_OK, TC_ACT_SHOT},
     cty::c_long,
     helpers::bpf_probe_read_kernel,
-    macros::map,
-    maps::PerfEventArray,
+    maps::lpm_trie::Key,
     programs::TcContext,
 };
-use furui_common::{ContainerIP, Egress6IcmpEvent, IcmpPolicyKey, IcmpVersion, TcAction};
+use furui_common::{
+    ContainerIP, Direction, EbpfEvent, Egress6IcmpEvent, IcmpPolicyCidrKey, IcmpPolicyKey,
+    IcmpVersion, PolicyMatch, TcAction,
+};
 
 use crate::{
     helpers::{
-        eth_protocol, ip_protocol, ETH_HDR_LEN, IPV6_HDR_LEN, NEIGHBOR_ADVERTISEMENT,
+        count_packet, default_action, eth_protocol, ip_protocol, is_audit, output_event,
+        unknown_container_verdict, ETH_HDR_LEN, IPV6_HDR_LEN, NEIGHBOR_ADVERTISEMENT,
         NEIGHBOR_SOLICITAION,
     },
     vmlinux::{icmphdr, ipv6hdr},
-    CONTAINER_ID_FROM_IPS, ICMP_POLICY_LIST,
+    CONTAINER_ID_FROM_IPS, ICMP_POLICY_CIDR_LIST, ICMP_POLICY_LIST,
 };
 
-#[map]
-static EGRESS6_ICMP_EVENTS: PerfEventArray<Egress6IcmpEvent> =
-    PerfEventArray::<Egress6IcmpEvent>::new(0);
-
 pub(crate) unsafe fn ipv6_icmp(ctx: &TcContext) -> Result<i32, c_long> {
     let mut event: Egress6IcmpEvent = core::mem::zeroed();
 
@@ -48,7 +47,7 @@ pub(crate) unsafe fn ipv6_icmp(ctx: &TcContext) -> Result<i32, c_long> {
 
     let cid_val = CONTAINER_ID_FROM_IPS.get(&ip_key);
     if cid_val.is_none() {
-        return Ok(TC_ACT_SHOT);
+        return Ok(unknown_container_verdict());
     }
 
     event.container_id = bpf_probe_read_kernel(&cid_val.unwrap().container_id)?;
@@ -58,13 +57,32 @@ pub(crate) unsafe fn ipv6_icmp(ctx: &TcContext) -> Result<i32, c_long> {
     policy_key.container_id = event.container_id;
     policy_key.version = event.version;
 
-    if event.search_key(&mut policy_key, |policy_key| {
-        ICMP_POLICY_LIST.get(&policy_key).is_some()
-    }) {
-        return finish(ctx, TcAction::Pass, &mut event);
+    // The most specific policy found in any of the lists decides.
+    let mut found = event.search_key(&mut policy_key, |policy_key| {
+        ICMP_POLICY_LIST
+            .get(&policy_key)
+            .map(|policy_val| policy_val.action)
+    });
+
+    let mut cidr_key: IcmpPolicyCidrKey = core::mem::zeroed();
+
+    cidr_key.container_id = event.container_id;
+    cidr_key.version = event.version;
+    cidr_key.remote_ip = event.daddr;
+
+    found = PolicyMatch::most_specific(
+        found,
+        event.search_cidr_key(&mut cidr_key, |cidr_key| {
+            ICMP_POLICY_CIDR_LIST
+                .get(&Key::new(IcmpPolicyCidrKey::LOOKUP_PREFIX_LEN, *cidr_key))
+                .map(|policy_val| policy_val.action)
+        }),
+    );
+
+    match found {
+        Some(found) => finish(ctx, found.action, &mut event),
+        None => finish(ctx, default_action(&event.container_id), &mut event),
     }
-
-    finish(ctx, TcAction::Drop, &mut event)
 }
 
 unsafe fn finish(
@@ -73,9 +91,19 @@ unsafe fn finish(
     event: &mut Egress6IcmpEvent,
 ) -> Result<i32, c_long> {
     event.action = action;
-    EGRESS6_ICMP_EVENTS.output(ctx, event, 0);
+    event.would_drop = action == TcAction::Drop && is_audit(&event.container_id);
+    count_packet(
+        ctx,
+        &event.container_id,
+        Direction::Egress,
+        event.protocol,
+        action,
+        event.would_drop,
+    );
+    output_event(EbpfEvent::Egress6Icmp(*event));
     Ok(match action {
         TcAction::Pass => TC_ACT_OK,
+        TcAction::Drop if event.would_drop => TC_ACT_OK,
         TcAction::Drop => TC_ACT_SHOT,
     })
 }
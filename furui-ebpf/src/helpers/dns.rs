@@ -0,0 +1,302 @@
+use aya_ebpf::{
+    cty::{c_char, c_long},
+    helpers::bpf_ktime_get_ns,
+    macros::map,
+    maps::PerCpuArray,
+    programs::TcContext,
+};
+use furui_common::{
+    ipv4_mapped_ipv6, DnsQueryKey, FqdnIpKey, FqdnIpValue, FqdnKey, CONTAINER_ID_LEN,
+    FQDN_IP_NAMES_MAX, FQDN_LEN, FQDN_WILDCARD, IPV6_LEN,
+};
+
+use crate::{
+    helpers::{ntohl, ntohs, ETH_HDR_LEN},
+    DNS_QUERIES, FQDN_IPS, FQDN_LIST,
+};
+
+pub(crate) const DNS_PORT: u16 = 53;
+
+const UDP_HDR_LEN: usize = 8;
+const DNS_HDR_LEN: usize = 12;
+// TYPE, CLASS, TTL and RDLENGTH of a resource record.
+const DNS_RR_HDR_LEN: usize = 10;
+// QTYPE and QCLASS of a question.
+const DNS_QUESTION_TAIL_LEN: usize = 4;
+
+const DNS_FLAG_QR: u16 = 0x8000;
+const DNS_RCODE_MASK: u16 = 0x000f;
+const DNS_POINTER: u8 = 0xc0;
+const DNS_TYPE_A: u16 = 1;
+const DNS_TYPE_AAAA: u16 = 28;
+
+// Answers after this many are not looked at.
+const DNS_ANSWERS_MAX: u16 = 16;
+// Parent domains after this many are not matched against wildcards.
+const DNS_PARENTS_MAX: usize = 8;
+// Answers are kept at least this long, however short their TTL is, so that a connection can still
+// be opened right after the response.
+const DNS_TTL_MIN: u64 = 30;
+const NS_PER_SEC: u64 = 1_000_000_000;
+
+#[repr(C)]
+struct DnsHeader {
+    id: u16,
+    flags: u16,
+    qdcount: u16,
+    ancount: u16,
+    nscount: u16,
+    arcount: u16,
+}
+
+// The keys are too large for the stack.
+#[map]
+static FQDN_KEY: PerCpuArray<FqdnKey> = PerCpuArray::with_max_entries(1, 0);
+
+#[map]
+static WILDCARD_KEY: PerCpuArray<FqdnKey> = PerCpuArray::with_max_entries(1, 0);
+
+// The IDs of the domain names in the rules of the container that an address was answered for,
+// as long as their answers have not expired.
+#[inline]
+pub(crate) unsafe fn answered_fqdn_ids(
+    container_id: &[c_char; CONTAINER_ID_LEN],
+    ip: &[u8; IPV6_LEN],
+) -> [Option<u32>; FQDN_IP_NAMES_MAX] {
+    let mut ip_key: FqdnIpKey = core::mem::zeroed();
+
+    ip_key.container_id = *container_id;
+    ip_key.ip = *ip;
+
+    match FQDN_IPS.get(&ip_key) {
+        Some(ip_val) => ip_val.fqdn_ids(bpf_ktime_get_ns()),
+        None => [None; FQDN_IP_NAMES_MAX],
+    }
+}
+
+// Remembers a DNS query of a container for a domain name in its rules, so that only the response
+// to it is learned from. `port` is the port the query is sent from and `server` the address it
+// is sent to.
+#[inline]
+pub(crate) unsafe fn snoop_dns_query(
+    ctx: &TcContext,
+    ip_hdr_len: usize,
+    container_id: &[c_char; CONTAINER_ID_LEN],
+    port: u16,
+    server: [u8; IPV6_LEN],
+) -> Result<(), c_long> {
+    let offset = ETH_HDR_LEN + ip_hdr_len + UDP_HDR_LEN;
+
+    let header = ctx.load::<DnsHeader>(offset)?;
+
+    // Resolvers ask a single question at a time.
+    if ntohs(header.flags) & DNS_FLAG_QR != 0 || ntohs(header.qdcount) != 1 {
+        return Ok(());
+    }
+
+    let fqdn_id = match question(ctx, offset + DNS_HDR_LEN, container_id)? {
+        Some((fqdn_id, _)) => fqdn_id,
+        None => return Ok(()),
+    };
+
+    let query_key = DnsQueryKey {
+        container_id: *container_id,
+        id: header.id,
+        port,
+        server,
+    };
+
+    DNS_QUERIES.insert(&query_key, &fqdn_id, 0)
+}
+
+// Remembers the addresses answered in a DNS response to a container until their TTL runs out, so
+// that the rules for the domain name asked for apply to the addresses the container actually
+// resolved. Only the response from the server a query was sent to, to the port it was sent from,
+// with its ID and its question is learned from, and only once. `port` is the port the response
+// is sent to and `server` the address it is sent from.
+#[inline]
+pub(crate) unsafe fn snoop_dns_response(
+    ctx: &TcContext,
+    ip_hdr_len: usize,
+    container_id: &[c_char; CONTAINER_ID_LEN],
+    port: u16,
+    server: [u8; IPV6_LEN],
+) -> Result<(), c_long> {
+    let mut offset = ETH_HDR_LEN + ip_hdr_len + UDP_HDR_LEN;
+
+    let header = ctx.load::<DnsHeader>(offset)?;
+
+    let flags = ntohs(header.flags);
+    if flags & DNS_FLAG_QR == 0 || ntohs(header.qdcount) != 1 {
+        return Ok(());
+    }
+
+    let query_key = DnsQueryKey {
+        container_id: *container_id,
+        id: header.id,
+        port,
+        server,
+    };
+
+    let query_fqdn_id = match DNS_QUERIES.get(&query_key) {
+        Some(fqdn_id) => *fqdn_id,
+        None => return Ok(()),
+    };
+
+    offset += DNS_HDR_LEN;
+
+    let (fqdn_id, name_len) = match question(ctx, offset, container_id)? {
+        Some(question) => question,
+        None => return Ok(()),
+    };
+
+    if fqdn_id != query_fqdn_id {
+        return Ok(());
+    }
+
+    DNS_QUERIES.remove(&query_key)?;
+
+    if flags & DNS_RCODE_MASK != 0 {
+        return Ok(());
+    }
+
+    offset += name_len + DNS_QUESTION_TAIL_LEN;
+
+    let mut ip_key: FqdnIpKey = core::mem::zeroed();
+    ip_key.container_id = *container_id;
+
+    let now = bpf_ktime_get_ns();
+
+    let ancount = ntohs(header.ancount);
+    for i in 0..DNS_ANSWERS_MAX {
+        if i >= ancount {
+            break;
+        }
+
+        // Answers refer to the question name with a pointer, other names are not followed.
+        if ctx.load::<u8>(offset)? & DNS_POINTER != DNS_POINTER {
+            break;
+        }
+        offset += 2;
+
+        let type_ = ntohs(ctx.load::<u16>(offset)?);
+        let ttl = ntohl(ctx.load::<u32>(offset + 4)?) as u64;
+        let rdlength = ntohs(ctx.load::<u16>(offset + DNS_RR_HDR_LEN - 2)?) as usize;
+        offset += DNS_RR_HDR_LEN;
+
+        let expires_at = now + ttl.max(DNS_TTL_MIN) * NS_PER_SEC;
+
+        match (type_, rdlength) {
+            (DNS_TYPE_A, 4) => {
+                ip_key.ip = ipv4_mapped_ipv6(ntohl(ctx.load::<u32>(offset)?));
+                remember_answer(&ip_key, fqdn_id, expires_at)?;
+            }
+            (DNS_TYPE_AAAA, IPV6_LEN) => {
+                ip_key.ip = ctx.load::<[u8; IPV6_LEN]>(offset)?;
+                remember_answer(&ip_key, fqdn_id, expires_at)?;
+            }
+            // CNAME records and the like.
+            _ => {}
+        }
+
+        offset += rdlength;
+    }
+
+    Ok(())
+}
+
+// Adds a name to the names an address was answered for, keeping the names it was answered for
+// before.
+#[inline]
+unsafe fn remember_answer(ip_key: &FqdnIpKey, fqdn_id: u32, expires_at: u64) -> Result<(), c_long> {
+    let mut ip_val: FqdnIpValue = match FQDN_IPS.get(ip_key) {
+        Some(ip_val) => *ip_val,
+        None => core::mem::zeroed(),
+    };
+
+    ip_val.answer(fqdn_id, expires_at);
+
+    FQDN_IPS.insert(ip_key, &ip_val, 0)
+}
+
+// The ID of the domain name in the rules of the container that the question at `offset` asks
+// for, with the length of the name in the question.
+#[inline]
+unsafe fn question(
+    ctx: &TcContext,
+    offset: usize,
+    container_id: &[c_char; CONTAINER_ID_LEN],
+) -> Result<Option<(u32, usize)>, c_long> {
+    let key = match FQDN_KEY.get_ptr_mut(0) {
+        Some(key) => &mut *key,
+        None => return Ok(None),
+    };
+
+    key.container_id = *container_id;
+
+    // The question name is copied as is, apart from the case. The bytes after its end are
+    // cleared, since the key is reused between packets.
+    let mut name_len = 0;
+    for i in 0..FQDN_LEN {
+        if name_len != 0 {
+            key.name[i] = 0;
+            continue;
+        }
+
+        let byte = ctx.load::<u8>(offset + i)?;
+        key.name[i] = byte.to_ascii_lowercase();
+
+        if byte == 0 {
+            name_len = i + 1;
+        }
+    }
+
+    if name_len == 0 {
+        return Ok(None);
+    }
+
+    let fqdn_id = match FQDN_LIST.get(key) {
+        Some(fqdn_id) => *fqdn_id,
+        None => match search_wildcard(key) {
+            Some(fqdn_id) => fqdn_id,
+            None => return Ok(None),
+        },
+    };
+
+    Ok(Some((fqdn_id, name_len)))
+}
+
+// Searches `*.` followed by each parent domain of the name in `key`, the closest parent first, so
+// that `*.example.com` matches the names at any depth below `example.com`.
+#[inline]
+unsafe fn search_wildcard(key: &FqdnKey) -> Option<u32> {
+    let wildcard = &mut *WILDCARD_KEY.get_ptr_mut(0)?;
+
+    wildcard.container_id = key.container_id;
+
+    let mut parent = 0;
+    for _ in 0..DNS_PARENTS_MAX {
+        if parent >= FQDN_LEN {
+            return None;
+        }
+        parent += 1 + key.name[parent] as usize;
+
+        // The root domain is not matched.
+        if parent >= FQDN_LEN || key.name[parent] == 0 {
+            return None;
+        }
+
+        wildcard.name[0] = FQDN_WILDCARD[0];
+        wildcard.name[1] = FQDN_WILDCARD[1];
+        for i in FQDN_WILDCARD.len()..FQDN_LEN {
+            let j = parent + i - FQDN_WILDCARD.len();
+            wildcard.name[i] = if j < FQDN_LEN { key.name[j] } else { 0 };
+        }
+
+        if let Some(fqdn_id) = FQDN_LIST.get(wildcard) {
+            return Some(*fqdn_id);
+        }
+    }
+
+    None
+}
@@ -1,16 +1,26 @@
 use aya_ebpf::{
+    bindings::BPF_RB_AVAIL_DATA,
     cty::{c_char, c_long},
-    helpers::{bpf_get_current_task, bpf_probe_read_kernel},
+    helpers::{bpf_get_current_ancestor_cgroup_id, bpf_get_current_task, bpf_probe_read_kernel},
 };
-use furui_common::CONTAINER_ID_LEN;
+pub(crate) use dns::*;
+use furui_common::{EbpfEvent, CONTAINER_ID_LEN};
 pub(crate) use net::*;
 pub(crate) use tc::*;
 
-use crate::vmlinux::task_struct;
+use crate::{vmlinux::task_struct, CONTAINER_ID_FROM_CGROUPS, EVENTS, EVENTS_LOST};
 
+mod dns;
 mod net;
 mod tc;
 
+/// How deep the cgroup of a container is searched for, the root cgroup being level 0.
+const CGROUP_LEVELS_MAX: i32 = 16;
+
+/// How many unread bytes `EVENTS` can hold before the packet events are counted as lost, so that
+/// the last 64K of its 256K are left to the bind, connect and close events.
+const PACKET_EVENTS_MAX: u64 = 192 * 1024;
+
 #[inline]
 pub(crate) unsafe fn is_container_process() -> Result<bool, c_long> {
     let task = bpf_get_current_task() as *const task_struct;
@@ -21,14 +31,71 @@ pub(crate) unsafe fn is_container_process() -> Result<bool, c_long> {
     return Ok(bpf_probe_read_kernel(&(*pidns).level)? > 0);
 }
 
+/// The inode and device of the current task's executable, or zeros for a task without one.
 #[inline]
-pub(crate) unsafe fn get_container_id() -> Result<[c_char; CONTAINER_ID_LEN], c_long> {
+pub(crate) unsafe fn get_exe() -> Result<(u64, u32), c_long> {
     let task = bpf_get_current_task() as *const task_struct;
 
-    let nsproxy = bpf_probe_read_kernel(&(*task).nsproxy)?;
-    let uts = bpf_probe_read_kernel(&(*nsproxy).uts_ns)?;
+    let mm = bpf_probe_read_kernel(&(*task).mm)?;
+    if mm.is_null() {
+        return Ok((0, 0));
+    }
+
+    let exe_file = bpf_probe_read_kernel(&(*mm).__bindgen_anon_1.exe_file)?;
+    if exe_file.is_null() {
+        return Ok((0, 0));
+    }
+
+    let inode = bpf_probe_read_kernel(&(*exe_file).f_inode)?;
+    let sb = bpf_probe_read_kernel(&(*inode).i_sb)?;
+
+    Ok((
+        bpf_probe_read_kernel(&(*inode).i_ino)?,
+        bpf_probe_read_kernel(&(*sb).s_dev)?,
+    ))
+}
+
+/// The container of the current task, by the cgroup userspace saved for the container. The task
+/// can be in a cgroup below it. `None` until userspace has seen the container.
+#[inline]
+pub(crate) unsafe fn get_container_id() -> Option<[c_char; CONTAINER_ID_LEN]> {
+    for level in 0..CGROUP_LEVELS_MAX {
+        // 0 past the cgroup of the task.
+        let cgroup_id = bpf_get_current_ancestor_cgroup_id(level);
+        if cgroup_id == 0 {
+            break;
+        }
+
+        if let Some(container) = CONTAINER_ID_FROM_CGROUPS.get(&cgroup_id) {
+            return Some(container.container_id);
+        }
+    }
+
+    None
+}
+
+/// Sends an event to userspace, or counts it as lost when userspace has fallen so far behind that
+/// the ring buffer is full. Packet events are counted as lost earlier, so that a flood of packets
+/// cannot crowd out the events userspace keeps its maps by.
+#[inline(always)]
+pub(crate) fn output_event(event: EbpfEvent) {
+    if !event.is_lifecycle() && EVENTS.query(BPF_RB_AVAIL_DATA as u64) > PACKET_EVENTS_MAX {
+        count_lost_event();
+        return;
+    }
+
+    match EVENTS.reserve::<EbpfEvent>(0) {
+        Some(mut entry) => {
+            entry.write(event);
+            entry.submit(0);
+        }
+        None => count_lost_event(),
+    }
+}
 
-    return Ok(*bpf_probe_read_kernel(&(*uts).name.nodename)?
-        .as_ptr()
-        .cast::<[c_char; CONTAINER_ID_LEN]>());
+#[inline(always)]
+fn count_lost_event() {
+    if let Some(lost) = EVENTS_LOST.get_ptr_mut(0) {
+        unsafe { *lost += 1 };
+    }
 }
@@ -1,9 +1,19 @@
-use aya_ebpf::{bindings::TC_ACT_OK, cty::c_long, programs::TcContext};
-use furui_common::{EthProtocol, IpProtocol};
+use aya_ebpf::{
+    bindings::{TC_ACT_OK, TC_ACT_SHOT},
+    cty::{c_char, c_long},
+    programs::TcContext,
+};
+use furui_common::{
+    CgroupKey, ContainerID, Direction, EthProtocol, ExeKey, IpProtocol, PacketCount,
+    PacketCountKey, PolicyMatch, PortRangePolicyKey, PortVal, ProcessTreeKey, TcAction,
+    ALL_CONTAINERS, CONTAINER_ID_LEN, IPV6_LEN, TASK_COMM_LEN,
+};
 
 use crate::{
     helpers::{ntohs, ETH_HDR_LEN, IPV6_HDR_LEN, IP_HDR_LEN},
     vmlinux::{ethhdr, iphdr, ipv6hdr, tcphdr, udphdr},
+    AUDIT_CONTAINERS, CGROUP_LIST, DEFAULT_ACTIONS, EXE_LIST, PACKET_COUNTS,
+    PORT_RANGE_POLICY_LIST, PROCESS_TREE_LIST,
 };
 
 pub(crate) const NEIGHBOR_SOLICITAION: u8 = 135;
@@ -53,3 +63,143 @@ pub(crate) unsafe fn get_port(ctx: &TcContext) -> Result<(u16, u16), c_long> {
         _ => Err(TC_ACT_OK as c_long),
     };
 }
+
+/// The most specific port range rule of the process going by the name `comm`, among those
+/// without a remote address and those for `remote_ip` itself.
+#[inline]
+pub(crate) unsafe fn search_port_ranges(
+    container_id: [c_char; CONTAINER_ID_LEN],
+    comm: [u8; TASK_COMM_LEN],
+    protocol: IpProtocol,
+    local_port: u16,
+    remote_port: u16,
+    remote_ip: &[u8; IPV6_LEN],
+) -> Option<PolicyMatch> {
+    let mut range_key: PortRangePolicyKey = core::mem::zeroed();
+
+    range_key.container_id = container_id;
+    range_key.comm = comm;
+
+    let mut found = None;
+    for key_ip in PortRangePolicyKey::remote_ips(remote_ip) {
+        range_key.remote_ip = key_ip;
+
+        if let Some(range_policies) = PORT_RANGE_POLICY_LIST.get(&range_key) {
+            found = PolicyMatch::most_specific(
+                found,
+                range_policies.search(protocol, local_port, remote_port, remote_ip),
+            );
+        }
+    }
+
+    found
+}
+
+/// The names the rules of the process behind a port are saved under: the executable, the process
+/// tree and the cgroup named by a rule, as the process name can be set by the process itself, and
+/// the process name. The rules saved under each of them apply to the process. An executable, a
+/// process tree or a cgroup is only there once userspace has seen the process, so until then only
+/// the rules of the process name, and of a cgroup seen before, apply.
+#[inline]
+pub(crate) unsafe fn rule_comms(
+    container_id: [c_char; CONTAINER_ID_LEN],
+    port_val: &PortVal,
+    comm: [u8; TASK_COMM_LEN],
+) -> [Option<[u8; TASK_COMM_LEN]>; 4] {
+    let mut exe_key: ExeKey = core::mem::zeroed();
+
+    exe_key.container_id = container_id;
+    exe_key.ino = port_val.exe_ino;
+    exe_key.dev = port_val.exe_dev;
+
+    let tree_key = ProcessTreeKey {
+        container_id,
+        pid: port_val.pid,
+    };
+
+    let mut cgroup_key: CgroupKey = core::mem::zeroed();
+
+    cgroup_key.container_id = container_id;
+    cgroup_key.cgroup_id = port_val.cgroup_id;
+
+    [
+        EXE_LIST.get(&exe_key).map(|exe_val| exe_val.comm),
+        PROCESS_TREE_LIST
+            .get(&tree_key)
+            .map(|tree_val| tree_val.comm),
+        CGROUP_LIST
+            .get(&cgroup_key)
+            .map(|cgroup_val| cgroup_val.comm),
+        Some(comm),
+    ]
+}
+
+// In audit mode the classifiers report what they would have dropped, but pass the packet.
+#[inline]
+pub(crate) unsafe fn is_audit(container_id: &[c_char; CONTAINER_ID_LEN]) -> bool {
+    AUDIT_CONTAINERS.get(&ALL_CONTAINERS).is_some()
+        || AUDIT_CONTAINERS
+            .get(&ContainerID {
+                container_id: *container_id,
+            })
+            .is_some()
+}
+
+// The action for packets no policy matches. A container without its own entry is not managed
+// by any policy and falls back to the global default action.
+#[inline]
+pub(crate) unsafe fn default_action(container_id: &[c_char; CONTAINER_ID_LEN]) -> TcAction {
+    if let Some(action) = DEFAULT_ACTIONS.get(&ContainerID {
+        container_id: *container_id,
+    }) {
+        return *action;
+    }
+
+    match DEFAULT_ACTIONS.get(&ALL_CONTAINERS) {
+        Some(action) => *action,
+        None => TcAction::Drop,
+    }
+}
+
+// Counts the packet by the action taken on it, for the metrics of userspace.
+#[inline]
+pub(crate) unsafe fn count_packet(
+    ctx: &TcContext,
+    container_id: &[c_char; CONTAINER_ID_LEN],
+    direction: Direction,
+    protocol: IpProtocol,
+    action: TcAction,
+    would_drop: bool,
+) {
+    let mut key: PacketCountKey = core::mem::zeroed();
+
+    key.container_id = *container_id;
+    key.direction = direction;
+    key.protocol = protocol;
+    key.action = action;
+    key.would_drop = would_drop;
+
+    match PACKET_COUNTS.get_ptr_mut(&key) {
+        Some(count) => {
+            (*count).packets += 1;
+            (*count).bytes += ctx.len() as u64;
+        }
+        None => {
+            let count = PacketCount {
+                packets: 1,
+                bytes: ctx.len() as u64,
+            };
+            let _ = PACKET_COUNTS.insert(&key, &count, 0);
+        }
+    }
+}
+
+// For packets to addresses that belong to no known container.
+#[inline]
+pub(crate) unsafe fn unknown_container_verdict() -> i32 {
+    match default_action(&ALL_CONTAINERS.container_id) {
+        TcAction::Pass => TC_ACT_OK,
+        TcAction::Drop if is_audit(&ALL_CONTAINERS.container_id) => TC_ACT_OK,
+        TcAction::Drop => TC_ACT_SHOT,
+    }
+}
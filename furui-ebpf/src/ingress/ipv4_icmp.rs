@@ -2,22 +2,23 @@ use aya_ebpf::{
     bindings::{TC_ACT_OK, TC_ACT_SHOT},
     cty::c_long,
     helpers::bpf_probe_read_kernel,
-    macros::map,
-    maps::PerfEventArray,
+    maps::lpm_trie::Key,
     programs::TcContext,
 };
-use furui_common::{ContainerIP, IcmpPolicyKey, IcmpVersion, IngressIcmpEvent, TcAction};
+use furui_common::{
+    ipv4_mapped_ipv6, ContainerIP, Direction, EbpfEvent, IcmpPolicyCidrKey, IcmpPolicyKey,
+    IcmpVersion, IngressIcmpEvent, PolicyMatch, TcAction,
+};
 
 use crate::{
-    helpers::{eth_protocol, ip_protocol, ntohl, ETH_HDR_LEN, IP_HDR_LEN},
+    helpers::{
+        count_packet, default_action, eth_protocol, ip_protocol, is_audit, ntohl, output_event,
+        unknown_container_verdict, ETH_HDR_LEN, IP_HDR_LEN,
+    },
     vmlinux::{icmphdr, iphdr},
-    CONTAINER_ID_FROM_IPS, ICMP_POLICY_LIST,
+    CONTAINER_ID_FROM_IPS, ICMP_POLICY_CIDR_LIST, ICMP_POLICY_LIST,
 };
 
-#[map]
-static INGRESS_ICMP_EVENTS: PerfEventArray<IngressIcmpEvent> =
-    PerfEventArray::<IngressIcmpEvent>::new(0);
-
 pub(crate) unsafe fn ipv4_icmp(ctx: &TcContext) -> Result<i32, c_long> {
     let mut event: IngressIcmpEvent = core::mem::zeroed();
 
@@ -39,7 +40,7 @@ pub(crate) unsafe fn ipv4_icmp(ctx: &TcContext) -> Result<i32, c_long> {
 
     let cid_val = CONTAINER_ID_FROM_IPS.get(&ip_key);
     if cid_val.is_none() {
-        return Ok(TC_ACT_SHOT);
+        return Ok(unknown_container_verdict());
     }
 
     event.container_id = bpf_probe_read_kernel(&cid_val.unwrap().container_id)?;
@@ -49,13 +50,32 @@ pub(crate) unsafe fn ipv4_icmp(ctx: &TcContext) -> Result<i32, c_long> {
     policy_key.container_id = event.container_id;
     policy_key.version = event.version;
 
-    if event.search_key(&mut policy_key, |policy_key| {
-        ICMP_POLICY_LIST.get(&policy_key).is_some()
-    }) {
-        return finish(ctx, TcAction::Pass, &mut event);
-    }
+    // The most specific policy found in any of the lists decides.
+    let mut found = event.search_key(&mut policy_key, |policy_key| {
+        ICMP_POLICY_LIST
+            .get(&policy_key)
+            .map(|policy_val| policy_val.action)
+    });
+
+    let mut cidr_key: IcmpPolicyCidrKey = core::mem::zeroed();
 
-    finish(ctx, TcAction::Drop, &mut event)
+    cidr_key.container_id = event.container_id;
+    cidr_key.version = event.version;
+    cidr_key.remote_ip = ipv4_mapped_ipv6(event.saddr);
+
+    found = PolicyMatch::most_specific(
+        found,
+        event.search_cidr_key(&mut cidr_key, |cidr_key| {
+            ICMP_POLICY_CIDR_LIST
+                .get(&Key::new(IcmpPolicyCidrKey::LOOKUP_PREFIX_LEN, *cidr_key))
+                .map(|policy_val| policy_val.action)
+        }),
+    );
+
+    match found {
+        Some(found) => finish(ctx, found.action, &mut event),
+        None => finish(ctx, default_action(&event.container_id), &mut event),
+    }
 }
 
 unsafe fn finish(
@@ -64,9 +84,19 @@ unsafe fn finish(
     event: &mut IngressIcmpEvent,
 ) -> Result<i32, c_long> {
     event.action = action;
-    INGRESS_ICMP_EVENTS.output(ctx, event, 0);
+    event.would_drop = action == TcAction::Drop && is_audit(&event.container_id);
+    count_packet(
+        ctx,
+        &event.container_id,
+        Direction::Ingress,
+        event.protocol,
+        action,
+        event.would_drop,
+    );
+    output_event(EbpfEvent::IngressIcmp(*event));
     Ok(match action {
         TcAction::Pass => TC_ACT_OK,
+        TcAction::Drop if event.would_drop => TC_ACT_OK,
         TcAction::Drop => TC_ACT_SHOT,
     })
 }
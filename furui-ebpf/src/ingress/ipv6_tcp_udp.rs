@@ -2,21 +2,24 @@ use aya_ebpf::{
     bindings::{TC_ACT_OK, TC_ACT_SHOT},
     cty::c_long,
     helpers::bpf_probe_read_kernel,
-    macros::map,
-    maps::PerfEventArray,
+    maps::lpm_trie::Key,
     programs::TcContext,
 };
-use furui_common::{ContainerIP, Ingress6Event, PolicyKey, PortKey, TcAction};
+use furui_common::{
+    ContainerIP, Direction, EbpfEvent, FqdnPolicyKey, Ingress6Event, IpProtocol, PolicyCidrKey,
+    PolicyKey, PolicyMatch, PortKey, TcAction, TASK_COMM_LEN,
+};
 
 use crate::{
-    helpers::{eth_protocol, get_port, ip_protocol, ETH_HDR_LEN},
+    helpers::{
+        answered_fqdn_ids, count_packet, default_action, eth_protocol, get_port, ip_protocol,
+        is_audit, output_event, rule_comms, search_port_ranges, snoop_dns_response,
+        unknown_container_verdict, DNS_PORT, ETH_HDR_LEN, IPV6_HDR_LEN,
+    },
     vmlinux::ipv6hdr,
-    CONTAINER_ID_FROM_IPS, POLICY_LIST, PROC_PORTS,
+    CONTAINER_ID_FROM_IPS, FQDN_POLICY_LIST, POLICY_CIDR_LIST, POLICY_LIST, PROC_PORTS,
 };
 
-#[map]
-static INGRESS6_EVENTS: PerfEventArray<Ingress6Event> = PerfEventArray::<Ingress6Event>::new(0);
-
 pub(crate) unsafe fn ipv6_tcp_udp(ctx: &TcContext) -> Result<i32, c_long> {
     let mut event: Ingress6Event = core::mem::zeroed();
 
@@ -37,7 +40,7 @@ pub(crate) unsafe fn ipv6_tcp_udp(ctx: &TcContext) -> Result<i32, c_long> {
 
     let cid_val = CONTAINER_ID_FROM_IPS.get(&ip_key);
     if cid_val.is_none() {
-        return Ok(TC_ACT_SHOT);
+        return Ok(unknown_container_verdict());
     }
 
     event.container_id = bpf_probe_read_kernel(&cid_val.unwrap().container_id)?;
@@ -50,31 +53,92 @@ pub(crate) unsafe fn ipv6_tcp_udp(ctx: &TcContext) -> Result<i32, c_long> {
 
     let port_val = PROC_PORTS.get(&port_key);
     if port_val.is_none() {
-        return finish(ctx, TcAction::Drop, &mut event);
+        return finish(ctx, default_action(&event.container_id), &mut event);
     }
 
     let port_val = port_val.unwrap();
 
-    let mut policy_key: PolicyKey = core::mem::zeroed();
+    event.comm = bpf_probe_read_kernel(&port_val.comm)?;
 
-    // If nothing is specified in the policy except the container name and
-    // executable name, allow all communication to that process.
-    policy_key.container_id = event.container_id;
-    policy_key.comm = bpf_probe_read_kernel(&port_val.comm)?;
-    let policy_val = POLICY_LIST.get(&policy_key);
-    if policy_val.is_some() {
-        return finish(ctx, TcAction::Pass, &mut event);
+    // Each name the process goes by is searched, and the most specific policy found for any of
+    // them decides.
+    let mut found = None;
+    for comm in rule_comms(event.container_id, port_val, event.comm)
+        .iter()
+        .flatten()
+    {
+        found = PolicyMatch::most_specific(found, search(&mut event, *comm));
     }
 
-    event.comm = bpf_probe_read_kernel(&port_val.comm)?;
+    match found {
+        Some(found) => finish(ctx, found.action, &mut event),
+        None => finish(ctx, default_action(&event.container_id), &mut event),
+    }
+}
+
+// The most specific policy for the process going by the name `comm`.
+unsafe fn search(event: &mut Ingress6Event, comm: [u8; TASK_COMM_LEN]) -> Option<PolicyMatch> {
+    let mut policy_key: PolicyKey = core::mem::zeroed();
 
-    if event.search_key(&mut policy_key, |policy_key| {
-        POLICY_LIST.get(&policy_key).is_some()
-    }) {
-        return finish(ctx, TcAction::Pass, &mut event);
+    // The key with nothing but the container name and executable name is searched last,
+    // and allows all communication to that process.
+    policy_key.container_id = event.container_id;
+    policy_key.comm = comm;
+
+    // The most specific policy found in any of the lists decides.
+    let mut found = event.search_key(&mut policy_key, |policy_key| {
+        POLICY_LIST
+            .get(&policy_key)
+            .map(|policy_val| policy_val.action)
+    });
+
+    let mut cidr_key: PolicyCidrKey = core::mem::zeroed();
+
+    cidr_key.container_id = event.container_id;
+    cidr_key.comm = comm;
+    cidr_key.remote_ip = event.saddr;
+
+    found = PolicyMatch::most_specific(
+        found,
+        event.search_cidr_key(&mut cidr_key, |cidr_key| {
+            POLICY_CIDR_LIST
+                .get(&Key::new(PolicyCidrKey::LOOKUP_PREFIX_LEN, *cidr_key))
+                .map(|policy_val| policy_val.action)
+        }),
+    );
+
+    // Addresses the container was answered for the domain names in its rules, by each name.
+    for fqdn_id in answered_fqdn_ids(&event.container_id, &cidr_key.remote_ip)
+        .iter()
+        .flatten()
+    {
+        let mut fqdn_key: FqdnPolicyKey = core::mem::zeroed();
+
+        fqdn_key.container_id = event.container_id;
+        fqdn_key.comm = comm;
+        fqdn_key.fqdn_id = *fqdn_id;
+
+        found = PolicyMatch::most_specific(
+            found,
+            event.search_fqdn_key(&mut fqdn_key, |fqdn_key| {
+                FQDN_POLICY_LIST
+                    .get(&fqdn_key)
+                    .map(|policy_val| policy_val.action)
+            }),
+        );
     }
 
-    finish(ctx, TcAction::Drop, &mut event)
+    PolicyMatch::most_specific(
+        found,
+        search_port_ranges(
+            event.container_id,
+            comm,
+            event.protocol,
+            event.dport,
+            event.sport,
+            &cidr_key.remote_ip,
+        ),
+    )
 }
 
 unsafe fn finish(
@@ -83,9 +147,32 @@ unsafe fn finish(
     event: &mut Ingress6Event,
 ) -> Result<i32, c_long> {
     event.action = action;
-    INGRESS6_EVENTS.output(ctx, event, 0);
-    Ok(match action {
+    event.would_drop = action == TcAction::Drop && is_audit(&event.container_id);
+    count_packet(
+        ctx,
+        &event.container_id,
+        Direction::Ingress,
+        event.protocol,
+        action,
+        event.would_drop,
+    );
+    output_event(EbpfEvent::Ingress6(*event));
+    let verdict = match action {
         TcAction::Pass => TC_ACT_OK,
+        TcAction::Drop if event.would_drop => TC_ACT_OK,
         TcAction::Drop => TC_ACT_SHOT,
-    })
+    };
+
+    // Only the responses the container receives are learned from.
+    if verdict == TC_ACT_OK && event.protocol == IpProtocol::UDP && event.sport == DNS_PORT {
+        let _ = snoop_dns_response(
+            ctx,
+            IPV6_HDR_LEN,
+            &event.container_id,
+            event.dport,
+            event.saddr,
+        );
+    }
+
+    Ok(verdict)
 }
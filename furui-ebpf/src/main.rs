@@ -1,10 +1,15 @@
 #![no_std]
 #![no_main]
 
-use aya_ebpf::{macros::map, maps::HashMap};
+use aya_ebpf::{
+    macros::map,
+    maps::{HashMap, LpmTrie, LruHashMap, PerCpuArray, PerCpuHashMap, RingBuf},
+};
 use furui_common::{
-    ContainerID, ContainerIP, IcmpPolicyKey, IcmpPolicyValue, PolicyKey, PolicyValue, PortKey,
-    PortVal,
+    CgroupKey, ContainerID, ContainerIP, DnsQueryKey, ExeKey, ExeValue, FqdnIpKey, FqdnIpValue,
+    FqdnKey, FqdnPolicyKey, IcmpPolicyCidrKey, IcmpPolicyKey, IcmpPolicyValue, PacketCount,
+    PacketCountKey, PolicyCidrKey, PolicyKey, PolicyValue, PortKey, PortRangePolicies,
+    PortRangePolicyKey, PortVal, ProcessTreeKey, TcAction,
 };
 
 #[allow(warnings)]
@@ -21,17 +26,86 @@ mod ingress;
 #[map]
 pub(crate) static PROC_PORTS: HashMap<PortKey, PortVal> = HashMap::with_max_entries(1024, 0);
 
+// Executables named by `executable_path` rules, whose processes are matched by the name of the
+// rule instead of their own.
+#[map]
+pub(crate) static EXE_LIST: HashMap<ExeKey, ExeValue> = HashMap::with_max_entries(1024, 0);
+
+// Cgroups named by `cgroup` rules, likewise.
+#[map]
+pub(crate) static CGROUP_LIST: HashMap<CgroupKey, ExeValue> = HashMap::with_max_entries(1024, 0);
+
+// Processes in the process tree of an executable named by a `process_tree` rule, likewise.
+#[map]
+pub(crate) static PROCESS_TREE_LIST: HashMap<ProcessTreeKey, ExeValue> =
+    HashMap::with_max_entries(4096, 0);
+
 #[map]
 pub(crate) static POLICY_LIST: HashMap<PolicyKey, PolicyValue> = HashMap::with_max_entries(1024, 0);
 
+#[map]
+pub(crate) static POLICY_CIDR_LIST: LpmTrie<PolicyCidrKey, PolicyValue> =
+    LpmTrie::with_max_entries(1024, 0);
+
+#[map]
+pub(crate) static PORT_RANGE_POLICY_LIST: HashMap<PortRangePolicyKey, PortRangePolicies> =
+    HashMap::with_max_entries(1024, 0);
+
 #[map]
 pub(crate) static ICMP_POLICY_LIST: HashMap<IcmpPolicyKey, IcmpPolicyValue> =
     HashMap::with_max_entries(1024, 0);
 
+#[map]
+pub(crate) static ICMP_POLICY_CIDR_LIST: LpmTrie<IcmpPolicyCidrKey, IcmpPolicyValue> =
+    LpmTrie::with_max_entries(1024, 0);
+
+#[map]
+pub(crate) static FQDN_LIST: HashMap<FqdnKey, u32> = HashMap::with_max_entries(1024, 0);
+
+#[map]
+pub(crate) static FQDN_POLICY_LIST: HashMap<FqdnPolicyKey, PolicyValue> =
+    HashMap::with_max_entries(1024, 0);
+
+// Filled from the DNS responses to the containers, the least recently used addresses make room
+// for new ones.
+#[map]
+pub(crate) static FQDN_IPS: LruHashMap<FqdnIpKey, FqdnIpValue> =
+    LruHashMap::with_max_entries(16384, 0);
+
+// The DNS queries of the containers waiting for their response, which is the only one learned
+// from. Queries that are never answered make room for new ones.
+#[map]
+pub(crate) static DNS_QUERIES: LruHashMap<DnsQueryKey, u32> = LruHashMap::with_max_entries(4096, 0);
+
+// The cgroups of the containers, which the kprobes find the container of a task by.
+#[map]
+pub(crate) static CONTAINER_ID_FROM_CGROUPS: HashMap<u64, ContainerID> =
+    HashMap::with_max_entries(1024, 0);
+
 #[map]
 pub(crate) static CONTAINER_ID_FROM_IPS: HashMap<ContainerIP, ContainerID> =
     HashMap::with_max_entries(1024, 0);
 
+#[map]
+pub(crate) static AUDIT_CONTAINERS: HashMap<ContainerID, u8> = HashMap::with_max_entries(1024, 0);
+
+#[map]
+pub(crate) static DEFAULT_ACTIONS: HashMap<ContainerID, TcAction> =
+    HashMap::with_max_entries(1024, 0);
+
+// Kept per CPU so that the classifiers never contend for a counter. Userspace sums them up.
+#[map]
+pub(crate) static PACKET_COUNTS: PerCpuHashMap<PacketCountKey, PacketCount> =
+    PerCpuHashMap::with_max_entries(4096, 0);
+
+// The events of every program, read by a single task in userspace.
+#[map]
+pub(crate) static EVENTS: RingBuf = RingBuf::with_byte_size(256 * 1024, 0);
+
+// The events that did not fit in `EVENTS`, at index 0.
+#[map]
+pub(crate) static EVENTS_LOST: PerCpuArray<u64> = PerCpuArray::with_max_entries(1, 0);
+
 #[panic_handler]
 fn panic(_info: &core::panic::PanicInfo) -> ! {
     unsafe { core::hint::unreachable_unchecked() }
@@ -1,13 +1,28 @@
 extern crate proc_macro;
 
-use std::collections::HashMap;
-
 use itertools::Itertools;
 use proc_macro::TokenStream;
 use proc_macro2::{Delimiter, TokenTree};
 use quote::quote;
 use syn::{parse_macro_input, DeriveInput};
 
+const ADDRESS_TARGETS: [&str; 2] = ["remote_ip", "remote_ipv6"];
+
+/// Policy key fields from the most to the least specific. Keys using more fields are searched
+/// first, and among keys with the same number of fields the more specific fields come first.
+/// `search_rank` in furui-common numbers the keys in this order.
+const SEARCH_PRECEDENCE: [&str; 7] = [
+    "remote_ip",
+    "remote_ipv6",
+    "local_port",
+    "remote_port",
+    "protocol",
+    "type_",
+    "code",
+];
+
+type FieldDefault = (syn::Ident, TokenTree, proc_macro2::TokenStream);
+
 #[proc_macro_derive(SearchPolicyKey, attributes(search_key))]
 pub fn search_policy_key(input: TokenStream) -> TokenStream {
     let input: DeriveInput = parse_macro_input!(input as DeriveInput);
@@ -34,7 +49,7 @@ fn generate_search_policy_key(
         }
     };
 
-    let mut field_defaults = HashMap::new();
+    let mut field_defaults: Vec<FieldDefault> = Vec::new();
     for field in &struct_data.fields {
         let filed_name = field.ident.as_ref().unwrap();
 
@@ -48,16 +63,14 @@ fn generate_search_policy_key(
                     let target = group_stream.next().unwrap();
                     group_stream.next();
 
-                    field_defaults.insert(
-                        filed_name,
-                        (
-                            target,
-                            group_stream
-                                .collect_vec()
-                                .into_iter()
-                                .collect::<proc_macro2::TokenStream>(),
-                        ),
-                    );
+                    field_defaults.push((
+                        filed_name.clone(),
+                        target,
+                        group_stream
+                            .collect_vec()
+                            .into_iter()
+                            .collect::<proc_macro2::TokenStream>(),
+                    ));
                 }
                 _ => {
                     return Err(syn::Error::new_spanned(
@@ -69,48 +82,123 @@ fn generate_search_policy_key(
         }
     }
 
-    let mut sections = Vec::new();
-    for i in 1..=field_defaults.len() {
-        for use_fields in field_defaults.keys().into_iter().combinations(i) {
-            for (field_name, (target, value)) in field_defaults.clone() {
-                if use_fields.contains(&&field_name) {
-                    sections.push(quote! {
-                        policy_key.#target = self.#field_name;
-                    });
-                } else {
-                    sections.push(quote! {
-                        policy_key.#target = #value;
-                    });
-                }
-            }
-            sections.push(quote! {
-                if callback(policy_key) {
-                    return true;
-                };
-            });
-        }
-    }
+    field_defaults.sort_by_key(|(_, target, _)| {
+        SEARCH_PRECEDENCE
+            .iter()
+            .position(|name| *name == target.to_string())
+            .unwrap_or(SEARCH_PRECEDENCE.len())
+    });
+
+    // The remote address is matched by prefix in the CIDR lists, so it is set by the caller
+    // and only the remaining fields are searched.
+    let cidr_field_defaults = field_defaults
+        .iter()
+        .filter(|(_, target, _)| !ADDRESS_TARGETS.contains(&target.to_string().as_str()))
+        .cloned()
+        .collect_vec();
+
+    let sections = generate_sections(&field_defaults, &field_defaults, quote!(Address));
+    let cidr_sections = generate_sections(&field_defaults, &cidr_field_defaults, quote!(Network));
+    let fqdn_sections = generate_sections(&field_defaults, &cidr_field_defaults, quote!(Address));
 
     let struct_name = &derive_input.ident;
 
-    let policy_key_name = if is_icmp {
-        quote!(IcmpPolicyKey)
+    let (policy_key_name, policy_cidr_key_name) = if is_icmp {
+        (quote!(IcmpPolicyKey), quote!(IcmpPolicyCidrKey))
+    } else {
+        (quote!(PolicyKey), quote!(PolicyCidrKey))
+    };
+
+    // Rules naming a domain exist only for sockets. The domain takes the place of the remote
+    // address, so the same fields as in the CIDR lists are searched.
+    let search_fqdn_key = if is_icmp {
+        quote!()
     } else {
-        quote!(PolicyKey)
+        quote!(
+            pub fn search_fqdn_key<F: Fn(&FqdnPolicyKey) -> Option<TcAction>>(
+                &mut self,
+                policy_key: &mut FqdnPolicyKey,
+                callback: F,
+            ) -> Option<PolicyMatch> {
+                #(#fqdn_sections)*
+
+                None
+            }
+        )
     };
 
+    // The callback returns the action of the policy found for the key, if any. Keys are searched
+    // from the most specific, so the first policy found is the most specific one of the list.
     Ok(quote!(
         impl #struct_name {
-            pub fn search_key<F: Fn(&#policy_key_name) -> bool>(
+            pub fn search_key<F: Fn(&#policy_key_name) -> Option<TcAction>>(
                 &mut self,
                 policy_key: &mut #policy_key_name,
                 callback: F,
-            ) -> bool {
+            ) -> Option<PolicyMatch> {
                 #(#sections)*
 
-                false
+                None
+            }
+
+            pub fn search_cidr_key<F: Fn(&#policy_cidr_key_name) -> Option<TcAction>>(
+                &mut self,
+                policy_key: &mut #policy_cidr_key_name,
+                callback: F,
+            ) -> Option<PolicyMatch> {
+                #(#cidr_sections)*
+
+                None
             }
+
+            #search_fqdn_key
         }
     )
     .into())
 }
+
+/// Generates the lookups of the keys using each combination of `searched`, from the most
+/// specific. A field of `all_fields` that is not searched is always used, as the caller sets it.
+fn generate_sections(
+    all_fields: &[FieldDefault],
+    searched: &[FieldDefault],
+    list: proc_macro2::TokenStream,
+) -> Vec<proc_macro2::TokenStream> {
+    let is_used = |use_fields: &[&FieldDefault], field_name: &syn::Ident| {
+        use_fields.iter().any(|(name, _, _)| name == field_name)
+    };
+
+    let mut sections = Vec::new();
+    for i in (0..=searched.len()).rev() {
+        for use_fields in searched.iter().combinations(i) {
+            for (field_name, target, value) in searched {
+                if is_used(&use_fields, field_name) {
+                    sections.push(quote! {
+                        policy_key.#target = self.#field_name;
+                    });
+                } else {
+                    sections.push(quote! {
+                        policy_key.#target = #value;
+                    });
+                }
+            }
+
+            let used = all_fields.iter().map(|(field_name, _, _)| {
+                is_used(&use_fields, field_name)
+                    || !searched.iter().any(|(name, _, _)| name == field_name)
+            });
+
+            sections.push(quote! {
+                {
+                    const RANK: u32 = search_rank(&[#(#used),*], PolicyList::#list);
+
+                    if let Some(action) = callback(policy_key) {
+                        return Some(PolicyMatch { rank: RANK, action });
+                    }
+                }
+            });
+        }
+    }
+
+    sections
+}
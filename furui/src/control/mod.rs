@@ -0,0 +1,64 @@
+use std::{
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use hyper_util::rt::TokioIo;
+use proto::{control_client::ControlClient, control_server::ControlServer};
+pub use service::ControlService;
+use tokio::{
+    net::{UnixListener, UnixStream},
+    task,
+};
+use tokio_stream::wrappers::UnixListenerStream;
+use tonic::transport::{Channel, Endpoint, Server};
+use tower::service_fn;
+use tracing::warn;
+
+pub mod proto {
+    use tonic;
+
+    tonic::include_proto!("furui.v1");
+}
+
+mod service;
+
+/// Serves the control API on a Unix socket that only root can connect to.
+pub fn serve(socket_path: &Path, service: ControlService) -> anyhow::Result<()> {
+    if let Some(socket_dir) = socket_path.parent() {
+        fs::create_dir_all(socket_dir)?;
+    }
+
+    // Left behind when furui did not exit cleanly.
+    let _ = fs::remove_file(socket_path);
+
+    let listener = UnixListener::bind(socket_path)?;
+    fs::set_permissions(socket_path, fs::Permissions::from_mode(0o600))?;
+
+    task::spawn(async move {
+        Server::builder()
+            .add_service(ControlServer::new(service))
+            .serve_with_incoming(UnixListenerStream::new(listener))
+            .await
+            .unwrap_or_else(|e| warn!("the control API stopped: {}", e));
+    });
+
+    Ok(())
+}
+
+/// Connects to the control API of a running furui.
+pub async fn connect(socket_path: PathBuf) -> anyhow::Result<ControlClient<Channel>> {
+    // The URI is not used, as the connector dials the socket.
+    let channel = Endpoint::try_from("http://[::]")?
+        .connect_with_connector(service_fn(move |_| {
+            let socket_path = socket_path.clone();
+            async move {
+                let io = TokioIo::new(UnixStream::connect(socket_path).await?);
+                Ok::<_, std::io::Error>(io)
+            }
+        }))
+        .await?;
+
+    Ok(ControlClient::new(channel))
+}
@@ -0,0 +1,616 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+};
+
+use furui_common::IpProtocol;
+use tokio::{
+    select,
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc, Mutex,
+    },
+    task,
+};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+use tracing::warn;
+
+use crate::{
+    control::proto::{
+        self, control_server::Control, AddPolicyRequest, AddPolicyResponse, CheckRequest,
+        CheckResponse, ListContainersRequest, ListContainersResponse, ListMapEntriesRequest,
+        ListMapEntriesResponse, ListPoliciesRequest, ListPoliciesResponse, ListProcessesRequest,
+        ListProcessesResponse, RemovePolicyRequest, RemovePolicyResponse, SetAuditRequest,
+        SetAuditResponse, WatchEventsRequest,
+    },
+    domain::{self, Container, Event, Policies},
+    handle::{PidProcesses, PolicySources},
+    map::Packet,
+    parse_policies::Policy,
+    Containers, Maps,
+};
+
+pub struct ControlService {
+    sources: Arc<Mutex<PolicySources>>,
+    maps: Arc<Maps>,
+    policies: Arc<Mutex<Policies>>,
+    containers: Arc<Mutex<Containers>>,
+    pid_processes: Arc<Mutex<PidProcesses>>,
+    events: broadcast::Sender<Event>,
+}
+
+impl ControlService {
+    pub fn new(
+        sources: Arc<Mutex<PolicySources>>,
+        maps: Arc<Maps>,
+        policies: Arc<Mutex<Policies>>,
+        containers: Arc<Mutex<Containers>>,
+        pid_processes: Arc<Mutex<PidProcesses>>,
+        events: broadcast::Sender<Event>,
+    ) -> ControlService {
+        ControlService {
+            sources,
+            maps,
+            policies,
+            containers,
+            pid_processes,
+            events,
+        }
+    }
+
+    /// Loads the changed sources, or puts them back as they were when they do not load.
+    async fn apply(
+        &self,
+        sources: &mut PolicySources,
+        undo: impl FnOnce(&mut PolicySources),
+    ) -> Result<(), Status> {
+        let result = sources
+            .apply(&self.maps, &self.policies, self.containers.clone())
+            .await;
+
+        if let Err(err) = result {
+            undo(sources);
+            return Err(Status::failed_precondition(err.to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+/// The name of the container whose ID starts with the shortened ID the maps hold.
+fn container_name(containers: &[Container], container_id: &str) -> String {
+    containers
+        .iter()
+        .find(|container| {
+            container
+                .id
+                .as_ref()
+                .is_some_and(|id| id.starts_with(container_id))
+        })
+        .map(|container| container.name.trim_start_matches("/").to_string())
+        .unwrap_or_default()
+}
+
+/// Whether `name_or_id` is the name of the container, or its ID in full or shortened.
+fn is_container(container: &Container, name_or_id: &str) -> bool {
+    if container.name.trim_start_matches("/") == name_or_id {
+        return true;
+    }
+
+    match &container.id {
+        Some(id) if !name_or_id.is_empty() => {
+            id.starts_with(name_or_id) || name_or_id.starts_with(id.as_str())
+        }
+        _ => false,
+    }
+}
+
+/// Whether a filter of `WatchEventsRequest` passes `value`.
+fn passes(filter: &[String], value: &str) -> bool {
+    filter.is_empty() || filter.iter().any(|v| v.eq_ignore_ascii_case(value))
+}
+
+/// Whether an event of `container` passes every filter of a `WatchEventsRequest`. An event of a
+/// container that is not known passes only without a container filter.
+fn watches(filter: &WatchEventsRequest, event: &Event, container: Option<&Container>) -> bool {
+    passes(&filter.events, event.event)
+        && passes(&filter.actions, event.action)
+        && passes(&filter.protocols, event.protocol)
+        && (filter.containers.is_empty()
+            || filter.containers.iter().any(|name_or_id| {
+                container.is_some_and(|container| is_container(container, name_or_id))
+            }))
+}
+
+fn protocol_name(protocol: IpProtocol) -> String {
+    match protocol {
+        IpProtocol::Default => "".to_string(),
+        protocol => protocol.to_string().to_lowercase(),
+    }
+}
+
+#[tonic::async_trait]
+impl Control for ControlService {
+    type WatchEventsStream = ReceiverStream<Result<proto::Event, Status>>;
+
+    async fn list_policies(
+        &self,
+        _: Request<ListPoliciesRequest>,
+    ) -> Result<Response<ListPoliciesResponse>, Status> {
+        let sources = self.sources.lock().await;
+        let policies = self.policies.lock().await;
+
+        let parsed_policies = match sources.applied() {
+            Some(parsed_policies) => &parsed_policies.policies,
+            None => return Ok(Response::new(ListPoliciesResponse::default())),
+        };
+
+        // The policies are made from the parsed policies in the same order.
+        let policies = parsed_policies
+            .iter()
+            .zip(&policies.policies)
+            .map(|(parsed_policy, policy)| proto::Policy {
+                selector: parsed_policy.container.to_selector().to_string(),
+                container_ids: policy.container_ids.clone(),
+                policy: serde_yaml::to_string(parsed_policy).unwrap_or_default(),
+                added: sources.is_added(parsed_policy),
+            })
+            .collect();
+
+        Ok(Response::new(ListPoliciesResponse { policies }))
+    }
+
+    async fn list_containers(
+        &self,
+        _: Request<ListContainersRequest>,
+    ) -> Result<Response<ListContainersResponse>, Status> {
+        let containers = self.containers.lock().await.list();
+        let sources = self.sources.lock().await;
+
+        let containers = containers
+            .into_iter()
+            .map(|container| proto::Container {
+                id: container.id.clone().unwrap_or_default(),
+                audit: sources.is_audit(container.name.trim_start_matches("/")),
+                name: container.name,
+                pid: container.pid,
+                image: container.image,
+                ip_addresses: container
+                    .ip_addresses
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|ip| ip.to_string())
+                    .collect(),
+                labels: container.labels,
+            })
+            .collect();
+
+        Ok(Response::new(ListContainersResponse { containers }))
+    }
+
+    async fn list_processes(
+        &self,
+        _: Request<ListProcessesRequest>,
+    ) -> Result<Response<ListProcessesResponse>, Status> {
+        let processes = self
+            .pid_processes
+            .lock()
+            .await
+            .list()
+            .into_iter()
+            .map(|(pid, process)| proto::Process {
+                pid,
+                container_id: process.container_id,
+                port: process.port as u32,
+                protocol: process.protocol.to_string().to_lowercase(),
+            })
+            .collect();
+
+        Ok(Response::new(ListProcessesResponse { processes }))
+    }
+
+    async fn add_policy(
+        &self,
+        request: Request<AddPolicyRequest>,
+    ) -> Result<Response<AddPolicyResponse>, Status> {
+        let request = request.into_inner();
+
+        let policy = Policy::parse_for(&request.container, &request.policy)
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+
+        let mut sources = self.sources.lock().await;
+
+        let previous = sources.added(&request.container).cloned();
+        sources.add(request.container.clone(), policy);
+
+        self.apply(&mut sources, |sources| match previous {
+            Some(previous) => sources.add(request.container, previous),
+            None => {
+                sources.remove(&request.container);
+            }
+        })
+        .await?;
+
+        Ok(Response::new(AddPolicyResponse {}))
+    }
+
+    async fn remove_policy(
+        &self,
+        request: Request<RemovePolicyRequest>,
+    ) -> Result<Response<RemovePolicyResponse>, Status> {
+        let request = request.into_inner();
+
+        let mut sources = self.sources.lock().await;
+
+        let previous = match sources.added(&request.container).cloned() {
+            Some(previous) => previous,
+            None => {
+                return Err(Status::not_found(format!(
+                    "no policy was added for {}",
+                    request.container
+                )))
+            }
+        };
+        sources.remove(&request.container);
+
+        self.apply(&mut sources, |sources| {
+            sources.add(request.container, previous)
+        })
+        .await?;
+
+        Ok(Response::new(RemovePolicyResponse {}))
+    }
+
+    async fn set_audit(
+        &self,
+        request: Request<SetAuditRequest>,
+    ) -> Result<Response<SetAuditResponse>, Status> {
+        let request = request.into_inner();
+
+        let mut sources = self.sources.lock().await;
+
+        let previous = sources.is_audit(&request.container);
+        sources.set_audit(request.container.clone(), request.audit);
+
+        self.apply(&mut sources, |sources| {
+            sources.set_audit(request.container, previous)
+        })
+        .await?;
+
+        Ok(Response::new(SetAuditResponse {}))
+    }
+
+    async fn list_map_entries(
+        &self,
+        _: Request<ListMapEntriesRequest>,
+    ) -> Result<Response<ListMapEntriesResponse>, Status> {
+        let internal = |err: anyhow::Error| Status::internal(err.to_string());
+
+        let policy_entries = self.maps.inspect.policies().await.map_err(internal)?;
+        let port_entries = self.maps.inspect.ports().await.map_err(internal)?;
+        let ip_entries = self.maps.inspect.container_ips().await.map_err(internal)?;
+
+        let containers = self.containers.lock().await.list();
+        let policies = self.policies.lock().await;
+
+        let policies = policy_entries
+            .into_iter()
+            .map(|(key, value)| {
+                let container_id = domain::c_char_bytes_to_string(key.container_id);
+
+                let remote_ip = if key.remote_ip != 0 {
+                    Ipv4Addr::from(key.remote_ip).to_string()
+                } else if key.remote_ipv6 != [0; 16] {
+                    Ipv6Addr::from(key.remote_ipv6).to_string()
+                } else {
+                    "".to_string()
+                };
+
+                proto::PolicyEntry {
+                    container_name: container_name(&containers, &container_id),
+                    container_id,
+                    process: policies.process_name(key.comm),
+                    remote_ip,
+                    local_port: key.local_port as u32,
+                    remote_port: key.remote_port as u32,
+                    protocol: protocol_name(key.protocol),
+                    action: value.action.to_string().to_string(),
+                }
+            })
+            .collect();
+
+        let ports = port_entries
+            .into_iter()
+            .map(|(key, value)| {
+                let container_id = domain::c_char_bytes_to_string(key.container_id);
+
+                proto::PortEntry {
+                    container_name: container_name(&containers, &container_id),
+                    container_id,
+                    port: key.port as u32,
+                    protocol: protocol_name(key.proto),
+                    process: domain::u8_bytes_to_string(value.comm),
+                    exe_ino: value.exe_ino,
+                    exe_dev: value.exe_dev,
+                    cgroup_id: value.cgroup_id,
+                }
+            })
+            .collect();
+
+        let container_ips = ip_entries
+            .into_iter()
+            .map(|(key, value)| {
+                let container_id = domain::c_char_bytes_to_string(value.container_id);
+
+                let ip = if key.ip != 0 {
+                    Ipv4Addr::from(key.ip).to_string()
+                } else {
+                    Ipv6Addr::from(key.ipv6).to_string()
+                };
+
+                proto::ContainerIpEntry {
+                    ip,
+                    container_name: container_name(&containers, &container_id),
+                    container_id,
+                }
+            })
+            .collect();
+
+        Ok(Response::new(ListMapEntriesResponse {
+            policies,
+            ports,
+            container_ips,
+        }))
+    }
+
+    async fn check(
+        &self,
+        request: Request<CheckRequest>,
+    ) -> Result<Response<CheckResponse>, Status> {
+        let request = request.into_inner();
+
+        let ingress = match request.direction.as_str() {
+            "ingress" => true,
+            "egress" => false,
+            direction => {
+                return Err(Status::invalid_argument(format!(
+                    "unknown direction: {}",
+                    direction
+                )))
+            }
+        };
+
+        let protocol = match request.protocol.as_str() {
+            "tcp" => IpProtocol::TCP,
+            "udp" => IpProtocol::UDP,
+            protocol => {
+                return Err(Status::invalid_argument(format!(
+                    "unknown protocol: {}",
+                    protocol
+                )))
+            }
+        };
+
+        let remote_ip = request
+            .remote_ip
+            .parse::<IpAddr>()
+            .map_err(|err| Status::invalid_argument(format!("remote_ip: {}", err)))?;
+
+        let (local_port, remote_port) = match (
+            request.local_port.try_into(),
+            request.remote_port.try_into(),
+        ) {
+            (Ok(local_port), Ok(remote_port)) => (local_port, remote_port),
+            _ => return Err(Status::invalid_argument("the ports must be below 65536")),
+        };
+
+        let container = self
+            .containers
+            .lock()
+            .await
+            .list()
+            .into_iter()
+            .find(|container| is_container(container, &request.container))
+            .ok_or_else(|| Status::not_found(format!("no container {}", request.container)))?;
+
+        let packet = Packet {
+            ingress,
+            protocol,
+            local_port,
+            remote_ip,
+            remote_port,
+        };
+
+        let verdict = self
+            .maps
+            .inspect
+            .check(&container.id.unwrap_or_default(), &packet)
+            .await
+            .map_err(|err| Status::internal(err.to_string()))?;
+
+        let process = match verdict.comm {
+            Some(comm) => self.policies.lock().await.process_name(comm),
+            None => "".to_string(),
+        };
+
+        Ok(Response::new(CheckResponse {
+            action: verdict.action.to_string().to_string(),
+            would_drop: verdict.would_drop,
+            decided_by: verdict.decided_by.to_string(),
+            process,
+        }))
+    }
+
+    async fn watch_events(
+        &self,
+        request: Request<WatchEventsRequest>,
+    ) -> Result<Response<Self::WatchEventsStream>, Status> {
+        let filter = request.into_inner();
+
+        let mut events = self.events.subscribe();
+        let containers = self.containers.clone();
+
+        let (tx, rx) = mpsc::channel(128);
+
+        task::spawn(async move {
+            loop {
+                let event = select! {
+                    event = events.recv() => event,
+                    _ = tx.closed() => return,
+                };
+
+                let event = match event {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("{} events were skipped for a slow subscriber", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return,
+                };
+
+                let container = containers.lock().await.get(event.container_id.clone());
+
+                if !watches(&filter, &event, container.as_ref()) {
+                    continue;
+                }
+
+                let event = proto::Event {
+                    event: event.event.to_string(),
+                    action: event.action.to_string(),
+                    container_name: container
+                        .map(|container| container.name.trim_start_matches("/").to_string())
+                        .unwrap_or_default(),
+                    container_id: event.container_id,
+                    pid: event.pid,
+                    comm: event.comm,
+                    family: event.family.to_lowercase(),
+                    protocol: event.protocol.to_lowercase(),
+                    source_addr: event.source_addr,
+                    source_port: event.source_port as u32,
+                    destination_addr: event.destination_addr,
+                    destination_port: event.destination_port as u32,
+                    icmp_version: event.icmp_version.to_string(),
+                    icmp_type: event.icmp_type as u32,
+                    icmp_code: event.icmp_code as u32,
+                };
+
+                if tx.send(Ok(event)).await.is_err() {
+                    return;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn container(id: &str, name: &str) -> Container {
+        Container {
+            name: name.to_string(),
+            ..Container::new(id.to_string())
+        }
+    }
+
+    #[test]
+    fn a_container_is_named_by_its_name_or_its_id() {
+        let web = container("3f4e8a9b2c1d5e6f", "/web");
+
+        assert!(is_container(&web, "web"));
+        assert!(is_container(&web, "3f4e8a9b2c1d5e6f"));
+        assert!(is_container(&web, "3f4e8a"));
+        assert!(is_container(&web, "3f4e8a9b2c1d5e6f7a8b"));
+        assert!(!is_container(&web, "/web"));
+        assert!(!is_container(&web, "db"));
+        assert!(!is_container(&web, "4f4e8a"));
+        assert!(!is_container(&web, ""));
+    }
+
+    #[test]
+    fn container_name_is_found_by_the_shortened_id() {
+        let containers = vec![
+            container("3f4e8a9b2c1d5e6f", "/web"),
+            container("9a8b7c6d5e4f3a2b", "/db"),
+        ];
+
+        assert_eq!(container_name(&containers, "9a8b7c6d5e4f"), "db");
+        assert_eq!(container_name(&containers, "3f4e8a9b2c1d"), "web");
+        assert_eq!(container_name(&containers, "0123456789ab"), "");
+    }
+
+    fn drop_event() -> Event {
+        Event {
+            event: "egress",
+            action: "drop",
+            container_id: "3f4e8a9b2c1d".to_string(),
+            protocol: "TCP",
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn an_empty_filter_passes_everything() {
+        assert!(passes(&[], "drop"));
+        assert!(passes(&["pass".to_string(), "DROP".to_string()], "drop"));
+        assert!(!passes(&["pass".to_string()], "drop"));
+
+        let filter = WatchEventsRequest::default();
+        assert!(watches(&filter, &drop_event(), None));
+        assert!(watches(&filter, &Event::default(), None));
+    }
+
+    #[test]
+    fn every_filter_has_to_pass() {
+        let web = container("3f4e8a9b2c1d5e6f", "/web");
+        let filter = WatchEventsRequest {
+            containers: vec!["web".to_string()],
+            actions: vec!["drop".to_string(), "would-drop".to_string()],
+            protocols: vec!["tcp".to_string()],
+            events: vec!["egress".to_string()],
+        };
+
+        assert!(watches(&filter, &drop_event(), Some(&web)));
+        assert!(!watches(&filter, &drop_event(), None));
+        assert!(!watches(
+            &filter,
+            &drop_event(),
+            Some(&container("9a8b7c6d5e4f3a2b", "/db"))
+        ));
+        assert!(!watches(
+            &filter,
+            &Event {
+                action: "pass",
+                ..drop_event()
+            },
+            Some(&web)
+        ));
+        assert!(!watches(
+            &filter,
+            &Event {
+                protocol: "UDP",
+                ..drop_event()
+            },
+            Some(&web)
+        ));
+        assert!(!watches(
+            &filter,
+            &Event {
+                event: "ingress",
+                ..drop_event()
+            },
+            Some(&web)
+        ));
+    }
+
+    #[test]
+    fn protocol_name_leaves_out_the_default() {
+        assert_eq!(protocol_name(IpProtocol::TCP), "tcp");
+        assert_eq!(protocol_name(IpProtocol::UDP), "udp");
+        assert_eq!(protocol_name(IpProtocol::ICMP), "icmp");
+        assert_eq!(protocol_name(IpProtocol::Default), "");
+    }
+}
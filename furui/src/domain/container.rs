@@ -10,6 +10,10 @@ pub struct Container {
     pub ip_addresses: Option<Vec<IpAddr>>,
     pub name: String,
     pub pid: u32,
+    /// The cgroup v2 ID of the first process, 0 when it is not known.
+    pub cgroup_id: u64,
+    pub labels: HashMap<String, String>,
+    pub image: String,
 }
 
 impl Container {
@@ -19,6 +23,9 @@ impl Container {
             ip_addresses: None,
             name: "".to_string(),
             pid: 0,
+            cgroup_id: 0,
+            labels: HashMap::new(),
+            image: "".to_string(),
         }
     }
 
@@ -72,17 +79,6 @@ impl Containers {
         None
     }
 
-    pub fn ids(&self) -> HashMap<String, String> {
-        let mut map = HashMap::new();
-        for container in &self.containers {
-            map.insert(
-                container.name.clone().trim_start_matches("/").to_string(),
-                container.id.as_ref().unwrap().clone(),
-            );
-        }
-        map
-    }
-
     pub fn remove(&mut self, id: String) {
         for (i, container) in self.containers.clone().iter().enumerate() {
             if id.starts_with(&container.id.clone().unwrap()) {
@@ -0,0 +1,130 @@
+use furui_common::{
+    BindEvent, Connect6Event, ConnectEvent, Egress6Event, Egress6IcmpEvent, EgressEvent,
+    EgressIcmpEvent, Ingress6Event, Ingress6IcmpEvent, IngressEvent, IngressIcmpEvent,
+};
+use tokio::sync::broadcast;
+
+/// A subscriber that has not taken this many events yet misses the older ones.
+const EVENTS_CAPACITY: usize = 1024;
+
+/// Sends the events to the subscribers of the control API. Sending fails only when there are
+/// none, which is ignored.
+pub fn event_channel() -> broadcast::Sender<Event> {
+    broadcast::channel(EVENTS_CAPACITY).0
+}
+
+/// An event of the eBPF programs with the fields it is logged with, for the subscribers of the
+/// control API. Fields an event does not have are left empty.
+#[derive(Debug, Clone, Default)]
+pub struct Event {
+    /// ingress, egress, bind or connect.
+    pub event: &'static str,
+    /// pass, drop or would-drop, for ingress and egress.
+    pub action: &'static str,
+    pub container_id: String,
+    pub pid: u32,
+    pub comm: String,
+    pub family: &'static str,
+    pub protocol: &'static str,
+    pub source_addr: String,
+    pub source_port: u16,
+    pub destination_addr: String,
+    pub destination_port: u16,
+    pub icmp_version: &'static str,
+    pub icmp_type: u8,
+    pub icmp_code: u8,
+}
+
+macro_rules! impl_from_packet_event {
+    ($name:literal, $($event:ty),*) => {
+        $(
+            impl From<&$event> for Event {
+                fn from(event: &$event) -> Event {
+                    Event {
+                        event: $name,
+                        action: event.action_name(),
+                        container_id: event.container_id(),
+                        comm: event.comm(),
+                        family: event.family.to_string(),
+                        protocol: event.protocol.to_string(),
+                        source_addr: event.src_addr(),
+                        source_port: event.sport,
+                        destination_addr: event.dst_addr(),
+                        destination_port: event.dport,
+                        ..Default::default()
+                    }
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! impl_from_icmp_event {
+    ($name:literal, $($event:ty),*) => {
+        $(
+            impl From<&$event> for Event {
+                fn from(event: &$event) -> Event {
+                    Event {
+                        event: $name,
+                        action: event.action_name(),
+                        container_id: event.container_id(),
+                        family: event.family.to_string(),
+                        protocol: event.protocol.to_string(),
+                        source_addr: event.src_addr(),
+                        destination_addr: event.dst_addr(),
+                        icmp_version: event.version.to_string(),
+                        icmp_type: event.type_,
+                        icmp_code: event.code,
+                        ..Default::default()
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_from_packet_event!("ingress", IngressEvent, Ingress6Event);
+impl_from_packet_event!("egress", EgressEvent, Egress6Event);
+impl_from_icmp_event!("ingress", IngressIcmpEvent, Ingress6IcmpEvent);
+impl_from_icmp_event!("egress", EgressIcmpEvent, Egress6IcmpEvent);
+
+impl From<&BindEvent> for Event {
+    fn from(event: &BindEvent) -> Event {
+        Event {
+            event: "bind",
+            container_id: event.container_id(),
+            pid: event.pid,
+            comm: event.comm(),
+            family: event.family.to_string(),
+            protocol: event.protocol.to_string(),
+            source_port: event.lport,
+            ..Default::default()
+        }
+    }
+}
+
+macro_rules! impl_from_connect_event {
+    ($($event:ty),*) => {
+        $(
+            impl From<&$event> for Event {
+                fn from(event: &$event) -> Event {
+                    Event {
+                        event: "connect",
+                        container_id: event.container_id(),
+                        pid: event.pid,
+                        comm: event.comm(),
+                        family: event.family.to_string(),
+                        protocol: event.protocol.to_string(),
+                        source_addr: event.src_addr(),
+                        source_port: event.src_port,
+                        destination_addr: event.dst_addr(),
+                        destination_port: event.dst_port,
+                        ..Default::default()
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_from_connect_event!(ConnectEvent, Connect6Event);
@@ -1,9 +1,11 @@
 use aya_ebpf::cty::c_char;
 pub use container::*;
+pub use event::*;
 pub use policy::*;
 pub use process::*;
 
 mod container;
+mod event;
 mod policy;
 mod process;
 
@@ -18,7 +20,18 @@ fn string_to_u8_bytes<const N: usize>(src: String) -> [u8; N] {
     result
 }
 
-fn string_to_c_char_bytes<const N: usize>(src: String) -> [c_char; N] {
+pub(crate) fn u8_bytes_to_string<const N: usize>(src: [u8; N]) -> String {
+    src.iter()
+        .take_while(|&&c| c != 0)
+        .map(|&c| c as char)
+        .collect()
+}
+
+pub(crate) fn c_char_bytes_to_string<const N: usize>(src: [c_char; N]) -> String {
+    u8_bytes_to_string(src.map(|c| c as u8))
+}
+
+pub(crate) fn string_to_c_char_bytes<const N: usize>(src: String) -> [c_char; N] {
     let mut result: [c_char; N] = [0; N];
 
     let bytes = src.as_bytes();
@@ -1,64 +1,659 @@
-use std::{net::IpAddr, sync::Arc};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+    net::IpAddr,
+    ops::RangeInclusive,
+    path::PathBuf,
+    sync::Arc,
+};
 
-use furui_common::{IcmpVersion, IpProtocol, TASK_COMM_LEN};
+use aya_ebpf::cty::c_char;
+use furui_common::{IcmpVersion, IpProtocol, TcAction, CONTAINER_ID_LEN, TASK_COMM_LEN};
+use regex::Regex;
 use tokio::sync::Mutex;
+use tracing::warn;
 
 use crate::{domain::container::Container, Containers};
 
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub struct Policies {
+    pub(crate) default_action: TcAction,
     pub(crate) policies: Vec<Policy>,
+    /// Names of the containers switched to audit mode through the control API, whatever their
+    /// policies say.
+    pub(crate) audit_containers: BTreeSet<String>,
+    pub(crate) audit_container_ids: Vec<String>,
 }
 
 impl Policies {
+    /// Resolves the containers each policy applies to and the addresses of the peer containers
+    /// its rules name, so it has to run again whenever a container starts or stops.
     pub async fn set_container_id(&mut self, containers: Arc<Mutex<Containers>>) {
-        let ids = containers.lock().await.ids();
+        let containers = containers.lock().await.list();
+
+        self.audit_container_ids = containers
+            .iter()
+            .filter(|container| {
+                self.audit_containers
+                    .contains(container.name.trim_start_matches("/"))
+            })
+            .filter_map(|container| container.id.clone())
+            .collect();
 
         for policy in &mut self.policies {
-            if policy.container.name.len() == 0 {
-                continue;
+            policy.container_ids = containers
+                .iter()
+                .filter(|container| policy.selector.matches(container))
+                .filter_map(|container| container.id.clone())
+                .collect();
+
+            for communication in &mut policy.communications {
+                for socket in &mut communication.sockets {
+                    if let Some(RemoteHost::Container(name)) = &socket.remote_host {
+                        socket.remote_ips = container_ips(&containers, name);
+                    }
+                }
+
+                for icmp in &mut communication.icmp {
+                    if let Some(RemoteHost::Container(name)) = &icmp.remote_host {
+                        icmp.remote_ips = container_ips(&containers, name);
+                    }
+                }
+            }
+        }
+
+        self.warn_overlaps(&containers);
+    }
+
+    /// Policies with different selectors can still select the same container, such as one by its
+    /// name and one by its labels. The rules of both apply to it, and the default action of the
+    /// one that comes last.
+    fn warn_overlaps(&self, containers: &[Container]) {
+        for container in containers {
+            let id = match &container.id {
+                Some(id) => id,
+                None => continue,
+            };
+
+            let selecting = self
+                .policies
+                .iter()
+                .filter(|policy| policy.container_ids.contains(id))
+                .collect::<Vec<_>>();
+
+            for (i, policy) in selecting.iter().enumerate() {
+                for other in &selecting[i + 1..] {
+                    warn!(
+                        "container {} is selected by {} in {} and by {} in {}, the rules of both \
+                         apply with the default_action of the latter",
+                        container.name.trim_start_matches('/'),
+                        policy.selector,
+                        policy.source(),
+                        other.selector,
+                        other.source()
+                    );
+                }
+            }
+        }
+    }
+
+    /// The executables named in the policies of a container.
+    pub fn executables(&self, container_id: &str) -> Vec<&Executable> {
+        self.policies
+            .iter()
+            .filter(|policy| policy.container_ids.iter().any(|id| id == container_id))
+            .flat_map(|policy| &policy.communications)
+            .filter_map(|communication| communication.executable.as_ref())
+            .collect()
+    }
+
+    /// The process trees named in the policies of a container.
+    pub fn process_trees(&self, container_id: &str) -> Vec<&ProcessTree> {
+        self.policies
+            .iter()
+            .filter(|policy| policy.container_ids.iter().any(|id| id == container_id))
+            .flat_map(|policy| &policy.communications)
+            .filter_map(|communication| communication.process_tree.as_ref())
+            .collect()
+    }
+
+    /// The cgroups named in the policies of a container.
+    pub fn cgroups(&self, container_id: &str) -> Vec<&Cgroup> {
+        self.policies
+            .iter()
+            .filter(|policy| policy.container_ids.iter().any(|id| id == container_id))
+            .flat_map(|policy| &policy.communications)
+            .filter_map(|communication| communication.cgroup.as_ref())
+            .collect()
+    }
+
+    /// The process a rule is saved under, with the names of executables, cgroups and process trees
+    /// found from the policies, as the maps only hold their hashes.
+    pub fn process_name(&self, comm: [u8; TASK_COMM_LEN]) -> String {
+        if comm[TASK_COMM_LEN - 1] & 0x80 == 0 {
+            return super::u8_bytes_to_string(comm);
+        }
+
+        for communication in self
+            .policies
+            .iter()
+            .flat_map(|policy| &policy.communications)
+        {
+            if let Some(executable) = &communication.executable {
+                if executable.comm() == comm {
+                    return format!("executable_path={}", executable.path);
+                }
             }
 
-            match ids.get(&policy.container.name) {
-                Some(id) => {
-                    policy.container.id = Some(id.to_string());
+            if let Some(cgroup) = &communication.cgroup {
+                if cgroup.comm() == comm {
+                    return format!("cgroup={}", cgroup.path);
+                }
+            }
+
+            if let Some(process_tree) = &communication.process_tree {
+                if process_tree.comm() == comm {
+                    return format!("process_tree={}", process_tree.path);
+                }
+            }
+        }
+
+        "(unknown rule)".to_string()
+    }
+
+    /// The host names the rules name, which have to be looked up again when their records expire.
+    /// Wildcards are left out, as only the DNS responses to the containers resolve them.
+    pub fn domains(&self) -> BTreeSet<String> {
+        let mut domains = BTreeSet::new();
+
+        for policy in &self.policies {
+            for communication in &policy.communications {
+                let remote_hosts = communication
+                    .sockets
+                    .iter()
+                    .map(|socket| &socket.remote_host)
+                    .chain(communication.icmp.iter().map(|icmp| &icmp.remote_host));
+
+                for remote_host in remote_hosts.flatten() {
+                    match remote_host {
+                        RemoteHost::Domain(domain) if !remote_host.is_wildcard() => {
+                            domains.insert(domain.clone());
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        domains
+    }
+
+    /// The addresses the host names were last looked up as.
+    pub fn domain_ips(&self) -> HashMap<String, Vec<IpAddr>> {
+        let mut domain_ips = HashMap::new();
+
+        for communication in self
+            .policies
+            .iter()
+            .flat_map(|policy| &policy.communications)
+        {
+            let remote_hosts = communication
+                .sockets
+                .iter()
+                .map(|socket| (&socket.remote_host, &socket.remote_ips))
+                .chain(
+                    communication
+                        .icmp
+                        .iter()
+                        .map(|icmp| (&icmp.remote_host, &icmp.remote_ips)),
+                );
+
+            for (remote_host, remote_ips) in remote_hosts {
+                if let Some(RemoteHost::Domain(domain)) = remote_host {
+                    domain_ips.insert(domain.clone(), remote_ips.clone());
+                }
+            }
+        }
+
+        domain_ips
+    }
+
+    /// Replaces the addresses of the looked up host names. Host names missing from `domain_ips`
+    /// keep their addresses.
+    pub fn set_domain_ips(&mut self, domain_ips: &HashMap<String, Vec<IpAddr>>) {
+        for policy in &mut self.policies {
+            for communication in &mut policy.communications {
+                for socket in &mut communication.sockets {
+                    if let Some(RemoteHost::Domain(domain)) = &socket.remote_host {
+                        if let Some(ips) = domain_ips.get(domain) {
+                            socket.remote_ips = ips.clone();
+                        }
+                    }
                 }
-                None => {
-                    policy.container.id = None;
+
+                for icmp in &mut communication.icmp {
+                    if let Some(RemoteHost::Domain(domain)) = &icmp.remote_host {
+                        if let Some(ips) = domain_ips.get(domain) {
+                            icmp.remote_ips = ips.clone();
+                        }
+                    }
                 }
             }
         }
     }
 }
 
+fn container_ips(containers: &[Container], name: &str) -> Vec<IpAddr> {
+    containers
+        .iter()
+        .filter(|container| container.name.trim_start_matches("/") == name)
+        .flat_map(|container| container.ip_addresses.clone().unwrap_or_default())
+        .collect()
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Policy {
-    pub(crate) container: Container,
+    pub(crate) selector: ContainerSelector,
+    pub(crate) container_ids: Vec<String>,
+    pub(crate) audit: bool,
+    pub(crate) default_action: TcAction,
     pub(crate) communications: Vec<Communication>,
+    /// The file the policy comes from, or `None` for a policy set through the control API.
+    pub(crate) file: Option<PathBuf>,
+}
+
+impl Policy {
+    fn source(&self) -> String {
+        match &self.file {
+            Some(file) => file.display().to_string(),
+            None => "the control API".to_string(),
+        }
+    }
+}
+
+impl Policy {
+    pub fn container_ids(&self) -> Vec<[c_char; CONTAINER_ID_LEN]> {
+        self.container_ids
+            .iter()
+            .map(|id| super::string_to_c_char_bytes(id.clone()))
+            .collect()
+    }
+}
+
+/// Selects the containers a policy applies to. Every given condition has to match.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ContainerSelector {
+    pub(crate) name: Option<String>,
+    pub(crate) name_regex: Option<NameRegex>,
+    pub(crate) labels: BTreeMap<String, String>,
+    pub(crate) image: Option<String>,
+}
+
+impl ContainerSelector {
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none()
+            && self.name_regex.is_none()
+            && self.labels.is_empty()
+            && self.image.is_none()
+    }
+
+    pub fn matches(&self, container: &Container) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+
+        let container_name = container.name.trim_start_matches("/");
+
+        if let Some(name) = &self.name {
+            if !glob_matches(name, container_name) {
+                return false;
+            }
+        }
+
+        if let Some(name_regex) = &self.name_regex {
+            if !name_regex.is_match(container_name) {
+                return false;
+            }
+        }
+
+        if let Some(image) = &self.image {
+            if !image_matches(&container.image, image) {
+                return false;
+            }
+        }
+
+        self.labels
+            .iter()
+            .all(|(key, value)| container.labels.get(key) == Some(value))
+    }
+}
+
+impl fmt::Display for ContainerSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut conditions = vec![];
+
+        if let Some(name) = &self.name {
+            conditions.push(name.clone());
+        }
+        if let Some(name_regex) = &self.name_regex {
+            conditions.push(format!("name_regex={}", name_regex));
+        }
+        if let Some(image) = &self.image {
+            conditions.push(format!("image={}", image));
+        }
+        for (key, value) in &self.labels {
+            conditions.push(format!("{}={}", key, value));
+        }
+
+        write!(f, "{}", conditions.join(","))
+    }
+}
+
+/// A `name_regex` compiled once when the policy is loaded, which has to match the whole container
+/// name. A pattern that does not compile, which the validation of the policies rejects, matches no
+/// container.
+#[derive(Debug, Clone)]
+pub struct NameRegex {
+    pattern: String,
+    regex: Option<Regex>,
+}
+
+impl NameRegex {
+    pub fn new(pattern: &str) -> NameRegex {
+        NameRegex {
+            pattern: pattern.to_string(),
+            regex: NameRegex::compile(pattern).ok(),
+        }
+    }
+
+    pub fn compile(pattern: &str) -> Result<Regex, regex::Error> {
+        Regex::new(&format!("^(?:{})$", pattern))
+    }
+
+    pub fn is_match(&self, name: &str) -> bool {
+        self.regex
+            .as_ref()
+            .map_or(false, |regex| regex.is_match(name))
+    }
+}
+
+impl PartialEq for NameRegex {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern == other.pattern
+    }
+}
+
+impl Eq for NameRegex {}
+
+impl fmt::Display for NameRegex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.pattern)
+    }
+}
+
+/// `*` matches any characters and `?` a single one, so a name without them has to be equal.
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            // Let the last `*` take one more character and retry.
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// `nginx` matches `nginx`, `nginx:1.25` and `docker.io/library/nginx:1.25`, but not an image of
+/// the same name from another registry or namespace. A tag or a digest in the selector has to
+/// match as well.
+fn image_matches(container_image: &str, image: &str) -> bool {
+    if container_image == image {
+        return true;
+    }
+
+    let container_image = ImageReference::parse(container_image);
+    let image = ImageReference::parse(image);
+
+    if container_image.name != image.name {
+        return false;
+    }
+    if image.tag.is_some() && container_image.tag() != image.tag() {
+        return false;
+    }
+
+    image.digest.is_none() || container_image.digest == image.digest
+}
+
+/// An image reference with the registry and namespace Docker assumes filled in, so `nginx` is
+/// named `docker.io/library/nginx` and `bitnami/nginx` is named `docker.io/bitnami/nginx`.
+#[derive(Debug, PartialEq, Eq)]
+struct ImageReference {
+    name: String,
+    tag: Option<String>,
+    digest: Option<String>,
+}
+
+impl ImageReference {
+    fn parse(reference: &str) -> ImageReference {
+        let (reference, digest) = match reference.split_once('@') {
+            Some((reference, digest)) => (reference, Some(digest.to_string())),
+            None => (reference, None),
+        };
+        let (name, tag) = match reference.rsplit_once(':') {
+            Some((name, tag)) if !tag.contains('/') => (name, Some(tag.to_string())),
+            _ => (reference, None),
+        };
+
+        // Like Docker, the first component is only a registry when it looks like a host.
+        let name = match name.split_once('/') {
+            Some((registry, path))
+                if registry.contains('.') || registry.contains(':') || registry == "localhost" =>
+            {
+                match registry {
+                    "index.docker.io" | "registry-1.docker.io" => Self::docker_hub_name(path),
+                    _ => name.to_string(),
+                }
+            }
+            _ => Self::docker_hub_name(name),
+        };
+
+        ImageReference { name, tag, digest }
+    }
+
+    fn docker_hub_name(path: &str) -> String {
+        if path.contains('/') {
+            format!("docker.io/{}", path)
+        } else {
+            format!("docker.io/library/{}", path)
+        }
+    }
+
+    /// An image pulled without a tag and without a digest is `latest`.
+    fn tag(&self) -> Option<&str> {
+        match (&self.tag, &self.digest) {
+            (Some(tag), _) => Some(tag),
+            (None, None) => Some("latest"),
+            (None, Some(_)) => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Communication {
     pub(crate) process: Option<String>,
+    pub(crate) executable: Option<Executable>,
+    pub(crate) cgroup: Option<Cgroup>,
+    pub(crate) process_tree: Option<ProcessTree>,
     pub(crate) sockets: Vec<Socket>,
     pub(crate) icmp: Vec<ICMP>,
 }
 
 impl Communication {
+    /// The name the rules are saved under, which is the name of the executable, the cgroup or the
+    /// process tree when one is given.
     pub fn process(&self) -> [u8; TASK_COMM_LEN] {
+        if let Some(executable) = &self.executable {
+            return executable.comm();
+        }
+
+        if let Some(cgroup) = &self.cgroup {
+            return cgroup.comm();
+        }
+
+        if let Some(process_tree) = &self.process_tree {
+            return process_tree.comm();
+        }
+
         match self.process.as_ref() {
             Some(process) => super::string_to_u8_bytes((*process).clone()),
             None => [0; TASK_COMM_LEN],
         }
     }
+
+    /// The sockets with one per address of their remote host. A container that is not running
+    /// or a host name that did not resolve has no address, and then its rules are left out
+    /// instead of allowing any host.
+    pub fn resolved_sockets(&self) -> Vec<Socket> {
+        let mut sockets = vec![];
+
+        for socket in &self.sockets {
+            if socket.remote_host.is_none() {
+                sockets.push(socket.clone());
+                continue;
+            }
+
+            for remote_ip in &socket.remote_ips {
+                sockets.push(Socket {
+                    remote_ip: Some(*remote_ip),
+                    ..socket.clone()
+                });
+            }
+        }
+
+        sockets
+    }
+
+    /// Same as `resolved_sockets` for ICMP.
+    pub fn resolved_icmp(&self) -> Vec<ICMP> {
+        let mut resolved = vec![];
+
+        for icmp in &self.icmp {
+            if icmp.remote_host.is_none() {
+                resolved.push(icmp.clone());
+                continue;
+            }
+
+            for remote_ip in &icmp.remote_ips {
+                resolved.push(ICMP {
+                    remote_ip: Some(*remote_ip),
+                    ..icmp.clone()
+                });
+            }
+        }
+
+        resolved
+    }
+}
+
+/// An executable named by its path in the container, and optionally the hash of its contents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Executable {
+    pub(crate) path: String,
+    pub(crate) sha256: Option<String>,
+}
+
+impl Executable {
+    pub fn comm(&self) -> [u8; TASK_COMM_LEN] {
+        rule_comm(format!(
+            "{}\0{}",
+            self.path,
+            self.sha256.as_deref().unwrap_or_default()
+        ))
+    }
+}
+
+/// A cgroup named by its path below the cgroup of the container. It covers the cgroups below it
+/// too, and so every process started in it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cgroup {
+    pub(crate) path: String,
+}
+
+impl Cgroup {
+    /// The path of an executable is absolute, so the name cannot be one of an executable.
+    pub fn comm(&self) -> [u8; TASK_COMM_LEN] {
+        rule_comm(format!("cgroup\0{}", self.path))
+    }
+
+    /// Whether the cgroup at `path`, relative to the cgroup of the container, is this one or one
+    /// below it.
+    pub fn matches(&self, path: &str) -> bool {
+        let rule = self.path.trim_end_matches('/');
+
+        match path.strip_prefix(rule) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/') || rule.is_empty(),
+            None => false,
+        }
+    }
+}
+
+/// The processes running an executable, named by its path in the container, and all their
+/// descendants.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessTree {
+    pub(crate) path: String,
+}
+
+impl ProcessTree {
+    pub fn comm(&self) -> [u8; TASK_COMM_LEN] {
+        rule_comm(format!("process_tree\0{}", self.path))
+    }
+}
+
+/// Uses all TASK_COMM_LEN bytes, where a process name always ends with a NUL, so that no process
+/// can take the name of a rule by renaming itself.
+fn rule_comm(name: String) -> [u8; TASK_COMM_LEN] {
+    let mut comm: [u8; TASK_COMM_LEN] = md5::compute(name).0;
+
+    comm[TASK_COMM_LEN - 1] |= 0x80;
+
+    comm
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Socket {
     pub(crate) protocol: IpProtocol,
     pub(crate) local_port: Option<u16>,
+    pub(crate) local_port_range: Option<RangeInclusive<u16>>,
     pub(crate) remote_ip: Option<IpAddr>,
+    pub(crate) remote_prefix_len: Option<u8>,
     pub(crate) remote_port: Option<u16>,
+    pub(crate) remote_port_range: Option<RangeInclusive<u16>>,
+    pub(crate) remote_host: Option<RemoteHost>,
+    pub(crate) remote_ips: Vec<IpAddr>,
+    pub(crate) action: TcAction,
+}
+
+impl Socket {
+    pub fn has_port_range(&self) -> bool {
+        self.local_port_range.is_some() || self.remote_port_range.is_some()
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -67,4 +662,114 @@ pub struct ICMP {
     pub(crate) type_: u8,
     pub(crate) code: Option<u8>,
     pub(crate) remote_ip: Option<IpAddr>,
+    pub(crate) remote_prefix_len: Option<u8>,
+    pub(crate) remote_host: Option<RemoteHost>,
+    pub(crate) remote_ips: Vec<IpAddr>,
+    pub(crate) action: TcAction,
+}
+
+/// A `remote_host` that is a name rather than an address, so its addresses change while furui is
+/// running.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RemoteHost {
+    /// Resolved whenever containers start or stop.
+    Container(String),
+    /// Looked up again whenever its DNS records expire.
+    Domain(String),
+}
+
+impl RemoteHost {
+    /// A domain such as `*.example.com`, matching every name below `example.com`.
+    pub fn is_wildcard(&self) -> bool {
+        matches!(self, RemoteHost::Domain(domain) if domain.starts_with("*."))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_matches_stars_and_question_marks() {
+        assert!(glob_matches("web", "web"));
+        assert!(!glob_matches("web", "web-1"));
+        assert!(glob_matches("web-*", "web-1"));
+        assert!(glob_matches("web-*", "web-"));
+        assert!(glob_matches("*-db-*", "app-db-primary"));
+        assert!(!glob_matches("*-db-*", "app-cache-primary"));
+        assert!(glob_matches("web-?", "web-1"));
+        assert!(!glob_matches("web-?", "web-10"));
+        assert!(glob_matches("*a*b", "xaxxab"));
+        assert!(glob_matches("*", ""));
+        assert!(!glob_matches("?", ""));
+    }
+
+    #[test]
+    fn name_regex_matches_the_whole_name() {
+        let name_regex = NameRegex::new("web-[0-9]+");
+
+        assert!(name_regex.is_match("web-1"));
+        assert!(name_regex.is_match("web-10"));
+        assert!(!name_regex.is_match("web-1a"));
+        assert!(!name_regex.is_match("my-web-1"));
+        assert!(!NameRegex::new("web-(").is_match("web-("));
+    }
+
+    #[test]
+    fn image_matches_without_tag_or_registry() {
+        assert!(image_matches("nginx", "nginx"));
+        assert!(image_matches("nginx:1.25", "nginx"));
+        assert!(image_matches("docker.io/library/nginx:1.25", "nginx"));
+        assert!(image_matches(
+            "docker.io/library/nginx@sha256:abcd",
+            "nginx"
+        ));
+        assert!(!image_matches("nginx-proxy:1.0", "nginx"));
+        assert!(!image_matches("mynginx", "nginx"));
+    }
+
+    #[test]
+    fn image_does_not_match_a_foreign_registry_or_namespace() {
+        assert!(!image_matches("attacker.example/nginx", "nginx"));
+        assert!(!image_matches(
+            "attacker.example/library/nginx:1.25",
+            "nginx"
+        ));
+        assert!(!image_matches("localhost:5000/nginx", "nginx"));
+        assert!(!image_matches("evil/nginx", "nginx"));
+        assert!(!image_matches("docker.io/evil/nginx:1.25", "nginx"));
+        assert!(!image_matches("docker.io/library/nginx", "bitnami/nginx"));
+        assert!(image_matches(
+            "bitnami/nginx:1.25",
+            "docker.io/bitnami/nginx"
+        ));
+        assert!(image_matches(
+            "attacker.example/nginx:1.25",
+            "attacker.example/nginx"
+        ));
+    }
+
+    #[test]
+    fn image_matches_the_tag_and_registry_given() {
+        assert!(image_matches("nginx:1.25", "nginx:1.25"));
+        assert!(!image_matches("nginx:1.24", "nginx:1.25"));
+        assert!(image_matches(
+            "docker.io/library/nginx:1.25",
+            "library/nginx:1.25"
+        ));
+        assert!(image_matches(
+            "docker.io/library/nginx:1.25",
+            "docker.io/library/nginx"
+        ));
+        assert!(!image_matches(
+            "ghcr.io/library/nginx:1.25",
+            "docker.io/library/nginx"
+        ));
+        assert!(image_matches("nginx", "nginx:latest"));
+        assert!(image_matches(
+            "nginx@sha256:abcd",
+            "docker.io/library/nginx@sha256:abcd"
+        ));
+        assert!(!image_matches("nginx@sha256:abcd", "nginx@sha256:ef01"));
+    }
 }
@@ -1,5 +1,7 @@
 use aya_ebpf::cty::c_char;
-use furui_common::{IpProtocol, CONTAINER_ID_LEN, TASK_COMM_LEN};
+use furui_common::{
+    BindEvent, Connect6Event, ConnectEvent, IpProtocol, CONTAINER_ID_LEN, TASK_COMM_LEN,
+};
 
 #[derive(Debug, Clone, Default)]
 pub struct Process {
@@ -8,6 +10,9 @@ pub struct Process {
     pub protocol: IpProtocol,
     pub port: u16,
     pub pid: u32,
+    pub exe_ino: u64,
+    pub exe_dev: u32,
+    pub cgroup_id: u64,
 }
 
 impl Process {
@@ -19,3 +24,40 @@ impl Process {
         super::string_to_u8_bytes(self.executable.clone())
     }
 }
+
+impl From<&BindEvent> for Process {
+    fn from(event: &BindEvent) -> Self {
+        Process {
+            container_id: event.container_id(),
+            executable: event.comm(),
+            protocol: event.protocol,
+            port: event.lport,
+            pid: event.pid,
+            exe_ino: event.exe_ino,
+            exe_dev: event.exe_dev,
+            cgroup_id: event.cgroup_id,
+        }
+    }
+}
+
+macro_rules! impl_from_connect_event {
+    ($event:ty) => {
+        impl From<&$event> for Process {
+            fn from(event: &$event) -> Self {
+                Process {
+                    container_id: event.container_id(),
+                    executable: event.comm(),
+                    protocol: event.protocol,
+                    port: event.src_port,
+                    pid: event.pid,
+                    exe_ino: event.exe_ino,
+                    exe_dev: event.exe_dev,
+                    cgroup_id: event.cgroup_id,
+                }
+            }
+        }
+    };
+}
+
+impl_from_connect_event!(ConnectEvent);
+impl_from_connect_event!(Connect6Event);
@@ -0,0 +1,537 @@
+use std::{fs, path::PathBuf, process};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use furui::control::{
+    self,
+    proto::{
+        AddPolicyRequest, CheckRequest, Event, ListContainersRequest, ListMapEntriesRequest,
+        ListPoliciesRequest, ListProcessesRequest, RemovePolicyRequest, SetAuditRequest,
+        WatchEventsRequest,
+    },
+};
+
+/// Inspects and controls a running furui through its control API.
+#[derive(Debug, Parser)]
+struct Options {
+    /// The Unix socket of the control API.
+    #[arg(long, default_value = "/run/furui/furui.sock")]
+    socket: PathBuf,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// List the policies in effect and the containers they apply to.
+    Policies,
+    /// List the containers furui knows of.
+    Containers,
+    /// List the processes seen binding or connecting.
+    Processes,
+    /// Print the entries of POLICY_LIST, PROC_PORTS and CONTAINER_ID_FROM_IPS.
+    Maps,
+    /// Tell what would be done with a TCP or UDP packet of a container.
+    Check(CheckOptions),
+    /// Print the packets and the sockets seen from now on, one per line.
+    Events(EventsOptions),
+    /// Set the policy of a container, in place of the one the policy files have for it.
+    AddPolicy {
+        /// The name of the container.
+        container: String,
+        /// A policy in YAML or JSON, without its container selector.
+        file: PathBuf,
+    },
+    /// Remove the policy set by add-policy.
+    RemovePolicy {
+        /// The name of the container.
+        container: String,
+    },
+    /// Only log the packets of a container that would be dropped.
+    Audit {
+        /// The name of the container.
+        container: String,
+        /// Enforce the policies of the container again.
+        #[arg(long)]
+        off: bool,
+    },
+}
+
+#[derive(Debug, clap::Args)]
+struct CheckOptions {
+    /// The name or the ID of the container.
+    container: String,
+
+    #[arg(long, value_enum)]
+    direction: Direction,
+
+    #[arg(long, value_enum, default_value = "tcp")]
+    protocol: Protocol,
+
+    /// The port in the container.
+    #[arg(long)]
+    local_port: u16,
+
+    #[arg(long)]
+    remote_ip: String,
+
+    #[arg(long)]
+    remote_port: u16,
+}
+
+/// Each option may be repeated, and any of its values passes.
+#[derive(Debug, clap::Args)]
+struct EventsOptions {
+    /// A name or an ID of a container.
+    #[arg(long = "container", short = 'c')]
+    containers: Vec<String>,
+
+    /// pass, drop or would-drop.
+    #[arg(long = "action")]
+    actions: Vec<String>,
+
+    /// tcp, udp or icmp.
+    #[arg(long = "protocol")]
+    protocols: Vec<String>,
+
+    /// ingress, egress, bind or connect.
+    #[arg(long = "event")]
+    events: Vec<String>,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum Direction {
+    Ingress,
+    Egress,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum Protocol {
+    Tcp,
+    Udp,
+}
+
+#[tokio::main]
+async fn main() {
+    let opt = Options::parse();
+
+    if let Err(err) = run(opt).await {
+        println!("{}", err);
+        process::exit(1);
+    }
+}
+
+async fn run(opt: Options) -> anyhow::Result<()> {
+    let mut client = control::connect(opt.socket).await?;
+
+    match opt.command {
+        Command::Policies => {
+            let policies = client
+                .list_policies(ListPoliciesRequest {})
+                .await?
+                .into_inner()
+                .policies;
+
+            for policy in policies {
+                let added = if policy.added { " (added)" } else { "" };
+
+                println!("# {}{}", policy.selector, added);
+                println!("# containers: {}", policy.container_ids.join(", "));
+                println!("{}", policy.policy);
+            }
+        }
+        Command::Containers => {
+            let containers = client
+                .list_containers(ListContainersRequest {})
+                .await?
+                .into_inner()
+                .containers;
+
+            print_table(
+                &["ID", "NAME", "PID", "IMAGE", "IP ADDRESSES", "AUDIT"],
+                containers
+                    .into_iter()
+                    .map(|container| {
+                        vec![
+                            container.id,
+                            container.name.trim_start_matches("/").to_string(),
+                            container.pid.to_string(),
+                            container.image,
+                            container.ip_addresses.join(","),
+                            container.audit.to_string(),
+                        ]
+                    })
+                    .collect(),
+            );
+        }
+        Command::Processes => {
+            let processes = client
+                .list_processes(ListProcessesRequest {})
+                .await?
+                .into_inner()
+                .processes;
+
+            print_table(
+                &["PID", "CONTAINER ID", "PORT", "PROTOCOL"],
+                processes
+                    .into_iter()
+                    .map(|process| {
+                        vec![
+                            process.pid.to_string(),
+                            process.container_id,
+                            process.port.to_string(),
+                            process.protocol,
+                        ]
+                    })
+                    .collect(),
+            );
+        }
+        Command::Maps => {
+            let entries = client
+                .list_map_entries(ListMapEntriesRequest {})
+                .await?
+                .into_inner();
+
+            println!("POLICY_LIST");
+            print_table(
+                &[
+                    "CONTAINER",
+                    "PROCESS",
+                    "PROTOCOL",
+                    "LOCAL PORT",
+                    "REMOTE IP",
+                    "REMOTE PORT",
+                    "ACTION",
+                ],
+                entries
+                    .policies
+                    .into_iter()
+                    .map(|entry| {
+                        vec![
+                            container(&entry.container_id, &entry.container_name),
+                            or_any(entry.process),
+                            or_any(entry.protocol),
+                            or_any_port(entry.local_port),
+                            or_any(entry.remote_ip),
+                            or_any_port(entry.remote_port),
+                            entry.action,
+                        ]
+                    })
+                    .collect(),
+            );
+
+            println!();
+            println!("PROC_PORTS");
+            print_table(
+                &[
+                    "CONTAINER",
+                    "PORT",
+                    "PROTOCOL",
+                    "PROCESS",
+                    "EXE INODE",
+                    "EXE DEVICE",
+                    "CGROUP ID",
+                ],
+                entries
+                    .ports
+                    .into_iter()
+                    .map(|entry| {
+                        vec![
+                            container(&entry.container_id, &entry.container_name),
+                            entry.port.to_string(),
+                            entry.protocol,
+                            entry.process,
+                            entry.exe_ino.to_string(),
+                            entry.exe_dev.to_string(),
+                            entry.cgroup_id.to_string(),
+                        ]
+                    })
+                    .collect(),
+            );
+
+            println!();
+            println!("CONTAINER_ID_FROM_IPS");
+            print_table(
+                &["IP", "CONTAINER"],
+                entries
+                    .container_ips
+                    .into_iter()
+                    .map(|entry| {
+                        vec![
+                            entry.ip,
+                            container(&entry.container_id, &entry.container_name),
+                        ]
+                    })
+                    .collect(),
+            );
+        }
+        Command::Check(check_opt) => {
+            let response = client
+                .check(CheckRequest {
+                    container: check_opt.container,
+                    direction: match check_opt.direction {
+                        Direction::Ingress => "ingress",
+                        Direction::Egress => "egress",
+                    }
+                    .to_string(),
+                    protocol: match check_opt.protocol {
+                        Protocol::Tcp => "tcp",
+                        Protocol::Udp => "udp",
+                    }
+                    .to_string(),
+                    local_port: check_opt.local_port as u32,
+                    remote_ip: check_opt.remote_ip,
+                    remote_port: check_opt.remote_port as u32,
+                })
+                .await?
+                .into_inner();
+
+            let action = if response.would_drop {
+                "would-drop".to_string()
+            } else {
+                response.action
+            };
+
+            println!("{} by {}", action, response.decided_by);
+            if !response.process.is_empty() {
+                println!("process: {}", response.process);
+            }
+        }
+        Command::Events(events_opt) => {
+            let mut events = client
+                .watch_events(WatchEventsRequest {
+                    containers: events_opt.containers,
+                    actions: events_opt.actions,
+                    protocols: events_opt.protocols,
+                    events: events_opt.events,
+                })
+                .await?
+                .into_inner();
+
+            while let Some(event) = events.message().await? {
+                println!("{}", event_line(event));
+            }
+        }
+        Command::AddPolicy { container, file } => {
+            let policy = fs::read_to_string(&file)
+                .map_err(|err| anyhow::anyhow!("{}: {}", file.display(), err))?;
+
+            client
+                .add_policy(AddPolicyRequest { container, policy })
+                .await?;
+        }
+        Command::RemovePolicy { container } => {
+            client
+                .remove_policy(RemovePolicyRequest { container })
+                .await?;
+        }
+        Command::Audit { container, off } => {
+            client
+                .set_audit(SetAuditRequest {
+                    container,
+                    audit: !off,
+                })
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// An event on one line, leaving out the fields it does not have.
+fn event_line(event: Event) -> String {
+    let source = address(&event.source_addr, event.source_port);
+    let destination = address(&event.destination_addr, event.destination_port);
+
+    let mut line = vec![
+        event.event,
+        event.action,
+        container(&event.container_id, &event.container_name),
+        event.comm,
+        event.protocol,
+        format!("{} -> {}", source, destination),
+    ];
+    if !event.icmp_version.is_empty() {
+        line.push(format!(
+            "{} type={} code={}",
+            event.icmp_version, event.icmp_type, event.icmp_code
+        ));
+    }
+    if event.pid != 0 {
+        line.push(format!("pid={}", event.pid));
+    }
+
+    line.retain(|field| !field.is_empty());
+    line.join(" ")
+}
+
+fn container(id: &str, name: &str) -> String {
+    if name.is_empty() {
+        id.to_string()
+    } else {
+        format!("{} ({})", name, id)
+    }
+}
+
+fn address(addr: &str, port: u32) -> String {
+    match (addr.contains(':'), port) {
+        (_, 0) => addr.to_string(),
+        (true, port) => format!("[{}]:{}", addr, port),
+        (false, port) => format!("{}:{}", addr, port),
+    }
+}
+
+fn or_any(value: String) -> String {
+    if value.is_empty() {
+        "*".to_string()
+    } else {
+        value
+    }
+}
+
+fn or_any_port(port: u32) -> String {
+    if port == 0 {
+        "*".to_string()
+    } else {
+        port.to_string()
+    }
+}
+
+fn print_table(headers: &[&str], rows: Vec<Vec<String>>) {
+    print!("{}", table(headers, rows));
+}
+
+/// Lines up the cells of each column, with two spaces between the columns.
+fn table(headers: &[&str], rows: Vec<Vec<String>>) -> String {
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let line = |cells: Vec<&str>| {
+        let line = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        format!("{}\n", line.trim_end())
+    };
+
+    let mut table = line(headers.to_vec());
+    for row in &rows {
+        table.push_str(&line(row.iter().map(|cell| cell.as_str()).collect()));
+    }
+
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_lines_up_the_columns() {
+        let table = table(
+            &["PID", "CONTAINER ID", "PORT"],
+            vec![
+                vec![
+                    "7".to_string(),
+                    "3f4e8a9b2c1d".to_string(),
+                    "80".to_string(),
+                ],
+                vec!["12345".to_string(), "db".to_string(), "5432".to_string()],
+            ],
+        );
+
+        assert_eq!(
+            table,
+            "PID    CONTAINER ID  PORT\n\
+             7      3f4e8a9b2c1d  80\n\
+             12345  db            5432\n"
+        );
+    }
+
+    #[test]
+    fn address_puts_ipv6_in_brackets() {
+        assert_eq!(address("10.0.0.1", 80), "10.0.0.1:80");
+        assert_eq!(address("fd00::1", 80), "[fd00::1]:80");
+        assert_eq!(address("fd00::1", 0), "fd00::1");
+        assert_eq!(address("10.0.0.1", 0), "10.0.0.1");
+    }
+
+    #[test]
+    fn unset_fields_match_anything() {
+        assert_eq!(or_any("".to_string()), "*");
+        assert_eq!(or_any("tcp".to_string()), "tcp");
+        assert_eq!(or_any_port(0), "*");
+        assert_eq!(or_any_port(443), "443");
+        assert_eq!(container("3f4e8a9b2c1d", ""), "3f4e8a9b2c1d");
+        assert_eq!(container("3f4e8a9b2c1d", "web"), "web (3f4e8a9b2c1d)");
+    }
+
+    #[test]
+    fn event_line_leaves_out_the_missing_fields() {
+        let event = Event {
+            event: "egress".to_string(),
+            action: "drop".to_string(),
+            container_id: "3f4e8a9b2c1d".to_string(),
+            container_name: "web".to_string(),
+            comm: "curl".to_string(),
+            protocol: "tcp".to_string(),
+            source_addr: "172.17.0.2".to_string(),
+            source_port: 40000,
+            destination_addr: "93.184.216.34".to_string(),
+            destination_port: 443,
+            ..Default::default()
+        };
+        assert_eq!(
+            event_line(event),
+            "egress drop web (3f4e8a9b2c1d) curl tcp 172.17.0.2:40000 -> 93.184.216.34:443"
+        );
+
+        let event = Event {
+            event: "ingress".to_string(),
+            action: "pass".to_string(),
+            container_id: "3f4e8a9b2c1d".to_string(),
+            protocol: "icmp".to_string(),
+            source_addr: "fd00::2".to_string(),
+            destination_addr: "fd00::1".to_string(),
+            icmp_version: "v6".to_string(),
+            icmp_type: 128,
+            pid: 0,
+            ..Default::default()
+        };
+        assert_eq!(
+            event_line(event),
+            "ingress pass 3f4e8a9b2c1d icmp fd00::2 -> fd00::1 v6 type=128 code=0"
+        );
+    }
+
+    #[test]
+    fn options_parse_the_subcommands() {
+        let opt = Options::try_parse_from(["furuictl", "audit", "web", "--off"]).unwrap();
+        assert!(matches!(
+            opt.command,
+            Command::Audit { ref container, off: true } if container == "web"
+        ));
+        assert_eq!(opt.socket, PathBuf::from("/run/furui/furui.sock"));
+
+        assert!(Options::try_parse_from([
+            "furuictl",
+            "check",
+            "web",
+            "--direction",
+            "sideways",
+            "--local-port",
+            "80",
+            "--remote-ip",
+            "10.0.0.1",
+            "--remote-port",
+            "40000",
+        ])
+        .is_err());
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use hickory_resolver::TokioAsyncResolver;
+use tokio::{sync::Mutex, task, time};
+use tracing::{info, warn};
+
+use crate::{domain::Policies, Maps};
+
+/// Records are not looked up more often than this, however short their TTL is.
+const MIN_TTL: Duration = Duration::from_secs(5);
+/// Records are looked up at least this often, however long their TTL is.
+const MAX_TTL: Duration = Duration::from_secs(300);
+/// How long to wait before looking up a host that failed to resolve again. It keeps its previous
+/// addresses until then.
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Looks up every host name in the policies once, before the policies are loaded into the maps.
+/// A host that fails to resolve is left to `dns_events`.
+pub async fn lookup_domains(policies: &Mutex<Policies>) -> anyhow::Result<()> {
+    let resolver = TokioAsyncResolver::tokio_from_system_conf()?;
+
+    let domains = policies.lock().await.domains();
+    let mut domain_ips = HashMap::new();
+
+    for domain in domains {
+        match resolver.lookup_ip(domain.as_str()).await {
+            Ok(lookup) => {
+                let mut ips = lookup.iter().collect::<Vec<_>>();
+                ips.sort();
+                ips.dedup();
+
+                domain_ips.insert(domain, ips);
+            }
+            Err(err) => warn!("failed to look up host: {} err: {}", domain, err),
+        }
+    }
+
+    policies.lock().await.set_domain_ips(&domain_ips);
+
+    Ok(())
+}
+
+/// Looks up the host names in the policies again when their records expire, and writes only the
+/// entries whose addresses changed.
+pub fn dns_events(maps: Arc<Maps>, policies: Arc<Mutex<Policies>>) -> anyhow::Result<()> {
+    let resolver = TokioAsyncResolver::tokio_from_system_conf()?;
+
+    task::spawn(async move {
+        let mut expires_at: HashMap<String, Instant> = HashMap::new();
+
+        loop {
+            time::sleep(Duration::from_secs(1)).await;
+
+            let domains = policies.lock().await.domains();
+            expires_at.retain(|domain, _| domains.contains(domain));
+
+            let mut domain_ips = HashMap::new();
+
+            for domain in domains {
+                let now = Instant::now();
+
+                if matches!(expires_at.get(&domain), Some(expires_at) if *expires_at > now) {
+                    continue;
+                }
+
+                match resolver.lookup_ip(domain.as_str()).await {
+                    Ok(lookup) => {
+                        let ttl = lookup
+                            .valid_until()
+                            .saturating_duration_since(now)
+                            .clamp(MIN_TTL, MAX_TTL);
+                        expires_at.insert(domain.clone(), now + ttl);
+
+                        let mut ips = lookup.iter().collect::<Vec<_>>();
+                        ips.sort();
+                        ips.dedup();
+
+                        domain_ips.insert(domain, ips);
+                    }
+                    Err(err) => {
+                        warn!("failed to look up host: {} err: {}", domain, err);
+                        expires_at.insert(domain, now + RETRY_INTERVAL);
+                    }
+                }
+            }
+
+            if domain_ips.is_empty() {
+                continue;
+            }
+
+            // The policies stay locked until the maps are written, so a reload in between cannot
+            // be overwritten with the addresses of the old policies.
+            let mut policies = policies.lock().await;
+
+            // The new addresses are kept only once they are in the maps, so the next diff is taken
+            // against what was actually written.
+            let mut new_policies = policies.clone();
+            new_policies.set_domain_ips(&domain_ips);
+
+            if new_policies == *policies {
+                continue;
+            }
+
+            match maps.policy.update(&policies, &new_policies).await {
+                Ok(_) => {
+                    *policies = new_policies;
+                    info!("host addresses updated.");
+                }
+                Err(err) => {
+                    warn!("failed to update host addresses: {}", err);
+
+                    let retry_at = Instant::now() + RETRY_INTERVAL;
+                    for domain in domain_ips.into_keys() {
+                        expires_at.insert(domain, retry_at);
+                    }
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
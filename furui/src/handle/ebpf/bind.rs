@@ -1,42 +1,25 @@
-use std::sync::Arc;
-
-use aya::Ebpf;
 use furui_common::BindEvent;
-use tokio::sync::Mutex;
-use tracing::info;
-
-use crate::handle::ebpf::{handle_perf_array, PidProcesses};
-
-pub async fn bind(
-    bpf: Arc<Mutex<Ebpf>>,
-    pid_processes: Arc<Mutex<PidProcesses>>,
-) -> anyhow::Result<()> {
-    let args = Arc::new(Mutex::new(pid_processes));
+use tracing::{info, warn};
 
-    handle_perf_array(
-        bpf,
-        "BIND_EVENTS",
-        args,
-        |event: BindEvent, args| async move {
-            let arg = args.lock().await;
-            let mut pid_processes = arg.lock().await;
+use crate::{
+    domain::{Event, Process},
+    handle::ebpf::Args,
+};
 
-            unsafe {
-                pid_processes.add(event.pid, event.container_id(), event.lport, event.protocol);
-            }
+pub(super) async fn bind(args: &Args, event: &BindEvent) {
+    args.save_process(&Process::from(event))
+        .await
+        .unwrap_or_else(|e| warn!("failed to save the process: {}", e));
 
-            info!(
-                event = "bind",
-                container_id = event.container_id().as_str(),
-                pid = event.pid,
-                comm = event.comm().as_str(),
-                family = event.family.to_string(),
-                protocol = event.protocol.to_string(),
-                lport = event.lport,
-            );
-        },
-    )
-    .await?;
+    let _ = args.events.send(Event::from(event));
 
-    Ok(())
+    info!(
+        event = "bind",
+        container_id = event.container_id().as_str(),
+        pid = event.pid,
+        comm = event.comm().as_str(),
+        family = event.family.to_string(),
+        protocol = event.protocol.to_string(),
+        lport = event.lport,
+    );
 }
@@ -1,39 +1,29 @@
-use std::{ops::Deref, sync::Arc};
+use tracing::{info, warn};
 
-use aya::Ebpf;
-use tokio::sync::Mutex;
-use tracing::info;
+use crate::handle::ebpf::Args;
 
-use crate::{
-    handle::ebpf::{handle_perf_array, PidProcesses},
-    Maps,
-};
+pub(super) async fn close(args: &Args, pid: u32) {
+    let mut pid_processes = args.pid_processes.lock().await;
 
-pub async fn close(
-    bpf: Arc<Mutex<Ebpf>>,
-    maps: Arc<Maps>,
-    pid_processes: Arc<Mutex<PidProcesses>>,
-) -> anyhow::Result<()> {
-    let args = Arc::new(Mutex::new((maps, pid_processes)));
+    match pid_processes.map.get(&pid) {
+        Some(processes) => unsafe {
+            for process in processes {
+                args.maps
+                    .process
+                    .remove(process.clone())
+                    .await
+                    .unwrap_or(());
+            }
 
-    handle_perf_array(bpf, "CLOSE_EVENTS", args, |event: u32, args| async move {
-        let args = args.lock().await;
-        let (maps, pid_processes) = args.deref();
-        let mut pid_processes = pid_processes.lock().await;
+            pid_processes.map.remove(&pid);
+            info!(event = "close", pid = pid,);
+        },
+        None => {}
+    };
 
-        match pid_processes.map.get(&event) {
-            Some(processes) => unsafe {
-                for process in processes {
-                    maps.process.remove(process.clone()).await.unwrap_or(());
-                }
-
-                pid_processes.map.remove(&event);
-                info!(event = "close", pid = event,);
-            },
-            None => {}
-        };
-    })
-    .await?;
-
-    Ok(())
+    args.maps
+        .process_tree
+        .remove_process(pid)
+        .await
+        .unwrap_or_else(|e| warn!("failed to remove the process: {}", e));
 }
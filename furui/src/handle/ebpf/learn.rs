@@ -0,0 +1,127 @@
+use std::sync::Arc;
+
+use aya::Ebpf;
+use furui_common::EbpfEvent;
+use tokio::sync::Mutex;
+
+use crate::{handle::ebpf::handle_events, learn::Learner};
+
+pub async fn learn_events(
+    bpf: Arc<Mutex<Ebpf>>,
+    learner: Arc<Mutex<Learner>>,
+) -> anyhow::Result<()> {
+    handle_events(bpf, move |event| {
+        let learner = learner.clone();
+
+        async move {
+            let mut learner = learner.lock().await;
+
+            match event {
+                EbpfEvent::Bind(event) => {
+                    learner
+                        .bind(
+                            event.container_id(),
+                            event.comm(),
+                            event.protocol,
+                            event.lport,
+                        )
+                        .await
+                }
+                EbpfEvent::Connect(event) => {
+                    learner
+                        .socket(
+                            event.container_id(),
+                            event.comm(),
+                            event.protocol,
+                            event.src_port,
+                            event.dst_addr(),
+                            event.dst_port,
+                        )
+                        .await
+                }
+                EbpfEvent::Connect6(event) => {
+                    learner
+                        .socket(
+                            event.container_id(),
+                            event.comm(),
+                            event.protocol,
+                            event.src_port,
+                            event.dst_addr(),
+                            event.dst_port,
+                        )
+                        .await
+                }
+                EbpfEvent::Close(_) => {}
+                EbpfEvent::Ingress(event) => {
+                    learner
+                        .socket(
+                            event.container_id(),
+                            event.comm(),
+                            event.protocol,
+                            event.dport,
+                            event.src_addr(),
+                            event.sport,
+                        )
+                        .await
+                }
+                EbpfEvent::Ingress6(event) => {
+                    learner
+                        .socket(
+                            event.container_id(),
+                            event.comm(),
+                            event.protocol,
+                            event.dport,
+                            event.src_addr(),
+                            event.sport,
+                        )
+                        .await
+                }
+                EbpfEvent::IngressIcmp(event) => {
+                    learner
+                        .icmp(event.container_id(), event.version, event.type_, event.code)
+                        .await
+                }
+                EbpfEvent::Ingress6Icmp(event) => {
+                    learner
+                        .icmp(event.container_id(), event.version, event.type_, event.code)
+                        .await
+                }
+                EbpfEvent::Egress(event) => {
+                    learner
+                        .socket(
+                            event.container_id(),
+                            event.comm(),
+                            event.protocol,
+                            event.sport,
+                            event.dst_addr(),
+                            event.dport,
+                        )
+                        .await
+                }
+                EbpfEvent::Egress6(event) => {
+                    learner
+                        .socket(
+                            event.container_id(),
+                            event.comm(),
+                            event.protocol,
+                            event.sport,
+                            event.dst_addr(),
+                            event.dport,
+                        )
+                        .await
+                }
+                EbpfEvent::EgressIcmp(event) => {
+                    learner
+                        .icmp(event.container_id(), event.version, event.type_, event.code)
+                        .await
+                }
+                EbpfEvent::Egress6Icmp(event) => {
+                    learner
+                        .icmp(event.container_id(), event.version, event.type_, event.code)
+                        .await
+                }
+            }
+        }
+    })
+    .await
+}
@@ -1,33 +1,55 @@
 use std::{collections::HashMap, convert::TryFrom, future::Future, sync::Arc};
 
-use aya::{maps::perf::AsyncPerfEventArray, util::online_cpus, Ebpf};
+use aya::{
+    maps::{MapData, RingBuf},
+    Ebpf,
+};
 use bind::*;
-use bytes::BytesMut;
 use close::*;
 use connect::*;
-use egress::*;
-use furui_common::IpProtocol;
-use ingress::*;
-use tokio::{sync::Mutex, task};
-
-use crate::{domain::Process, Maps};
+use furui_common::{EbpfEvent, IpProtocol};
+pub use learn::learn_events;
+use packet::*;
+use tokio::{
+    io::unix::AsyncFd,
+    sync::{broadcast, Mutex},
+    task,
+};
+use tracing::warn;
+
+use crate::{
+    domain::{Containers, Event, Policies, Process},
+    Maps,
+};
 
 mod bind;
 mod close;
 mod connect;
-
-mod egress;
-mod ingress;
+mod learn;
+mod packet;
 
 pub struct PidProcesses {
     map: HashMap<u32, Vec<Process>>,
 }
 
 impl PidProcesses {
-    fn new() -> PidProcesses {
-        PidProcesses {
+    pub fn new() -> Arc<Mutex<PidProcesses>> {
+        Arc::new(Mutex::new(PidProcesses {
             map: HashMap::<u32, Vec<Process>>::new(),
-        }
+        }))
+    }
+
+    /// The ports of every process, by its pid.
+    pub fn list(&self) -> Vec<(u32, Process)> {
+        let mut processes = self
+            .map
+            .iter()
+            .flat_map(|(pid, processes)| processes.iter().map(|process| (*pid, process.clone())))
+            .collect::<Vec<_>>();
+
+        processes.sort_by_key(|(pid, process)| (*pid, process.port));
+
+        processes
     }
 
     unsafe fn add(&mut self, pid: u32, container_id: String, port: u16, protocol: IpProtocol) {
@@ -48,75 +70,152 @@ impl PidProcesses {
     }
 }
 
-pub async unsafe fn perf_events(
+/// What the handlers of the events share.
+struct Args {
+    maps: Arc<Maps>,
+    policies: Arc<Mutex<Policies>>,
+    containers: Arc<Mutex<Containers>>,
+    pid_processes: Arc<Mutex<PidProcesses>>,
+    events: broadcast::Sender<Event>,
+}
+
+impl Args {
+    /// Keeps the port of a process until it exits, and saves its executable, its cgroup and its
+    /// ancestors for the rules naming them.
+    async fn save_process(&self, process: &Process) -> anyhow::Result<()> {
+        unsafe {
+            self.pid_processes.lock().await.add(
+                process.pid,
+                process.container_id.clone(),
+                process.port,
+                process.protocol,
+            );
+        }
+
+        self.maps
+            .executable
+            .save(
+                process.container_id.clone(),
+                process.pid,
+                process.exe_ino,
+                process.exe_dev,
+                &*self.policies.lock().await,
+            )
+            .await?;
+
+        let container_pid = self
+            .containers
+            .lock()
+            .await
+            .get(process.container_id.clone())
+            .map(|container| container.pid);
+
+        self.maps
+            .cgroup
+            .save(
+                process.container_id.clone(),
+                process.pid,
+                process.cgroup_id,
+                container_pid,
+                &*self.policies.lock().await,
+            )
+            .await?;
+
+        self.maps
+            .process_tree
+            .save(
+                process.container_id.clone(),
+                process.pid,
+                &*self.policies.lock().await,
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+pub async unsafe fn ebpf_events(
     bpf: Arc<Mutex<Ebpf>>,
     maps: Arc<Maps>,
+    policies: Arc<Mutex<Policies>>,
+    containers: Arc<Mutex<Containers>>,
+    pid_processes: Arc<Mutex<PidProcesses>>,
+    events: broadcast::Sender<Event>,
     processes: &Vec<Process>,
 ) -> anyhow::Result<()> {
-    let pid_processes = Arc::new(Mutex::new(PidProcesses::new()));
+    let args = Arc::new(Args {
+        maps,
+        policies,
+        containers,
+        pid_processes,
+        events,
+    });
 
     for process in processes {
-        pid_processes.lock().await.add(
-            process.pid,
-            process.container_id.clone(),
-            process.port,
-            process.protocol,
-        );
+        args.save_process(process).await?;
     }
 
-    bind(bpf.clone(), pid_processes.clone()).await?;
-    connect(bpf.clone(), pid_processes.clone()).await?;
-    close(bpf.clone(), maps, pid_processes.clone()).await?;
-
-    ingress(bpf.clone()).await?;
-    egress(bpf.clone()).await?;
+    handle_events(bpf, move |event| {
+        let args = args.clone();
 
-    Ok(())
+        async move {
+            match event {
+                EbpfEvent::Bind(event) => bind(&args, &event).await,
+                EbpfEvent::Connect(event) => {
+                    connect(&args, Process::from(&event), Event::from(&event)).await
+                }
+                EbpfEvent::Connect6(event) => {
+                    connect(&args, Process::from(&event), Event::from(&event)).await
+                }
+                EbpfEvent::Close(pid) => close(&args, pid).await,
+                EbpfEvent::Ingress(event) => packet(&args, Event::from(&event)),
+                EbpfEvent::Ingress6(event) => packet(&args, Event::from(&event)),
+                EbpfEvent::IngressIcmp(event) => packet(&args, Event::from(&event)),
+                EbpfEvent::Ingress6Icmp(event) => packet(&args, Event::from(&event)),
+                EbpfEvent::Egress(event) => packet(&args, Event::from(&event)),
+                EbpfEvent::Egress6(event) => packet(&args, Event::from(&event)),
+                EbpfEvent::EgressIcmp(event) => packet(&args, Event::from(&event)),
+                EbpfEvent::Egress6Icmp(event) => packet(&args, Event::from(&event)),
+            }
+        }
+    })
+    .await
 }
 
-async fn handle_perf_array<E, A, F, Fut>(
-    bpf: Arc<Mutex<Ebpf>>,
-    map_name: &str,
-    args: Arc<Mutex<A>>,
-    callback: F,
-) -> anyhow::Result<()>
+/// Reads the events of every program from the `EVENTS` ring buffer in a single task, and passes
+/// them to `callback` one at a time, in the order they were sent.
+async fn handle_events<F, Fut>(bpf: Arc<Mutex<Ebpf>>, mut callback: F) -> anyhow::Result<()>
 where
-    E: Send,
-    A: Send + 'static,
-    F: Fn(E, Arc<Mutex<A>>) -> Fut + Send + Sync + 'static,
+    F: FnMut(EbpfEvent) -> Fut + Send + 'static,
     Fut: Future<Output = ()> + Send,
 {
-    let shared_callback = Arc::from(callback);
-
-    let perf_array = Arc::new(Mutex::new(AsyncPerfEventArray::try_from(
-        bpf.lock().await.take_map(map_name).unwrap(),
-    )?));
-
-    for cpu_id in online_cpus().unwrap() {
-        let current_perf_array = perf_array.clone();
-        let current_args = args.clone();
-        let current_callback = shared_callback.clone();
-
-        task::spawn(async move {
-            let mut buf = current_perf_array.lock().await.open(cpu_id, None).unwrap();
-
-            let mut buffers = (0..10)
-                .map(|_| BytesMut::with_capacity(1024))
-                .collect::<Vec<_>>();
+    let ring_buf = RingBuf::try_from(bpf.lock().await.take_map("EVENTS").unwrap())?;
+    let mut ring_buf = AsyncFd::new(ring_buf)?;
+
+    task::spawn(async move {
+        loop {
+            let mut guard = match ring_buf.readable_mut().await {
+                Ok(guard) => guard,
+                Err(e) => {
+                    warn!("stopped reading the events: {}", e);
+                    return;
+                }
+            };
 
-            loop {
-                let events = buf.read_events(&mut buffers).await.unwrap();
+            while let Some(event) = next_event(guard.get_inner_mut()) {
+                callback(event).await;
+            }
 
-                for i in 0..events.read {
-                    let buf = &mut buffers[i];
+            guard.clear_ready();
+        }
+    });
 
-                    let event = unsafe { (buf.as_ptr() as *const E).read_unaligned() };
+    Ok(())
+}
 
-                    current_callback(event, current_args.clone()).await;
-                }
-            }
-        });
-    }
+/// The programs reserve nothing but `EbpfEvent` in the ring buffer.
+fn next_event(ring_buf: &mut RingBuf<MapData>) -> Option<EbpfEvent> {
+    let item = ring_buf.next()?;
 
-    Ok(())
+    Some(unsafe { (item.as_ptr() as *const EbpfEvent).read_unaligned() })
 }
@@ -0,0 +1,36 @@
+use tracing::info;
+
+use crate::{domain::Event, handle::ebpf::Args};
+
+/// Logs a packet the classifiers passed or dropped, of either direction.
+pub(super) fn packet(args: &Args, event: Event) {
+    let _ = args.events.send(event.clone());
+
+    if event.icmp_version.is_empty() {
+        info!(
+            event = event.event,
+            action = event.action,
+            container_id = event.container_id.as_str(),
+            comm = event.comm.as_str(),
+            family = event.family,
+            protocol = event.protocol,
+            source_addr = event.source_addr.as_str(),
+            source_port = event.source_port,
+            destination_addr = event.destination_addr.as_str(),
+            destination_port = event.destination_port,
+        );
+    } else {
+        info!(
+            event = event.event,
+            action = event.action,
+            container_id = event.container_id.as_str(),
+            family = event.family,
+            protocol = event.protocol,
+            source_addr = event.source_addr.as_str(),
+            destination_addr = event.destination_addr.as_str(),
+            version = event.icmp_version,
+            "type" = event.icmp_type,
+            code = event.icmp_code,
+        );
+    }
+}
@@ -1,7 +1,9 @@
-pub use ebpf::perf_events;
-pub use policy::policy_events;
+pub use dns::{dns_events, lookup_domains};
+pub use ebpf::{ebpf_events, learn_events, PidProcesses};
+pub use policy::{policy_events, PolicySources};
 pub use runtime::container_events;
 
+mod dns;
 mod ebpf;
 mod policy;
 mod runtime;
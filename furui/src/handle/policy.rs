@@ -1,37 +1,246 @@
-use std::{ops::Deref, path::PathBuf, sync::Arc, time::Duration};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use tokio::{sync::Mutex, task, time};
-use tracing::info;
+use futures::StreamExt;
+use inotify::{Inotify, WatchMask};
+use tokio::{sync::Mutex, task};
+use tracing::{info, warn};
 
-use crate::{domain::Policies, Containers, Maps, ParsePolicies};
+use crate::{
+    domain::Policies,
+    parse_policies::{Container, Policy},
+    Containers, Maps, ParsePolicies,
+};
+
+/// Where the policies come from: the policy files, and what the control API set for single
+/// containers, which takes the place of what the files have for them.
+pub struct PolicySources {
+    files: BTreeMap<PathBuf, ParsePolicies>,
+    added: BTreeMap<String, Policy>,
+    audit_containers: BTreeSet<String>,
+    applied: Option<(ParsePolicies, BTreeSet<String>)>,
+}
+
+impl PolicySources {
+    /// Loads a policy file, or every policy file in a directory, failing on the first one that
+    /// does not load.
+    pub fn load(path: &Path) -> anyhow::Result<PolicySources> {
+        let files = if path.is_dir() {
+            ParsePolicies::load_dir(path)?
+        } else {
+            BTreeMap::from([(path.to_path_buf(), ParsePolicies::load_file(path)?)])
+        };
+
+        let mut sources = PolicySources {
+            files,
+            added: BTreeMap::new(),
+            audit_containers: BTreeSet::new(),
+            applied: None,
+        };
+        sources.applied = Some((sources.merge()?, BTreeSet::new()));
+
+        Ok(sources)
+    }
+
+    /// The policy files merged, with the added policies in place of the policies the files have
+    /// for the same container names.
+    pub fn merge(&self) -> anyhow::Result<ParsePolicies> {
+        let mut merged = ParsePolicies::merge(&self.files)?;
+
+        merged.policies.retain(|policy| {
+            !self
+                .added
+                .keys()
+                .any(|name| policy.container == Container::named(name))
+        });
+        merged.policies.extend(self.added.values().cloned());
+
+        Ok(merged)
+    }
+
+    /// The policies last loaded into the maps.
+    pub fn applied(&self) -> Option<&ParsePolicies> {
+        self.applied
+            .as_ref()
+            .map(|(parsed_policies, _)| parsed_policies)
+    }
+
+    pub fn is_added(&self, policy: &Policy) -> bool {
+        self.added
+            .get(policy.container.name.as_deref().unwrap_or_default())
+            == Some(policy)
+    }
+
+    pub fn is_audit(&self, name: &str) -> bool {
+        self.audit_containers.contains(name)
+    }
+
+    /// Sets the policy of a container, see [`Policy::parse_for`].
+    pub fn add(&mut self, name: String, policy: Policy) {
+        self.added.insert(name, policy);
+    }
+
+    pub fn added(&self, name: &str) -> Option<&Policy> {
+        self.added.get(name)
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.added.remove(name);
+    }
+
+    pub fn set_audit(&mut self, name: String, audit: bool) {
+        if audit {
+            self.audit_containers.insert(name);
+        } else {
+            self.audit_containers.remove(&name);
+        }
+    }
+
+    /// Loads the policies into the maps, unless they are the ones already loaded.
+    pub async fn apply(
+        &mut self,
+        maps: &Maps,
+        policies: &Mutex<Policies>,
+        containers: Arc<Mutex<Containers>>,
+    ) -> anyhow::Result<()> {
+        let now_parsed_policies = self.merge()?;
+
+        // The files are compared rather than the policies, whose host addresses are kept up to
+        // date by `dns_events`.
+        let now_applied = (now_parsed_policies, self.audit_containers.clone());
+        if self.applied.as_ref() == Some(&now_applied) {
+            return Ok(());
+        }
+
+        let mut now_policies = now_applied
+            .0
+            .to_policies(containers.clone())
+            .await?
+            .lock()
+            .await
+            .clone();
+        now_policies.audit_containers = now_applied.1.clone();
+        now_policies.set_container_id(containers).await;
+
+        let mut policies = policies.lock().await;
+
+        // Nothing is looked up while the policies are locked. The hosts keep the addresses
+        // `dns_events` found for them, and it looks up the new ones within a second.
+        now_policies.set_domain_ips(&policies.domain_ips());
+
+        // A failed update puts the maps back to the policies kept here, and the next reload is
+        // taken against them.
+        maps.policy.update(&policies, &now_policies).await?;
+
+        maps.executable
+            .update(&now_policies)
+            .await
+            .unwrap_or_else(|e| warn!("failed to update the executables: {}", e));
+
+        maps.cgroup
+            .update(&now_policies)
+            .await
+            .unwrap_or_else(|e| warn!("failed to update the cgroups: {}", e));
+
+        maps.process_tree
+            .update(&now_policies)
+            .await
+            .unwrap_or_else(|e| warn!("failed to update the process trees: {}", e));
+
+        *policies = now_policies;
+        self.applied = Some(now_applied);
+
+        info!("policy updated.");
+
+        Ok(())
+    }
+
+    /// A file that is broken halfway through an edit keeps its previous policies, while the other
+    /// files still apply.
+    fn reload_file(&mut self, file: &Path) {
+        if !file.exists() {
+            self.files.remove(file);
+            return;
+        }
+
+        match ParsePolicies::load_file(file) {
+            Ok(parsed_policies) => {
+                self.files.insert(file.to_path_buf(), parsed_policies);
+            }
+            Err(err) => warn!("failed to load the policy: {}", err),
+        }
+    }
+
+    fn reload_dir(&mut self, dir: &Path) {
+        let dir_files = match ParsePolicies::policy_files(dir) {
+            Ok(dir_files) => dir_files,
+            Err(err) => {
+                warn!("failed to load the policy: {}", err);
+                return;
+            }
+        };
+
+        self.files.retain(|file, _| dir_files.contains(file));
+
+        for file in &dir_files {
+            self.reload_file(file);
+        }
+    }
+}
 
 pub fn policy_events(
     policy_path: PathBuf,
+    sources: Arc<Mutex<PolicySources>>,
     maps: Arc<Maps>,
     policies: Arc<Mutex<Policies>>,
     containers: Arc<Mutex<Containers>>,
 ) -> anyhow::Result<()> {
-    task::spawn(async move {
-        loop {
-            time::sleep(Duration::from_secs(1)).await;
-
-            let now_policies = match ParsePolicies::new(policy_path.clone()) {
-                Ok(parsed_policies) => parsed_policies
-                    .to_policies(containers.clone())
-                    .await
-                    .unwrap(),
-                Err(_) => Arc::new(Mutex::new(Policies::default())),
-            };
+    // Editors and Kubernetes replace the file instead of writing to it, so a single file is
+    // watched through its directory.
+    let is_dir = policy_path.is_dir();
+    let policy_dir = match policy_path.parent() {
+        _ if is_dir => policy_path.clone(),
+        Some(policy_dir) if !policy_dir.as_os_str().is_empty() => policy_dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
 
-            if policies.lock().await.deref() != now_policies.lock().await.deref() {
-                let _ = maps.policy.remove().await;
+    let inotify = Inotify::init()?;
+    inotify.watches().add(
+        &policy_dir,
+        WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::CREATE | WatchMask::DELETE,
+    )?;
+    let mut events = inotify.into_event_stream([0; 4096])?;
 
-                policies.lock().await.policies = now_policies.lock().await.policies.clone();
+    task::spawn(async move {
+        while let Some(event) = events.next().await {
+            let event = match event {
+                Ok(event) => event,
+                Err(err) => {
+                    warn!("failed to watch the policy: {}", err);
+                    continue;
+                }
+            };
 
-                let _ = maps.policy.save(policies.clone()).await;
+            let mut sources = sources.lock().await;
 
-                info!("policy updated.");
+            // Only the file that changed is parsed again. Other changes in the directory, such
+            // as Kubernetes swapping a config map, may touch every file. A single policy file
+            // that is missing for a moment while it is replaced keeps its policies.
+            let file = event.name.map(|name| policy_dir.join(name));
+            match file {
+                _ if !is_dir && !policy_path.exists() => continue,
+                _ if !is_dir => sources.reload_file(&policy_path),
+                Some(file) if ParsePolicies::is_policy_file(&file) => sources.reload_file(&file),
+                _ => sources.reload_dir(&policy_dir),
             }
+
+            sources
+                .apply(&maps, &policies, containers.clone())
+                .await
+                .unwrap_or_else(|e| warn!("failed to update the policy: {}", e));
         }
     });
 
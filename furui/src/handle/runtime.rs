@@ -7,6 +7,7 @@ use tracing::{info, warn};
 
 use crate::{
     domain::{Container, Policies},
+    process,
     runtime::ContainerAction,
     Containers, Loader, Maps, Runtime,
 };
@@ -61,28 +62,16 @@ async fn add_container(
         .await
         .unwrap_or_else(|e| warn!("failed to add the container inspection: {}", e));
 
-    containers.lock().await.add(container);
+    containers.lock().await.add(container.clone());
 
     maps.container
-        .save_id_with_ips(containers.clone())
+        .save_ids(containers.clone())
         .await
         .unwrap_or_else(|e| warn!("failed to save container: {}", e));
 
-    maps.policy
-        .remove()
-        .await
-        .unwrap_or_else(|e| warn!("failed to remove policies: {}", e));
-
-    policies
-        .lock()
-        .await
-        .set_container_id(containers.clone())
-        .await;
+    update_policies(maps.clone(), containers.clone(), policies.clone()).await;
 
-    maps.policy
-        .save(policies.clone())
-        .await
-        .unwrap_or_else(|e| warn!("failed to save policies: {}", e));
+    save_processes(&maps, &container, policies.clone()).await;
 
     let _ = loader.attach_tc_programs().await;
 
@@ -101,30 +90,113 @@ async fn remove_container(
     let container = containers.lock().await.get(id.clone()).unwrap();
 
     maps.container
-        .remove_id_from_ips(container)
+        .remove_ids(container)
         .await
         .unwrap_or_else(|e| warn!("failed to remove container: {}", e));
 
     containers.lock().await.remove(id.clone());
 
-    maps.policy
-        .remove()
+    maps.executable
+        .remove_container(&id)
+        .await
+        .unwrap_or_else(|e| warn!("failed to remove the executables: {}", e));
+
+    maps.cgroup
+        .remove_container(&id)
         .await
-        .unwrap_or_else(|e| warn!("failed to remove policies: {}", e));
+        .unwrap_or_else(|e| warn!("failed to remove the cgroups: {}", e));
 
-    policies
-        .lock()
+    maps.process_tree
+        .remove_container(&id)
         .await
-        .set_container_id(containers.clone())
-        .await;
+        .unwrap_or_else(|e| warn!("failed to remove the process trees: {}", e));
 
-    maps.policy
-        .save(policies.clone())
+    maps.metrics
+        .remove_container(&id)
         .await
-        .unwrap_or_else(|e| warn!("failed to save policies: {}", e));
+        .unwrap_or_else(|e| warn!("failed to remove the packet counts: {}", e));
+
+    update_policies(maps.clone(), containers.clone(), policies.clone()).await;
 
     info!(
         container_id = id.as_str(),
         "the container inspection removed."
     );
 }
+
+/// The kprobes skip the processes of a container until its cgroup is saved, so the ports bound
+/// before then are looked up here.
+async fn save_processes(maps: &Maps, container: &Container, policies: Arc<Mutex<Policies>>) {
+    let processes = process::get(container);
+
+    unsafe { maps.process.save_all(&processes).await }
+        .unwrap_or_else(|e| warn!("failed to save the processes: {}", e));
+
+    let policies = policies.lock().await;
+
+    for process in &processes {
+        maps.executable
+            .save(
+                process.container_id.clone(),
+                process.pid,
+                process.exe_ino,
+                process.exe_dev,
+                &policies,
+            )
+            .await
+            .unwrap_or_else(|e| warn!("failed to save the executable: {}", e));
+
+        maps.cgroup
+            .save(
+                process.container_id.clone(),
+                process.pid,
+                process.cgroup_id,
+                Some(container.pid),
+                &policies,
+            )
+            .await
+            .unwrap_or_else(|e| warn!("failed to save the cgroup: {}", e));
+
+        maps.process_tree
+            .save(process.container_id.clone(), process.pid, &policies)
+            .await
+            .unwrap_or_else(|e| warn!("failed to save the process tree: {}", e));
+    }
+}
+
+/// Resolves the containers of the policies again and writes only the entries that changed, so
+/// the other containers are enforced throughout.
+async fn update_policies(
+    maps: Arc<Maps>,
+    containers: Arc<Mutex<Containers>>,
+    policies: Arc<Mutex<Policies>>,
+) {
+    let mut policies = policies.lock().await;
+
+    // The new container IDs are kept only once they are in the maps, so the next diff is taken
+    // against what was actually written. The next container event tries again.
+    let mut new_policies = policies.clone();
+    new_policies.set_container_id(containers).await;
+
+    if let Err(e) = maps.policy.update(&policies, &new_policies).await {
+        warn!("failed to update policies: {}", e);
+        return;
+    }
+
+    *policies = new_policies;
+
+    maps.executable
+        .update(&policies)
+        .await
+        .unwrap_or_else(|e| warn!("failed to update the executables: {}", e));
+
+    maps.cgroup
+        .update(&policies)
+        .await
+        .unwrap_or_else(|e| warn!("failed to update the cgroups: {}", e));
+
+    maps.process_tree
+        .update(&policies)
+        .await
+        .unwrap_or_else(|e| warn!("failed to update the process trees: {}", e));
+}
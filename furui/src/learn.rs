@@ -0,0 +1,444 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    net::IpAddr,
+    ops::RangeInclusive,
+    path::Path,
+    sync::Arc,
+};
+
+use furui_common::{IcmpVersion, IpProtocol};
+use tokio::sync::Mutex;
+
+use crate::{
+    domain::Containers,
+    parse_policies::{self, Action, Format, Mode, ParsePolicies, Protocol},
+};
+
+const IP_LOCAL_PORT_RANGE: &str = "/proc/sys/net/ipv4/ip_local_port_range";
+const DEFAULT_EPHEMERAL_PORTS: RangeInclusive<u16> = 32768..=60999;
+
+/// Collects the communications observed while learning, keyed by the container name and the
+/// executable.
+pub struct Learner {
+    containers: Arc<Mutex<Containers>>,
+    container_names: Vec<String>,
+    ephemeral_ports: RangeInclusive<u16>,
+    communications: BTreeMap<(String, Option<String>), Communication>,
+}
+
+#[derive(Default)]
+struct Communication {
+    sockets: BTreeSet<Socket>,
+    icmp: BTreeSet<Icmp>,
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct Socket {
+    protocol: Protocol,
+    local_port: Option<u16>,
+    remote_host: Option<String>,
+    remote_port: Option<u16>,
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct Icmp {
+    version: parse_policies::IcmpVersion,
+    type_: u8,
+    code: u8,
+}
+
+impl Learner {
+    pub fn new(
+        containers: Arc<Mutex<Containers>>,
+        container_names: Vec<String>,
+    ) -> Arc<Mutex<Learner>> {
+        Arc::new(Mutex::new(Learner {
+            containers,
+            container_names,
+            ephemeral_ports: ephemeral_ports(),
+            communications: BTreeMap::new(),
+        }))
+    }
+
+    /// Records a bound port, which is always a local service.
+    pub async fn bind(
+        &mut self,
+        container_id: String,
+        comm: String,
+        protocol: IpProtocol,
+        local_port: u16,
+    ) {
+        let protocol = match to_protocol(protocol) {
+            Some(protocol) => protocol,
+            None => return,
+        };
+
+        self.add_socket(
+            container_id,
+            comm,
+            Socket {
+                protocol,
+                local_port: Some(local_port),
+                remote_host: None,
+                remote_port: None,
+            },
+        )
+        .await;
+    }
+
+    /// Records a packet or a connection of a process. When the local port is ephemeral the
+    /// process is the client and the remote end is kept, otherwise only the local port is.
+    pub async fn socket(
+        &mut self,
+        container_id: String,
+        comm: String,
+        protocol: IpProtocol,
+        local_port: u16,
+        remote_addr: String,
+        remote_port: u16,
+    ) {
+        let protocol = match to_protocol(protocol) {
+            Some(protocol) => protocol,
+            None => return,
+        };
+
+        let socket = if self.ephemeral_ports.contains(&local_port) {
+            Socket {
+                protocol,
+                local_port: None,
+                remote_host: Some(self.remote_host(remote_addr).await),
+                remote_port: Some(remote_port),
+            }
+        } else {
+            Socket {
+                protocol,
+                local_port: Some(local_port),
+                remote_host: None,
+                remote_port: None,
+            }
+        };
+
+        self.add_socket(container_id, comm, socket).await;
+    }
+
+    pub async fn icmp(&mut self, container_id: String, version: IcmpVersion, type_: u8, code: u8) {
+        let container_name = match self.container_name(&container_id).await {
+            Some(container_name) => container_name,
+            None => return,
+        };
+
+        let version = match version {
+            IcmpVersion::V4 => parse_policies::IcmpVersion::V4,
+            IcmpVersion::V6 => parse_policies::IcmpVersion::V6,
+            IcmpVersion::Default => return,
+        };
+
+        self.communications
+            .entry((container_name, None))
+            .or_default()
+            .icmp
+            .insert(Icmp {
+                version,
+                type_,
+                code,
+            });
+    }
+
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let format = Format::from_path(path).unwrap_or(Format::Yaml);
+
+        fs::write(path, self.to_parse_policies().to_string(format)?)?;
+
+        Ok(())
+    }
+
+    fn to_parse_policies(&self) -> ParsePolicies {
+        let mut policies: Vec<parse_policies::Policy> = vec![];
+
+        for ((container_name, executable), communication) in &self.communications {
+            let communication = parse_policies::Communication {
+                executable: executable.clone(),
+                executable_path: None,
+                executable_sha256: None,
+                cgroup: None,
+                process_tree: None,
+                include: vec![],
+                sockets: communication
+                    .sockets
+                    .iter()
+                    .map(|socket| parse_policies::Socket {
+                        protocol: socket.protocol,
+                        local_port: socket.local_port,
+                        local_port_range: None,
+                        remote_host: socket.remote_host.clone(),
+                        remote_port: socket.remote_port,
+                        remote_port_range: None,
+                        action: Action::Allow,
+                    })
+                    .collect(),
+                icmp: communication
+                    .icmp
+                    .iter()
+                    .map(|icmp| parse_policies::ICMP {
+                        version: icmp.version,
+                        type_: icmp.type_,
+                        code: Some(icmp.code),
+                        remote_host: None,
+                        action: Action::Allow,
+                    })
+                    .collect(),
+            };
+
+            // The map is sorted by the container name, so its communications are adjacent.
+            match policies.last_mut() {
+                Some(policy) if policy.container.name.as_ref() == Some(container_name) => {
+                    policy.communications.push(communication)
+                }
+                _ => policies.push(parse_policies::Policy {
+                    container: parse_policies::Container {
+                        name: Some(container_name.clone()),
+                        name_regex: None,
+                        labels: BTreeMap::new(),
+                        image: None,
+                    },
+                    mode: Mode::Enforce,
+                    default_action: None,
+                    communications: vec![communication],
+                    file: None,
+                }),
+            }
+        }
+
+        ParsePolicies {
+            default_action: None,
+            groups: BTreeMap::new(),
+            policies,
+        }
+    }
+
+    async fn add_socket(&mut self, container_id: String, comm: String, socket: Socket) {
+        // Packets to ports no process is known for carry no executable.
+        if comm.is_empty() {
+            return;
+        }
+
+        let container_name = match self.container_name(&container_id).await {
+            Some(container_name) => container_name,
+            None => return,
+        };
+
+        self.communications
+            .entry((container_name, Some(comm)))
+            .or_default()
+            .sockets
+            .insert(socket);
+    }
+
+    async fn container_name(&self, container_id: &str) -> Option<String> {
+        if container_id.is_empty() {
+            return None;
+        }
+
+        let container = self.containers.lock().await.get(container_id.to_string())?;
+        let container_name = container.name.trim_start_matches("/").to_string();
+
+        if !self.container_names.is_empty() && !self.container_names.contains(&container_name) {
+            return None;
+        }
+
+        Some(container_name)
+    }
+
+    /// Uses the container name for the addresses of known containers, like the hand-written
+    /// policies do.
+    async fn remote_host(&self, remote_addr: String) -> String {
+        let ip = match remote_addr.parse::<IpAddr>() {
+            Ok(ip) => ip,
+            Err(_) => return remote_addr,
+        };
+
+        for container in self.containers.lock().await.list() {
+            if let Some(ip_addresses) = &container.ip_addresses {
+                if ip_addresses.contains(&ip) {
+                    return container.name.trim_start_matches("/").to_string();
+                }
+            }
+        }
+
+        remote_addr
+    }
+}
+
+fn to_protocol(protocol: IpProtocol) -> Option<Protocol> {
+    match protocol {
+        IpProtocol::TCP => Some(Protocol::TCP),
+        IpProtocol::UDP => Some(Protocol::UDP),
+        _ => None,
+    }
+}
+
+fn ephemeral_ports() -> RangeInclusive<u16> {
+    fs::read_to_string(IP_LOCAL_PORT_RANGE)
+        .ok()
+        .and_then(|range| parse_port_range(&range))
+        .unwrap_or(DEFAULT_EPHEMERAL_PORTS)
+}
+
+/// The range in the contents of `ip_local_port_range`, the first and the last port.
+fn parse_port_range(range: &str) -> Option<RangeInclusive<u16>> {
+    let mut ports = range.split_whitespace().map(|port| port.parse::<u16>());
+    match (ports.next(), ports.next()) {
+        (Some(Ok(start)), Some(Ok(end))) => Some(start..=end),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::Container;
+
+    async fn learner(container_names: &[&str]) -> Learner {
+        let containers = Containers::new();
+        {
+            let mut containers = containers.lock().await;
+            containers.add(Container {
+                name: "/web".to_string(),
+                ..Container::new("web_id".to_string())
+            });
+            containers.add(Container {
+                name: "/db".to_string(),
+                ip_addresses: Some(vec![IpAddr::from([172, 17, 0, 3])]),
+                ..Container::new("db_id".to_string())
+            });
+        }
+
+        Learner {
+            containers,
+            container_names: container_names
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            ephemeral_ports: DEFAULT_EPHEMERAL_PORTS,
+            communications: BTreeMap::new(),
+        }
+    }
+
+    fn socket(
+        local_port: Option<u16>,
+        remote_host: Option<&str>,
+        remote_port: Option<u16>,
+    ) -> parse_policies::Socket {
+        parse_policies::Socket {
+            protocol: Protocol::TCP,
+            local_port,
+            local_port_range: None,
+            remote_host: remote_host.map(str::to_string),
+            remote_port,
+            remote_port_range: None,
+            action: Action::Allow,
+        }
+    }
+
+    #[test]
+    fn port_range_is_the_first_and_the_last_port() {
+        assert_eq!(parse_port_range("32768\t60999\n"), Some(32768..=60999));
+        assert_eq!(parse_port_range("1024 65535"), Some(1024..=65535));
+        assert_eq!(parse_port_range("32768"), None);
+        assert_eq!(parse_port_range(""), None);
+    }
+
+    #[tokio::test]
+    async fn ephemeral_ports_are_collapsed() {
+        let mut learner = learner(&[]).await;
+
+        for local_port in [40000, 40001] {
+            learner
+                .socket(
+                    "web_id".to_string(),
+                    "php-fpm".to_string(),
+                    IpProtocol::TCP,
+                    local_port,
+                    "172.17.0.3".to_string(),
+                    3306,
+                )
+                .await;
+        }
+        for remote_port in [50000, 50001] {
+            learner
+                .socket(
+                    "web_id".to_string(),
+                    "nginx".to_string(),
+                    IpProtocol::TCP,
+                    80,
+                    "10.0.0.1".to_string(),
+                    remote_port,
+                )
+                .await;
+        }
+        learner
+            .bind(
+                "web_id".to_string(),
+                "nginx".to_string(),
+                IpProtocol::TCP,
+                80,
+            )
+            .await;
+
+        let policies = learner.to_parse_policies().policies;
+
+        assert_eq!(policies.len(), 1);
+        assert_eq!(policies[0].container.name.as_deref(), Some("web"));
+
+        let communications = &policies[0].communications;
+        assert_eq!(communications.len(), 2);
+        assert_eq!(communications[0].executable.as_deref(), Some("nginx"));
+        assert_eq!(
+            communications[0].sockets,
+            vec![socket(Some(80), None, None)]
+        );
+        assert_eq!(communications[1].executable.as_deref(), Some("php-fpm"));
+        assert_eq!(
+            communications[1].sockets,
+            vec![socket(None, Some("db"), Some(3306))]
+        );
+    }
+
+    #[tokio::test]
+    async fn only_the_given_containers_are_learned() {
+        let mut learner = learner(&["db"]).await;
+
+        learner
+            .bind(
+                "web_id".to_string(),
+                "nginx".to_string(),
+                IpProtocol::TCP,
+                80,
+            )
+            .await;
+        learner
+            .bind(
+                "db_id".to_string(),
+                "mysqld".to_string(),
+                IpProtocol::TCP,
+                3306,
+            )
+            .await;
+        learner
+            .bind("".to_string(), "nginx".to_string(), IpProtocol::TCP, 8080)
+            .await;
+        learner
+            .icmp("web_id".to_string(), IcmpVersion::V4, 8, 0)
+            .await;
+
+        let policies = learner.to_parse_policies().policies;
+
+        assert_eq!(policies.len(), 1);
+        assert_eq!(policies[0].container.name.as_deref(), Some("db"));
+        assert_eq!(
+            policies[0].communications[0].sockets,
+            vec![socket(Some(3306), None, None)]
+        );
+    }
+}
@@ -1,24 +1,41 @@
-use std::path::PathBuf;
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
 
 use anyhow::anyhow;
 use clap::Parser;
 use thiserror::Error;
-use tracing::error;
+use tokio::{
+    select,
+    signal::unix::{signal, SignalKind},
+    sync::Mutex,
+    time,
+};
+use tracing::{error, info};
 use tracing_core::Level;
 use tracing_log::LogTracer;
 use tracing_subscriber::FmtSubscriber;
 
 use crate::{
-    domain::Containers, ebpf::Loader, map::Maps, parse_policies::ParsePolicies, runtime::Runtime,
+    control::ControlService,
+    domain::Containers,
+    ebpf::Loader,
+    handle::{PidProcesses, PolicySources},
+    learn::Learner,
+    map::Maps,
+    parse_policies::ParsePolicies,
+    runtime::Runtime,
 };
 
+pub mod control;
 mod domain;
 mod ebpf;
 mod handle;
+mod learn;
 mod map;
+mod metrics;
 mod parse_policies;
 mod process;
 mod runtime;
+mod validate;
 
 #[derive(clap::ValueEnum, PartialEq, Debug, Clone)]
 pub enum ContainerRuntime {
@@ -42,11 +59,18 @@ pub enum LogFormat {
 }
 
 #[derive(Debug, Clone, Parser)]
+#[command(subcommand_negates_reqs = true)]
 pub struct Options {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     #[arg(long, short = 'e', value_enum, default_value = "docker")]
     pub container_engine: ContainerRuntime,
 
-    pub policy_path: PathBuf,
+    /// A policy file, or a directory whose policy files are merged. The format of each file, YAML,
+    /// JSON or TOML, is told by its extension.
+    #[arg(required = true)]
+    pub policy_path: Option<PathBuf>,
 
     #[cfg_attr(debug_assertions, arg(long, value_enum, default_value = "debug"))]
     #[cfg_attr(not(debug_assertions), arg(long, value_enum, default_value = "info"))]
@@ -54,6 +78,52 @@ pub struct Options {
 
     #[arg(long, value_enum, default_value = "text")]
     pub log_fmt: LogFormat,
+
+    /// Only log the packets that would be dropped, for every container.
+    #[arg(long)]
+    pub audit: bool,
+
+    /// The Unix socket of the control API.
+    #[arg(long, default_value = "/run/furui/furui.sock")]
+    pub control_socket: PathBuf,
+
+    /// Serve the pass and drop counters of the containers over HTTP on this address, such as
+    /// 127.0.0.1:9090, in the Prometheus text format.
+    #[arg(long)]
+    pub metrics_addr: Option<SocketAddr>,
+}
+
+#[derive(Debug, Clone, clap::Subcommand)]
+pub enum Command {
+    /// Record the traffic of the running containers and write it out as a policy file.
+    Learn(LearnOptions),
+    /// Check a policy file or directory without loading it, printing each problem with its line
+    /// and column.
+    Validate(ValidateOptions),
+    /// Print the JSON Schema of the policy format.
+    Schema,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct LearnOptions {
+    /// Where to write the learned policies, as YAML, JSON or TOML by the extension.
+    #[arg(long, short = 'o', default_value = "learned.yaml")]
+    pub output: PathBuf,
+
+    /// How many seconds to record for. Interrupting stops recording early.
+    #[arg(long, short = 'd', default_value = "60")]
+    pub duration: u64,
+
+    /// Names of the containers to learn. All containers are learned when omitted.
+    #[arg(long = "container", short = 'c')]
+    pub containers: Vec<String>,
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct ValidateOptions {
+    /// A policy file, or a directory whose policy files are merged. The format of each file, YAML,
+    /// JSON or TOML, is told by its extension.
+    pub path: PathBuf,
 }
 
 pub async unsafe fn start(opt: Options) -> anyhow::Result<()> {
@@ -63,6 +133,13 @@ pub async unsafe fn start(opt: Options) -> anyhow::Result<()> {
 
     setup_tracing(&opt)?;
 
+    process::check_cgroup_v2()?;
+
+    let policy_path = match opt.policy_path.clone() {
+        Some(policy_path) => policy_path,
+        None => return Err(anyhow!("Please specify the policy file.")),
+    };
+
     let container_engine = Runtime::new(&opt).await?;
     let containers = Containers::new();
 
@@ -70,12 +147,10 @@ pub async unsafe fn start(opt: Options) -> anyhow::Result<()> {
         .add_running_containers_inspect(containers.clone())
         .await?;
 
-    let policies = match ParsePolicies::new(opt.policy_path.clone()) {
-        Ok(parsed_policies) => parsed_policies.to_policies(containers.clone()).await?,
-        Err(err) => {
-            return Err(err);
-        }
-    };
+    let sources = PolicySources::load(&policy_path)?;
+    let policies = sources.merge()?.to_policies(containers.clone()).await?;
+    handle::lookup_domains(&policies).await?;
+    let sources = Arc::new(Mutex::new(sources));
 
     let bpf = ebpf::load_bpf()?;
     let loader = Loader::new(bpf.clone());
@@ -85,11 +160,26 @@ pub async unsafe fn start(opt: Options) -> anyhow::Result<()> {
     let processes = process::get_all(containers.clone()).await;
 
     let maps = Maps::new(bpf.clone());
+    if opt.audit {
+        maps.policy.save_audit_all().await?;
+    }
     maps.policy.save(policies.clone()).await?;
-    maps.container.save_id_with_ips(containers.clone()).await?;
+    maps.container.save_ids(containers.clone()).await?;
     maps.process.save_all(&processes).await?;
 
-    handle::perf_events(bpf.clone(), maps.clone(), &processes).await?;
+    let pid_processes = PidProcesses::new();
+    let events = domain::event_channel();
+
+    handle::ebpf_events(
+        bpf.clone(),
+        maps.clone(),
+        policies.clone(),
+        containers.clone(),
+        pid_processes.clone(),
+        events.clone(),
+        &processes,
+    )
+    .await?;
     handle::container_events(
         loader.clone(),
         container_engine.clone(),
@@ -98,11 +188,82 @@ pub async unsafe fn start(opt: Options) -> anyhow::Result<()> {
         policies.clone(),
     );
     handle::policy_events(
-        opt.policy_path.clone(),
+        policy_path,
+        sources.clone(),
         maps.clone(),
         policies.clone(),
         containers.clone(),
     )?;
+    handle::dns_events(maps.clone(), policies.clone())?;
+
+    if let Some(metrics_addr) = opt.metrics_addr {
+        metrics::serve(metrics_addr, maps.clone(), containers.clone()).await?;
+    }
+
+    control::serve(
+        &opt.control_socket,
+        ControlService::new(sources, maps, policies, containers, pid_processes, events),
+    )?;
+
+    Ok(())
+}
+
+pub async unsafe fn learn(opt: Options, learn_opt: LearnOptions) -> anyhow::Result<()> {
+    if libc::geteuid() != 0 {
+        return Err(anyhow!("You must be root."));
+    }
+
+    setup_tracing(&opt)?;
+
+    process::check_cgroup_v2()?;
+
+    let container_engine = Runtime::new(&opt).await?;
+    let containers = Containers::new();
+
+    container_engine
+        .add_running_containers_inspect(containers.clone())
+        .await?;
+
+    let bpf = ebpf::load_bpf()?;
+    let loader = Loader::new(bpf.clone());
+
+    loader.attach_programs().await?;
+
+    let processes = process::get_all(containers.clone()).await;
+
+    // Without any policy every packet would be dropped, so nothing is enforced while learning.
+    let maps = Maps::new(bpf.clone());
+    maps.policy.save_audit_all().await?;
+    maps.container.save_ids(containers.clone()).await?;
+    maps.process.save_all(&processes).await?;
+
+    let learner = Learner::new(containers.clone(), learn_opt.containers.clone());
+    handle::learn_events(bpf.clone(), learner.clone()).await?;
+
+    info!("learning for {} seconds...", learn_opt.duration);
+
+    let mut sig_int = signal(SignalKind::interrupt())?;
+    let mut sig_term = signal(SignalKind::terminate())?;
+    select! {
+        _ = time::sleep(Duration::from_secs(learn_opt.duration)) => {},
+        _ = sig_int.recv() => {},
+        _ = sig_term.recv() => {},
+    }
+
+    learner.lock().await.write(&learn_opt.output)?;
+
+    info!("learned policies written to {}", learn_opt.output.display());
+
+    Ok(())
+}
+
+/// Needs neither root nor eBPF, so it can run in CI.
+pub fn validate(validate_opt: &ValidateOptions) -> anyhow::Result<()> {
+    validate::validate(&validate_opt.path)
+}
+
+pub fn schema() -> anyhow::Result<()> {
+    println!("{}", ParsePolicies::schema()?);
 
     Ok(())
 }
@@ -1,5 +1,7 @@
+use std::process;
+
 use clap::Parser;
-use furui::{self, Options};
+use furui::{self, Command, Options};
 use tokio::{
     select,
     signal::unix::{signal, SignalKind},
@@ -10,6 +12,21 @@ use tracing::info;
 async fn main() {
     let opt: Options = Options::parse();
 
+    // Nothing is attached by these, so there is nothing to clean up, and a failure has to show
+    // in the exit status.
+    let result = match &opt.command {
+        Some(Command::Validate(validate_opt)) => Some(furui::validate(validate_opt)),
+        Some(Command::Schema) => Some(furui::schema()),
+        _ => None,
+    };
+    if let Some(result) = result {
+        if let Err(err) = result {
+            println!("{}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
     match unsafe { try_main(opt.clone()).await } {
         Ok(_) => (),
         Err(err) => {
@@ -24,6 +41,10 @@ async fn main() {
 }
 
 async unsafe fn try_main(opt: Options) -> anyhow::Result<()> {
+    if let Some(Command::Learn(learn_opt)) = opt.command.clone() {
+        return furui::learn(opt, learn_opt).await;
+    }
+
     furui::start(opt).await?;
 
     let mut sig_int = signal(SignalKind::interrupt()).unwrap();
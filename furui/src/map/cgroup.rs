@@ -0,0 +1,116 @@
+use std::{collections::HashMap as StdHashMap, sync::Arc};
+
+use aya::Ebpf;
+use furui_common::CgroupKey;
+use tokio::sync::Mutex;
+
+use crate::{
+    domain::{self, Cgroup, Policies},
+    map::seen,
+    process,
+};
+
+/// Saves the name of the matching rule for every cgroup seen in the containers. As with the
+/// executables, the cgroups are kept so that the names can be saved again.
+pub struct CgroupMap {
+    bpf: Arc<Mutex<Ebpf>>,
+    cgroups: Mutex<StdHashMap<CgroupKey, SeenCgroup>>,
+}
+
+struct SeenCgroup {
+    container_id: String,
+    /// The path below the cgroup of the container.
+    path: String,
+}
+
+impl CgroupMap {
+    pub fn new(bpf: Arc<Mutex<Ebpf>>) -> CgroupMap {
+        CgroupMap {
+            bpf,
+            cgroups: Mutex::new(StdHashMap::new()),
+        }
+    }
+
+    /// Looks at the cgroup of a process the first time it is seen in its container. The path is
+    /// taken relative to the cgroup of the first process of the container, so the container has
+    /// to be known.
+    pub async fn save(
+        &self,
+        container_id: String,
+        pid: u32,
+        cgroup_id: u64,
+        container_pid: Option<u32>,
+        policies: &Policies,
+    ) -> anyhow::Result<()> {
+        let container_pid = match container_pid {
+            Some(container_pid) if cgroup_id != 0 && !container_id.is_empty() => container_pid,
+            _ => return Ok(()),
+        };
+
+        let mut key: CgroupKey = unsafe { std::mem::zeroed() };
+
+        key.container_id = domain::string_to_c_char_bytes(container_id.clone());
+        key.cgroup_id = cgroup_id;
+
+        let mut cgroups = self.cgroups.lock().await;
+        if cgroups.contains_key(&key) {
+            return Ok(());
+        }
+
+        let (root, path) = match (process::get_cgroup(container_pid), process::get_cgroup(pid)) {
+            (Some(root), Some(path)) => (root, path),
+            _ => return Ok(()),
+        };
+
+        let path = match path.strip_prefix(root.trim_end_matches('/')) {
+            Some("") => "/".to_string(),
+            Some(path) if path.starts_with('/') => path.to_string(),
+            // Not below the cgroup of the container, which no rule can name.
+            _ => return Ok(()),
+        };
+
+        let cgroup = cgroups
+            .entry(key)
+            .or_insert(SeenCgroup { container_id, path });
+
+        self.write(&key, cgroup, policies).await
+    }
+
+    /// Saves the names of all the cgroups seen again, after the policies or the containers
+    /// changed.
+    pub async fn update(&self, policies: &Policies) -> anyhow::Result<()> {
+        let cgroups = self.cgroups.lock().await;
+
+        for (key, cgroup) in cgroups.iter() {
+            self.write(key, cgroup, policies).await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn remove_container(&self, container_id: &str) -> anyhow::Result<()> {
+        let mut cgroups = self.cgroups.lock().await;
+
+        seen::remove(&self.bpf, "CGROUP_LIST", &mut cgroups, |_, seen| {
+            seen.container_id == container_id
+        })
+        .await
+    }
+
+    /// The deepest of the matching rules wins.
+    async fn write(
+        &self,
+        key: &CgroupKey,
+        cgroup: &SeenCgroup,
+        policies: &Policies,
+    ) -> anyhow::Result<()> {
+        let comm = policies
+            .cgroups(&cgroup.container_id)
+            .into_iter()
+            .filter(|rule| rule.matches(&cgroup.path))
+            .max_by_key(|rule| rule.path.trim_end_matches('/').len())
+            .map(Cgroup::comm);
+
+        seen::write(&self.bpf, "CGROUP_LIST", key, comm).await
+    }
+}
@@ -15,11 +15,11 @@ impl ContainerMap {
         ContainerMap { bpf }
     }
 
-    pub async fn save_id_with_ips(
-        &self,
-        containers: Arc<Mutex<domain::Containers>>,
-    ) -> anyhow::Result<()> {
+    /// Saves the ID of every container by its addresses, for the classifiers, and by its cgroup,
+    /// for the kprobes.
+    pub async fn save_ids(&self, containers: Arc<Mutex<domain::Containers>>) -> anyhow::Result<()> {
         let mut bpf = self.bpf.lock().await;
+
         let mut map = HashMap::try_from(bpf.map_mut("CONTAINER_ID_FROM_IPS").unwrap())?;
         for container in containers.lock().await.list() {
             for ip in container.ip_addresses.as_ref().unwrap() {
@@ -27,18 +27,31 @@ impl ContainerMap {
             }
         }
 
+        let mut map = HashMap::try_from(bpf.map_mut("CONTAINER_ID_FROM_CGROUPS").unwrap())?;
+        for container in containers.lock().await.list() {
+            if container.cgroup_id != 0 {
+                map.insert(container.cgroup_id, ContainerID::new(container.id()), 0)?;
+            }
+        }
+
         Ok(())
     }
 
-    pub async fn remove_id_from_ips(&self, container: domain::Container) -> anyhow::Result<()> {
+    pub async fn remove_ids(&self, container: domain::Container) -> anyhow::Result<()> {
         let mut bpf = self.bpf.lock().await;
+
         let mut map: HashMap<_, ContainerIP, ContainerID> =
             HashMap::try_from(bpf.map_mut("CONTAINER_ID_FROM_IPS").unwrap())?;
-
         for ip in container.ip_addresses.unwrap() {
             map.remove(&ContainerIP::new(ip))?;
         }
 
+        let mut map: HashMap<_, u64, ContainerID> =
+            HashMap::try_from(bpf.map_mut("CONTAINER_ID_FROM_CGROUPS").unwrap())?;
+        if container.cgroup_id != 0 {
+            map.remove(&container.cgroup_id)?;
+        }
+
         Ok(())
     }
 }
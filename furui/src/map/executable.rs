@@ -0,0 +1,229 @@
+use std::{
+    collections::HashMap as StdHashMap,
+    fs::{self, File, Metadata},
+    io,
+    os::unix::fs::MetadataExt,
+    path::Path,
+    sync::Arc,
+};
+
+use aya::Ebpf;
+use furui_common::ExeKey;
+use sha2::{Digest, Sha256};
+use tokio::{sync::Mutex, task};
+
+use crate::{
+    domain::{self, Executable, Policies},
+    map::seen,
+};
+
+/// Saves the name of the matching rule for every executable seen in the containers. The
+/// executables are kept, so that the names can be saved again when the policies or the
+/// containers change.
+pub struct ExecutableMap {
+    bpf: Arc<Mutex<Ebpf>>,
+    executables: Mutex<StdHashMap<ExeKey, SeenExecutable>>,
+}
+
+struct SeenExecutable {
+    container_id: String,
+    pid: u32,
+    path: String,
+    sha256: Option<(FileStamp, String)>,
+}
+
+/// What a binary looked like when it was hashed. A binary rewritten in place keeps its inode, so
+/// it is hashed again once any of these changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileStamp {
+    size: u64,
+    mtime: (i64, i64),
+    ctime: (i64, i64),
+}
+
+impl FileStamp {
+    fn of(metadata: &Metadata) -> FileStamp {
+        FileStamp {
+            size: metadata.size(),
+            mtime: (metadata.mtime(), metadata.mtime_nsec()),
+            ctime: (metadata.ctime(), metadata.ctime_nsec()),
+        }
+    }
+}
+
+impl SeenExecutable {
+    fn matches(&self, executable: &Executable) -> bool {
+        self.path == executable.path
+            && (executable.sha256.is_none()
+                || self.sha256.as_ref().map(|(_, sha256)| sha256) == executable.sha256.as_ref())
+    }
+
+    /// The hash is only computed for the rules asking for it, and off the async threads, as
+    /// hashing a large binary takes a while.
+    async fn hash(&mut self, rules: &[&Executable]) {
+        if !rules
+            .iter()
+            .any(|rule| rule.path == self.path && rule.sha256.is_some())
+        {
+            return;
+        }
+
+        let path = Path::new("/proc").join(self.pid.to_string()).join("exe");
+        let hashed = self.sha256.take();
+
+        self.sha256 = task::spawn_blocking(move || file_sha256(&path, hashed))
+            .await
+            .ok()
+            .and_then(Result::ok);
+    }
+}
+
+impl ExecutableMap {
+    pub fn new(bpf: Arc<Mutex<Ebpf>>) -> ExecutableMap {
+        ExecutableMap {
+            bpf,
+            executables: Mutex::new(StdHashMap::new()),
+        }
+    }
+
+    /// Looks at the executable of a process the first time it is seen in its container. A hashed
+    /// executable is looked at on every exec, in case it was rewritten in place since.
+    pub async fn save(
+        &self,
+        container_id: String,
+        pid: u32,
+        ino: u64,
+        dev: u32,
+        policies: &Policies,
+    ) -> anyhow::Result<()> {
+        if ino == 0 || container_id.is_empty() {
+            return Ok(());
+        }
+
+        let key = ExeKey {
+            container_id: domain::string_to_c_char_bytes(container_id.clone()),
+            dev,
+            ino,
+        };
+
+        let mut executables = self.executables.lock().await;
+        if let Some(executable) = executables.get_mut(&key) {
+            if executable.sha256.is_none() {
+                return Ok(());
+            }
+
+            // The process hashed before may be gone by now.
+            executable.pid = pid;
+            return self.write(&key, executable, policies).await;
+        }
+
+        // A replaced binary reads as `path (deleted)` and matches no rule.
+        let path = match fs::read_link(Path::new("/proc").join(pid.to_string()).join("exe")) {
+            Ok(path) => path.to_string_lossy().to_string(),
+            Err(_) => return Ok(()),
+        };
+
+        let executable = executables.entry(key).or_insert(SeenExecutable {
+            container_id,
+            pid,
+            path,
+            sha256: None,
+        });
+
+        self.write(&key, executable, policies).await
+    }
+
+    /// Saves the names of all the executables seen again, after the policies or the containers
+    /// changed.
+    pub async fn update(&self, policies: &Policies) -> anyhow::Result<()> {
+        let mut executables = self.executables.lock().await;
+
+        for (key, executable) in executables.iter_mut() {
+            self.write(key, executable, policies).await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn remove_container(&self, container_id: &str) -> anyhow::Result<()> {
+        let mut executables = self.executables.lock().await;
+
+        seen::remove(&self.bpf, "EXE_LIST", &mut executables, |_, seen| {
+            seen.container_id == container_id
+        })
+        .await
+    }
+
+    async fn write(
+        &self,
+        key: &ExeKey,
+        executable: &mut SeenExecutable,
+        policies: &Policies,
+    ) -> anyhow::Result<()> {
+        let rules = policies.executables(&executable.container_id);
+        executable.hash(&rules).await;
+
+        let comm = rules
+            .into_iter()
+            .find(|rule| executable.matches(rule))
+            .map(Executable::comm);
+
+        seen::write(&self.bpf, "EXE_LIST", key, comm).await
+    }
+}
+
+/// Hashes the file unless it is unchanged since it was `hashed`.
+fn file_sha256(
+    path: &Path,
+    hashed: Option<(FileStamp, String)>,
+) -> io::Result<(FileStamp, String)> {
+    let mut file = File::open(path)?;
+    let stamp = FileStamp::of(&file.metadata()?);
+
+    if let Some((hashed_stamp, sha256)) = hashed {
+        if hashed_stamp == stamp {
+            return Ok((stamp, sha256));
+        }
+    }
+
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+
+    Ok((stamp, format!("{:x}", hasher.finalize())))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs::OpenOptions, io::Write, process};
+
+    use super::*;
+
+    #[test]
+    fn a_binary_rewritten_in_place_is_hashed_again() {
+        let path = env::temp_dir().join(format!("furui-exe-{}", process::id()));
+        fs::write(&path, b"original").unwrap();
+
+        let hashed = file_sha256(&path, None).unwrap();
+        assert_eq!(
+            hashed.1,
+            "0682c5f2076f099c34cfdd15a9e063849ed437a49677e6fcc5b4198c76575be5"
+        );
+        assert_eq!(file_sha256(&path, Some(hashed.clone())).unwrap(), hashed);
+
+        // Truncating keeps the inode, like a binary overwritten by `cp`.
+        let mut file = OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(b"rewritten binary").unwrap();
+        drop(file);
+
+        let rehashed = file_sha256(&path, Some(hashed.clone())).unwrap();
+        assert_ne!(rehashed.0, hashed.0);
+        assert_eq!(rehashed.1, file_sha256(&path, None).unwrap().1);
+        assert_ne!(rehashed.1, hashed.1);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
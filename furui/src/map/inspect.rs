@@ -0,0 +1,411 @@
+use std::{convert::TryFrom, net::IpAddr, sync::Arc};
+
+use aya::{
+    maps::{lpm_trie::Key, HashMap, LpmTrie, MapData},
+    Ebpf,
+};
+use furui_common::{
+    cidr_ip, CgroupKey, ContainerID, ContainerIP, Egress6Event, EgressEvent, ExeKey, ExeValue,
+    FqdnIpKey, FqdnIpValue, FqdnPolicyKey, Ingress6Event, IngressEvent, IpProtocol, PolicyCidrKey,
+    PolicyKey, PolicyMatch, PolicyValue, PortKey, PortRangePolicies, PortRangePolicyKey, PortVal,
+    ProcessTreeKey, TcAction, ALL_CONTAINERS, CONTAINER_ID_LEN, FQDN_IP_NAMES_MAX, IPV6_LEN,
+    TASK_COMM_LEN,
+};
+use tokio::sync::Mutex;
+
+use crate::domain;
+
+/// Reads the maps the classifiers search, to show what they hold and what they would do with a
+/// packet.
+pub struct InspectMap {
+    bpf: Arc<Mutex<Ebpf>>,
+}
+
+/// A TCP or UDP packet of a container.
+pub struct Packet {
+    pub ingress: bool,
+    pub protocol: IpProtocol,
+    pub local_port: u16,
+    pub remote_ip: IpAddr,
+    pub remote_port: u16,
+}
+
+pub struct Verdict {
+    pub action: TcAction,
+    /// The packet is passed anyway, as the container is in audit mode.
+    pub would_drop: bool,
+    /// The name the rules of the process were searched under, if a process bound the port.
+    pub comm: Option<[u8; TASK_COMM_LEN]>,
+    /// The map that decided, or why none did.
+    pub decided_by: &'static str,
+}
+
+impl InspectMap {
+    pub fn new(bpf: Arc<Mutex<Ebpf>>) -> InspectMap {
+        InspectMap { bpf }
+    }
+
+    pub async fn policies(&self) -> anyhow::Result<Vec<(PolicyKey, PolicyValue)>> {
+        let bpf = self.bpf.lock().await;
+        let map: HashMap<_, PolicyKey, PolicyValue> =
+            HashMap::try_from(bpf.map("POLICY_LIST").unwrap())?;
+
+        Ok(map.iter().collect::<Result<_, _>>()?)
+    }
+
+    pub async fn ports(&self) -> anyhow::Result<Vec<(PortKey, PortVal)>> {
+        let bpf = self.bpf.lock().await;
+        let map: HashMap<_, PortKey, PortVal> = HashMap::try_from(bpf.map("PROC_PORTS").unwrap())?;
+
+        Ok(map.iter().collect::<Result<_, _>>()?)
+    }
+
+    pub async fn container_ips(&self) -> anyhow::Result<Vec<(ContainerIP, ContainerID)>> {
+        let bpf = self.bpf.lock().await;
+        let map: HashMap<_, ContainerIP, ContainerID> =
+            HashMap::try_from(bpf.map("CONTAINER_ID_FROM_IPS").unwrap())?;
+
+        Ok(map.iter().collect::<Result<_, _>>()?)
+    }
+
+    /// Searches the maps the way the TCP and UDP classifiers do, see `furui-ebpf/src/README.md`.
+    pub async fn check(&self, container_id: &str, packet: &Packet) -> anyhow::Result<Verdict> {
+        let bpf = self.bpf.lock().await;
+        let maps = Maps::new(&bpf)?;
+
+        let container_id = domain::string_to_c_char_bytes(container_id.to_string());
+
+        // The fields `search_key` reads are the ones the classifiers fill in.
+        let verdict = unsafe {
+            match (packet.ingress, packet.remote_ip) {
+                (true, IpAddr::V4(remote_ip)) => {
+                    let mut event: IngressEvent = std::mem::zeroed();
+                    event.saddr = remote_ip.into();
+                    event.sport = packet.remote_port;
+                    event.dport = packet.local_port;
+                    event.protocol = packet.protocol;
+                    maps.check(event, container_id, packet)
+                }
+                (true, IpAddr::V6(remote_ip)) => {
+                    let mut event: Ingress6Event = std::mem::zeroed();
+                    event.saddr = remote_ip.octets();
+                    event.sport = packet.remote_port;
+                    event.dport = packet.local_port;
+                    event.protocol = packet.protocol;
+                    maps.check(event, container_id, packet)
+                }
+                (false, IpAddr::V4(remote_ip)) => {
+                    let mut event: EgressEvent = std::mem::zeroed();
+                    event.daddr = remote_ip.into();
+                    event.sport = packet.local_port;
+                    event.dport = packet.remote_port;
+                    event.protocol = packet.protocol;
+                    maps.check(event, container_id, packet)
+                }
+                (false, IpAddr::V6(remote_ip)) => {
+                    let mut event: Egress6Event = std::mem::zeroed();
+                    event.daddr = remote_ip.octets();
+                    event.sport = packet.local_port;
+                    event.dport = packet.remote_port;
+                    event.protocol = packet.protocol;
+                    maps.check(event, container_id, packet)
+                }
+            }
+        };
+
+        Ok(verdict)
+    }
+}
+
+/// The searches `SearchPolicyKey` derives for the events of the TCP and UDP classifiers.
+trait SearchPolicy {
+    fn search_key<F: Fn(&PolicyKey) -> Option<TcAction>>(
+        &mut self,
+        policy_key: &mut PolicyKey,
+        callback: F,
+    ) -> Option<PolicyMatch>;
+
+    fn search_cidr_key<F: Fn(&PolicyCidrKey) -> Option<TcAction>>(
+        &mut self,
+        policy_key: &mut PolicyCidrKey,
+        callback: F,
+    ) -> Option<PolicyMatch>;
+
+    fn search_fqdn_key<F: Fn(&FqdnPolicyKey) -> Option<TcAction>>(
+        &mut self,
+        policy_key: &mut FqdnPolicyKey,
+        callback: F,
+    ) -> Option<PolicyMatch>;
+}
+
+macro_rules! impl_search_policy {
+    ($($event:ty),*) => {
+        $(
+            impl SearchPolicy for $event {
+                fn search_key<F: Fn(&PolicyKey) -> Option<TcAction>>(
+                    &mut self,
+                    policy_key: &mut PolicyKey,
+                    callback: F,
+                ) -> Option<PolicyMatch> {
+                    <$event>::search_key(self, policy_key, callback)
+                }
+
+                fn search_cidr_key<F: Fn(&PolicyCidrKey) -> Option<TcAction>>(
+                    &mut self,
+                    policy_key: &mut PolicyCidrKey,
+                    callback: F,
+                ) -> Option<PolicyMatch> {
+                    <$event>::search_cidr_key(self, policy_key, callback)
+                }
+
+                fn search_fqdn_key<F: Fn(&FqdnPolicyKey) -> Option<TcAction>>(
+                    &mut self,
+                    policy_key: &mut FqdnPolicyKey,
+                    callback: F,
+                ) -> Option<PolicyMatch> {
+                    <$event>::search_fqdn_key(self, policy_key, callback)
+                }
+            }
+        )*
+    };
+}
+
+impl_search_policy!(IngressEvent, Ingress6Event, EgressEvent, Egress6Event);
+
+struct Maps<'a> {
+    proc_ports: HashMap<&'a MapData, PortKey, PortVal>,
+    exe_list: HashMap<&'a MapData, ExeKey, ExeValue>,
+    process_tree_list: HashMap<&'a MapData, ProcessTreeKey, ExeValue>,
+    cgroup_list: HashMap<&'a MapData, CgroupKey, ExeValue>,
+    policy_list: HashMap<&'a MapData, PolicyKey, PolicyValue>,
+    policy_cidr_list: LpmTrie<&'a MapData, PolicyCidrKey, PolicyValue>,
+    fqdn_ips: HashMap<&'a MapData, FqdnIpKey, FqdnIpValue>,
+    fqdn_policy_list: HashMap<&'a MapData, FqdnPolicyKey, PolicyValue>,
+    port_range_policy_list: HashMap<&'a MapData, PortRangePolicyKey, PortRangePolicies>,
+    default_actions: HashMap<&'a MapData, ContainerID, TcAction>,
+    audit_containers: HashMap<&'a MapData, ContainerID, u8>,
+}
+
+impl<'a> Maps<'a> {
+    fn new(bpf: &'a Ebpf) -> anyhow::Result<Maps<'a>> {
+        Ok(Maps {
+            proc_ports: HashMap::try_from(bpf.map("PROC_PORTS").unwrap())?,
+            exe_list: HashMap::try_from(bpf.map("EXE_LIST").unwrap())?,
+            process_tree_list: HashMap::try_from(bpf.map("PROCESS_TREE_LIST").unwrap())?,
+            cgroup_list: HashMap::try_from(bpf.map("CGROUP_LIST").unwrap())?,
+            policy_list: HashMap::try_from(bpf.map("POLICY_LIST").unwrap())?,
+            policy_cidr_list: LpmTrie::try_from(bpf.map("POLICY_CIDR_LIST").unwrap())?,
+            fqdn_ips: HashMap::try_from(bpf.map("FQDN_IPS").unwrap())?,
+            fqdn_policy_list: HashMap::try_from(bpf.map("FQDN_POLICY_LIST").unwrap())?,
+            port_range_policy_list: HashMap::try_from(bpf.map("PORT_RANGE_POLICY_LIST").unwrap())?,
+            default_actions: HashMap::try_from(bpf.map("DEFAULT_ACTIONS").unwrap())?,
+            audit_containers: HashMap::try_from(bpf.map("AUDIT_CONTAINERS").unwrap())?,
+        })
+    }
+
+    unsafe fn check<E: SearchPolicy>(
+        &self,
+        mut event: E,
+        container_id: [std::os::raw::c_char; CONTAINER_ID_LEN],
+        packet: &Packet,
+    ) -> Verdict {
+        let verdict = |action, comm, decided_by| Verdict {
+            action,
+            would_drop: action == TcAction::Drop && self.is_audit(container_id),
+            comm,
+            decided_by,
+        };
+
+        let mut port_key: PortKey = std::mem::zeroed();
+
+        port_key.container_id = container_id;
+        port_key.port = packet.local_port;
+        port_key.proto = packet.protocol;
+
+        let port_val = match self.proc_ports.get(&port_key, 0) {
+            Ok(port_val) => port_val,
+            Err(_) => {
+                return verdict(
+                    self.default_action(container_id),
+                    None,
+                    "DEFAULT_ACTIONS, as no process bound the port",
+                )
+            }
+        };
+
+        // Each name the process goes by is searched, and the most specific policy found for any
+        // of them decides. The list and the name it was found under are told.
+        let mut found: Option<(PolicyMatch, &'static str, [u8; TASK_COMM_LEN])> = None;
+        let mut prefer = |other: Option<PolicyMatch>, list: &'static str, comm| {
+            let current = found.map(|(current, _, _)| current);
+            if PolicyMatch::most_specific(current, other) != current {
+                found = other.map(|other| (other, list, comm));
+            }
+        };
+
+        for comm in self.rule_comms(container_id, &port_val) {
+            let mut policy_key: PolicyKey = std::mem::zeroed();
+
+            policy_key.container_id = container_id;
+            policy_key.comm = comm;
+
+            prefer(
+                event.search_key(&mut policy_key, |policy_key| {
+                    self.policy_list
+                        .get(policy_key, 0)
+                        .ok()
+                        .map(|policy_val| policy_val.action)
+                }),
+                "POLICY_LIST",
+                comm,
+            );
+
+            let remote_ip: [u8; IPV6_LEN] = cidr_ip(packet.remote_ip);
+
+            let mut cidr_key: PolicyCidrKey = std::mem::zeroed();
+
+            cidr_key.container_id = container_id;
+            cidr_key.comm = comm;
+            cidr_key.remote_ip = remote_ip;
+
+            prefer(
+                event.search_cidr_key(&mut cidr_key, |cidr_key| {
+                    self.policy_cidr_list
+                        .get(&Key::new(PolicyCidrKey::LOOKUP_PREFIX_LEN, *cidr_key), 0)
+                        .ok()
+                        .map(|policy_val| policy_val.action)
+                }),
+                "POLICY_CIDR_LIST",
+                comm,
+            );
+
+            let mut fqdn_ip_key: FqdnIpKey = std::mem::zeroed();
+
+            fqdn_ip_key.container_id = container_id;
+            fqdn_ip_key.ip = remote_ip;
+
+            let fqdn_ids = self
+                .fqdn_ips
+                .get(&fqdn_ip_key, 0)
+                .map_or([None; FQDN_IP_NAMES_MAX], |fqdn_ip_val| {
+                    fqdn_ip_val.fqdn_ids(ktime_ns())
+                });
+
+            for fqdn_id in fqdn_ids.into_iter().flatten() {
+                let mut fqdn_key: FqdnPolicyKey = std::mem::zeroed();
+
+                fqdn_key.container_id = container_id;
+                fqdn_key.comm = comm;
+                fqdn_key.fqdn_id = fqdn_id;
+
+                prefer(
+                    event.search_fqdn_key(&mut fqdn_key, |fqdn_key| {
+                        self.fqdn_policy_list
+                            .get(fqdn_key, 0)
+                            .ok()
+                            .map(|policy_val| policy_val.action)
+                    }),
+                    "FQDN_POLICY_LIST",
+                    comm,
+                );
+            }
+
+            let mut range_key: PortRangePolicyKey = std::mem::zeroed();
+
+            range_key.container_id = container_id;
+            range_key.comm = comm;
+
+            for key_ip in PortRangePolicyKey::remote_ips(&remote_ip) {
+                range_key.remote_ip = key_ip;
+
+                if let Ok(range_policies) = self.port_range_policy_list.get(&range_key, 0) {
+                    prefer(
+                        range_policies.search(
+                            packet.protocol,
+                            packet.local_port,
+                            packet.remote_port,
+                            &remote_ip,
+                        ),
+                        "PORT_RANGE_POLICY_LIST",
+                        comm,
+                    );
+                }
+            }
+        }
+
+        match found {
+            Some((found, list, comm)) => verdict(found.action, Some(comm), list),
+            None => verdict(
+                self.default_action(container_id),
+                Some(port_val.comm),
+                "DEFAULT_ACTIONS, as no policy matched",
+            ),
+        }
+    }
+
+    /// The names the classifiers search the rules of a process under, see `rule_comms` in
+    /// furui-ebpf.
+    unsafe fn rule_comms(
+        &self,
+        container_id: [std::os::raw::c_char; CONTAINER_ID_LEN],
+        port_val: &PortVal,
+    ) -> Vec<[u8; TASK_COMM_LEN]> {
+        let mut exe_key: ExeKey = std::mem::zeroed();
+
+        exe_key.container_id = container_id;
+        exe_key.ino = port_val.exe_ino;
+        exe_key.dev = port_val.exe_dev;
+
+        let mut tree_key: ProcessTreeKey = std::mem::zeroed();
+
+        tree_key.container_id = container_id;
+        tree_key.pid = port_val.pid;
+
+        let mut cgroup_key: CgroupKey = std::mem::zeroed();
+
+        cgroup_key.container_id = container_id;
+        cgroup_key.cgroup_id = port_val.cgroup_id;
+
+        [
+            self.exe_list
+                .get(&exe_key, 0)
+                .ok()
+                .map(|exe_val| exe_val.comm),
+            self.process_tree_list
+                .get(&tree_key, 0)
+                .ok()
+                .map(|tree_val| tree_val.comm),
+            self.cgroup_list
+                .get(&cgroup_key, 0)
+                .ok()
+                .map(|cgroup_val| cgroup_val.comm),
+            Some(port_val.comm),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    fn is_audit(&self, container_id: [std::os::raw::c_char; CONTAINER_ID_LEN]) -> bool {
+        self.audit_containers.get(&ALL_CONTAINERS, 0).is_ok()
+            || self
+                .audit_containers
+                .get(&ContainerID::new(container_id), 0)
+                .is_ok()
+    }
+
+    fn default_action(&self, container_id: [std::os::raw::c_char; CONTAINER_ID_LEN]) -> TcAction {
+        self.default_actions
+            .get(&ContainerID::new(container_id), 0)
+            .or_else(|_| self.default_actions.get(&ALL_CONTAINERS, 0))
+            .unwrap_or(TcAction::Drop)
+    }
+}
+
+/// The time `bpf_ktime_get_ns` tells in the classifiers.
+fn ktime_ns() -> u64 {
+    let mut ts: libc::timespec = unsafe { std::mem::zeroed() };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
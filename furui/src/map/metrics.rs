@@ -0,0 +1,70 @@
+use std::{convert::TryFrom, sync::Arc};
+
+use aya::{
+    maps::{PerCpuArray, PerCpuHashMap, PerCpuValues},
+    Ebpf,
+};
+use furui_common::{PacketCount, PacketCountKey};
+use tokio::sync::Mutex;
+
+use crate::domain;
+
+pub struct MetricsMap {
+    bpf: Arc<Mutex<Ebpf>>,
+}
+
+impl MetricsMap {
+    pub fn new(bpf: Arc<Mutex<Ebpf>>) -> MetricsMap {
+        MetricsMap { bpf }
+    }
+
+    /// The packets the classifiers counted, summed over the CPUs.
+    pub async fn packet_counts(&self) -> anyhow::Result<Vec<(PacketCountKey, PacketCount)>> {
+        let bpf = self.bpf.lock().await;
+        let map: PerCpuHashMap<_, PacketCountKey, PacketCount> =
+            PerCpuHashMap::try_from(bpf.map("PACKET_COUNTS").unwrap())?;
+
+        let mut counts = vec![];
+        for entry in map.iter() {
+            let (key, values): (_, PerCpuValues<PacketCount>) = entry?;
+
+            let count = values
+                .iter()
+                .fold(PacketCount::default(), |sum, value| PacketCount {
+                    packets: sum.packets + value.packets,
+                    bytes: sum.bytes + value.bytes,
+                });
+
+            counts.push((key, count));
+        }
+
+        Ok(counts)
+    }
+
+    /// The events the programs could not send, as the ring buffer was full.
+    pub async fn events_lost(&self) -> anyhow::Result<u64> {
+        let bpf = self.bpf.lock().await;
+        let map: PerCpuArray<_, u64> = PerCpuArray::try_from(bpf.map("EVENTS_LOST").unwrap())?;
+
+        Ok(map.get(&0, 0)?.iter().sum())
+    }
+
+    /// The counters of a container that stopped would take room in the map forever.
+    pub async fn remove_container(&self, container_id: &str) -> anyhow::Result<()> {
+        let mut bpf = self.bpf.lock().await;
+        let mut map: PerCpuHashMap<_, PacketCountKey, PacketCount> =
+            PerCpuHashMap::try_from(bpf.map_mut("PACKET_COUNTS").unwrap())?;
+
+        let keys = map
+            .keys()
+            .filter_map(|key| key.ok())
+            .filter(|key| domain::c_char_bytes_to_string(key.container_id) == container_id)
+            .collect::<Vec<_>>();
+
+        for key in keys {
+            map.remove(&key)?;
+        }
+
+        Ok(())
+    }
+}
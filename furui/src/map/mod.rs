@@ -1,27 +1,48 @@
 use std::sync::Arc;
 
 use aya::Ebpf;
+pub use cgroup::CgroupMap;
 pub use container::ContainerMap;
+pub use executable::ExecutableMap;
+pub use inspect::{InspectMap, Packet};
+pub use metrics::MetricsMap;
 pub use policy::PolicyMap;
 pub use process::ProcessMap;
+pub use process_tree::ProcessTreeMap;
 use tokio::sync::Mutex;
 
+mod cgroup;
 mod container;
+mod executable;
+mod inspect;
+mod metrics;
 mod policy;
 mod process;
+mod process_tree;
+mod seen;
 
 pub struct Maps {
+    pub cgroup: CgroupMap,
     pub container: ContainerMap,
+    pub executable: ExecutableMap,
+    pub inspect: InspectMap,
+    pub metrics: MetricsMap,
     pub policy: PolicyMap,
     pub process: ProcessMap,
+    pub process_tree: ProcessTreeMap,
 }
 
 impl Maps {
     pub fn new(bpf: Arc<Mutex<Ebpf>>) -> Arc<Maps> {
         Arc::new(Maps {
+            cgroup: CgroupMap::new(bpf.clone()),
             container: ContainerMap::new(bpf.clone()),
+            executable: ExecutableMap::new(bpf.clone()),
+            inspect: InspectMap::new(bpf.clone()),
+            metrics: MetricsMap::new(bpf.clone()),
             policy: PolicyMap::new(bpf.clone()),
             process: ProcessMap::new(bpf.clone()),
+            process_tree: ProcessTreeMap::new(bpf.clone()),
         })
     }
 }
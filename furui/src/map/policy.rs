@@ -1,126 +1,538 @@
-use std::{convert::TryFrom, net::IpAddr, sync::Arc};
+use std::{
+    collections::HashMap as StdHashMap,
+    convert::TryFrom,
+    hash::Hash,
+    io,
+    net::IpAddr,
+    ops::RangeInclusive,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
 use anyhow::anyhow;
-use aya::{maps::HashMap, Ebpf};
-use furui_common::{IcmpPolicyKey, IcmpPolicyValue, PolicyKey, PolicyValue};
+use aya::{
+    maps::{
+        lpm_trie::{Key, LpmTrie},
+        HashMap, MapData, MapError,
+    },
+    sys::SyscallError,
+    Ebpf, Pod,
+};
+use furui_common::{
+    cidr_ip, cidr_mask, encode_fqdn, fqdn_id, search_rank, ContainerID, FqdnKey, FqdnPolicyKey,
+    IcmpPolicyCidrKey, IcmpPolicyKey, IcmpPolicyValue, IpProtocol, PolicyCidrKey, PolicyKey,
+    PolicyList, PolicyValue, PortRangePolicies, PortRangePolicy, PortRangePolicyKey, TcAction,
+    ALL_CONTAINERS, IPV6_LEN, PORT_RANGE_POLICIES_MAX, TASK_COMM_LEN,
+};
 use tokio::sync::Mutex;
+use tracing::warn;
 
 use crate::domain;
 
 pub struct PolicyMap {
     bpf: Arc<Mutex<Ebpf>>,
+    /// Set when the maps could not be put back to the old policies after a failed update, so
+    /// they may hold entries of neither. The next update rebuilds them instead of writing only
+    /// the differences.
+    needs_rebuild: AtomicBool,
+    /// Set by `save_audit_all`, which audits every container whatever the policies say.
+    audit_all: AtomicBool,
 }
 
 impl PolicyMap {
     pub fn new(bpf: Arc<Mutex<Ebpf>>) -> PolicyMap {
-        PolicyMap { bpf }
+        PolicyMap {
+            bpf,
+            needs_rebuild: AtomicBool::new(false),
+            audit_all: AtomicBool::new(false),
+        }
     }
 
     pub async fn save(&self, policies: Arc<Mutex<domain::Policies>>) -> anyhow::Result<()> {
-        unsafe {
-            self.save_policy_list(policies.clone()).await?;
-            self.save_icmp_policy_list(policies.clone()).await?;
+        let entries = unsafe { self.entries(&*policies.lock().await)? };
+
+        self.write(None, &entries).await
+    }
+
+    /// Writes only the entries that differ between the two policies, so the traffic both of them
+    /// allow keeps flowing while the maps are updated.
+    ///
+    /// The maps are written one after another. When one of them fails, those written before it
+    /// are put back to the old policies, so that the maps hold what the caller keeps and the next
+    /// update is taken against it.
+    pub async fn update(
+        &self,
+        old_policies: &domain::Policies,
+        new_policies: &domain::Policies,
+    ) -> anyhow::Result<()> {
+        let (old_entries, new_entries) =
+            unsafe { (self.entries(old_policies)?, self.entries(new_policies)?) };
+
+        let written = if self.needs_rebuild.load(Ordering::SeqCst) {
+            self.write(None, &new_entries).await
+        } else {
+            self.write(Some(&old_entries), &new_entries).await
+        };
+
+        let err = match written {
+            Ok(()) => {
+                self.needs_rebuild.store(false, Ordering::SeqCst);
+                return Ok(());
+            }
+            Err(err) => err,
+        };
+
+        match self.write(None, &old_entries).await {
+            Ok(()) => {
+                self.needs_rebuild.store(false, Ordering::SeqCst);
+                Err(err)
+            }
+            Err(restore_err) => {
+                self.needs_rebuild.store(true, Ordering::SeqCst);
+                Err(anyhow!(
+                    "{}, and failed to restore the previous policies: {}",
+                    err,
+                    restore_err
+                ))
+            }
         }
+    }
 
-        Ok(())
+    /// The entries of the policies, which audit every container after `save_audit_all`.
+    unsafe fn entries(&self, policies: &domain::Policies) -> anyhow::Result<Entries> {
+        let mut entries = Entries::new(policies)?;
+
+        if self.audit_all.load(Ordering::SeqCst) {
+            entries.audit_list.insert(ALL_CONTAINERS, 1u8);
+        }
+
+        Ok(entries)
     }
 
-    async unsafe fn save_policy_list(
+    /// Writes the differences between the old and the new entries, or without the old entries,
+    /// makes the maps hold the new entries whatever they hold now.
+    async fn write(
         &self,
-        policies: Arc<Mutex<domain::Policies>>,
+        old_entries: Option<&Entries>,
+        new_entries: &Entries,
     ) -> anyhow::Result<()> {
         let mut bpf = self.bpf.lock().await;
-        let mut policy_list = HashMap::try_from(bpf.map_mut("POLICY_LIST").unwrap())?;
 
-        for policy in &policies.lock().await.policies {
-            for communication in &policy.communications {
-                let mut key: PolicyKey = std::mem::zeroed();
+        update_hash_map(
+            HashMap::try_from(bpf.map_mut("POLICY_LIST").unwrap())?,
+            old_entries.map(|old_entries| &old_entries.policy_list),
+            &new_entries.policy_list,
+        )?;
+        update_lpm_trie(
+            LpmTrie::try_from(bpf.map_mut("POLICY_CIDR_LIST").unwrap())?,
+            old_entries.map(|old_entries| &old_entries.policy_cidr_list),
+            &new_entries.policy_cidr_list,
+        )?;
+        update_hash_map(
+            HashMap::try_from(bpf.map_mut("PORT_RANGE_POLICY_LIST").unwrap())?,
+            old_entries.map(|old_entries| &old_entries.port_range_policy_list),
+            &new_entries.port_range_policy_list,
+        )?;
+        update_hash_map(
+            HashMap::try_from(bpf.map_mut("FQDN_LIST").unwrap())?,
+            old_entries.map(|old_entries| &old_entries.fqdn_list),
+            &new_entries.fqdn_list,
+        )?;
+        update_hash_map(
+            HashMap::try_from(bpf.map_mut("FQDN_POLICY_LIST").unwrap())?,
+            old_entries.map(|old_entries| &old_entries.fqdn_policy_list),
+            &new_entries.fqdn_policy_list,
+        )?;
+        update_hash_map(
+            HashMap::try_from(bpf.map_mut("ICMP_POLICY_LIST").unwrap())?,
+            old_entries.map(|old_entries| &old_entries.icmp_policy_list),
+            &new_entries.icmp_policy_list,
+        )?;
+        update_lpm_trie(
+            LpmTrie::try_from(bpf.map_mut("ICMP_POLICY_CIDR_LIST").unwrap())?,
+            old_entries.map(|old_entries| &old_entries.icmp_policy_cidr_list),
+            &new_entries.icmp_policy_cidr_list,
+        )?;
+        update_hash_map(
+            HashMap::try_from(bpf.map_mut("AUDIT_CONTAINERS").unwrap())?,
+            old_entries.map(|old_entries| &old_entries.audit_list),
+            &new_entries.audit_list,
+        )?;
+        update_hash_map(
+            HashMap::try_from(bpf.map_mut("DEFAULT_ACTIONS").unwrap())?,
+            old_entries.map(|old_entries| &old_entries.default_actions),
+            &new_entries.default_actions,
+        )?;
 
-                key.container_id = policy.container.id();
-                key.comm = communication.process();
+        Ok(())
+    }
 
-                let mut value: PolicyValue = std::mem::zeroed();
+    pub async fn save_audit_all(&self) -> anyhow::Result<()> {
+        let mut bpf = self.bpf.lock().await;
+        let mut audit_list = HashMap::try_from(bpf.map_mut("AUDIT_CONTAINERS").unwrap())?;
 
-                value.comm = communication.process();
+        audit_list.insert(ALL_CONTAINERS, 1u8, 0)?;
+        self.audit_all.store(true, Ordering::SeqCst);
 
-                if communication.sockets.len() == 0
-                    && communication.icmp.len() == 0
-                    && communication.process.as_ref().unwrap().len() != 0
-                {
-                    policy_list.insert(key, value, 0)?;
-                    continue;
-                }
+        Ok(())
+    }
+}
+
+/// Every entry the policies put into the maps, kept by key so that two policies can be compared.
+/// The LPM trie keys are kept together with their prefix length.
+#[derive(Default)]
+struct Entries {
+    policy_list: StdHashMap<PolicyKey, PolicyValue>,
+    policy_cidr_list: StdHashMap<(u32, PolicyCidrKey), PolicyValue>,
+    port_range_policy_list: StdHashMap<PortRangePolicyKey, PortRangePolicies>,
+    fqdn_list: StdHashMap<FqdnKey, u32>,
+    fqdn_policy_list: StdHashMap<FqdnPolicyKey, PolicyValue>,
+    icmp_policy_list: StdHashMap<IcmpPolicyKey, IcmpPolicyValue>,
+    icmp_policy_cidr_list: StdHashMap<(u32, IcmpPolicyCidrKey), IcmpPolicyValue>,
+    audit_list: StdHashMap<ContainerID, u8>,
+    default_actions: StdHashMap<ContainerID, TcAction>,
+}
+
+impl Entries {
+    unsafe fn new(policies: &domain::Policies) -> anyhow::Result<Entries> {
+        let mut entries = Entries::default();
+
+        entries.add_policy_list(policies);
+        entries.add_policy_cidr_list(policies);
+        entries.add_port_range_policy_list(policies)?;
+        entries.add_fqdn_policy_list(policies)?;
+        entries.add_icmp_policy_list(policies)?;
+        entries.add_icmp_policy_cidr_list(policies)?;
+        entries.add_audit_list(policies);
+        entries.add_default_actions(policies);
 
-                for socket in &communication.sockets {
-                    key.local_port = socket.local_port.unwrap_or(0);
-                    key.remote_port = socket.remote_port.unwrap_or(0);
-                    key.protocol = socket.protocol;
+        Ok(entries)
+    }
+
+    unsafe fn add_policy_list(&mut self, policies: &domain::Policies) {
+        for policy in &policies.policies {
+            for container_id in policy.container_ids() {
+                for communication in &policy.communications {
+                    let mut key: PolicyKey = std::mem::zeroed();
+
+                    key.container_id = container_id;
+                    key.comm = communication.process();
+
+                    let mut value: PolicyValue = std::mem::zeroed();
+
+                    value.comm = communication.process();
+
+                    if communication.sockets.len() == 0
+                        && communication.icmp.len() == 0
+                        && communication.process() != [0; TASK_COMM_LEN]
+                    {
+                        self.policy_list.insert(key, value);
+                        continue;
+                    }
 
-                    value.local_port = socket.remote_port.unwrap_or(0);
-                    value.remote_port = socket.remote_port.unwrap_or(0);
-                    value.protocol = socket.protocol;
+                    for socket in &communication.resolved_sockets() {
+                        if socket.remote_prefix_len.is_some() || socket.has_port_range() {
+                            continue;
+                        }
+
+                        // Start from the communication's key so that no remote address is carried
+                        // over from the previous socket.
+                        let (mut key, mut value) = (key, value);
+
+                        key.local_port = socket.local_port.unwrap_or(0);
+                        key.remote_port = socket.remote_port.unwrap_or(0);
+                        key.protocol = socket.protocol;
+
+                        value.local_port = socket.remote_port.unwrap_or(0);
+                        value.remote_port = socket.remote_port.unwrap_or(0);
+                        value.protocol = socket.protocol;
+                        value.action = socket.action;
 
-                    match socket.remote_ip {
-                        Some(IpAddr::V4(ip)) => {
-                            key.remote_ip = ip.into();
-                            value.remote_ip = ip.into();
+                        match socket.remote_ip {
+                            Some(IpAddr::V4(ip)) => {
+                                key.remote_ip = ip.into();
+                                value.remote_ip = ip.into();
+                            }
+                            Some(IpAddr::V6(ip)) => {
+                                key.remote_ipv6 = ip.octets();
+                                value.remote_ipv6 = ip.octets();
+                            }
+                            None => {}
                         }
-                        Some(IpAddr::V6(ip)) => {
-                            key.remote_ipv6 = ip.octets();
-                            value.remote_ipv6 = ip.octets();
+
+                        // A deny takes precedence over an allow written for the same key.
+                        if is_denied(self.policy_list.get(&key).map(|value| value.action)) {
+                            continue;
                         }
-                        None => {}
+
+                        self.policy_list.insert(key, value);
                     }
+                }
+            }
+        }
+    }
+
+    unsafe fn add_policy_cidr_list(&mut self, policies: &domain::Policies) {
+        for policy in &policies.policies {
+            for container_id in policy.container_ids() {
+                for communication in &policy.communications {
+                    for socket in &communication.resolved_sockets() {
+                        if socket.has_port_range() {
+                            continue;
+                        }
+
+                        let (remote_ip, remote_prefix_len) =
+                            match (socket.remote_ip, socket.remote_prefix_len) {
+                                (Some(remote_ip), Some(remote_prefix_len)) => {
+                                    (remote_ip, remote_prefix_len)
+                                }
+                                _ => continue,
+                            };
+
+                        let mut key: PolicyCidrKey = std::mem::zeroed();
+
+                        key.container_id = container_id;
+                        key.comm = communication.process();
+                        key.local_port = socket.local_port.unwrap_or(0);
+                        key.remote_port = socket.remote_port.unwrap_or(0);
+                        key.protocol = socket.protocol;
+                        key.remote_ip = cidr_ip(remote_ip);
+
+                        let mut value: PolicyValue = std::mem::zeroed();
+
+                        value.comm = communication.process();
+                        value.local_port = socket.local_port.unwrap_or(0);
+                        value.remote_port = socket.remote_port.unwrap_or(0);
+                        value.protocol = socket.protocol;
+                        value.action = socket.action;
+
+                        match remote_ip {
+                            IpAddr::V4(ip) => value.remote_ip = ip.into(),
+                            IpAddr::V6(ip) => value.remote_ipv6 = ip.octets(),
+                        }
+
+                        let key = (PolicyCidrKey::prefix_len(remote_ip, remote_prefix_len), key);
+
+                        // A deny takes precedence over an allow written for the same key.
+                        if is_denied(self.policy_cidr_list.get(&key).map(|value| value.action)) {
+                            continue;
+                        }
 
-                    policy_list.insert(key, value, 0)?;
+                        self.policy_cidr_list.insert(key, value);
+                    }
                 }
             }
         }
-        Ok(())
     }
 
-    async unsafe fn save_icmp_policy_list(
-        &self,
-        policies: Arc<Mutex<domain::Policies>>,
+    unsafe fn add_port_range_policy_list(
+        &mut self,
+        policies: &domain::Policies,
     ) -> anyhow::Result<()> {
-        let mut locked_bpf = self.bpf.lock().await;
-        let mut icmp_policy_list =
-            HashMap::try_from(locked_bpf.map_mut("ICMP_POLICY_LIST").unwrap())?;
+        for policy in &policies.policies {
+            for container_id in policy.container_ids() {
+                for communication in &policy.communications {
+                    for socket in &communication.resolved_sockets() {
+                        if !socket.has_port_range() {
+                            continue;
+                        }
 
-        for policy in &policies.lock().await.policies {
-            for communication in &policy.communications {
-                for icmp in &communication.icmp {
-                    let mut key: IcmpPolicyKey = std::mem::zeroed();
+                        let mut range_policy: PortRangePolicy = std::mem::zeroed();
 
-                    key.container_id = policy.container.id();
-                    key.type_ = icmp.type_;
-                    key.code = icmp.code.unwrap_or(0);
+                        (range_policy.local_port_min, range_policy.local_port_max) =
+                            port_bounds(socket.local_port, &socket.local_port_range);
+                        (range_policy.remote_port_min, range_policy.remote_port_max) =
+                            port_bounds(socket.remote_port, &socket.remote_port_range);
+                        range_policy.protocol = socket.protocol;
+                        range_policy.action = socket.action;
+                        range_policy.rank = search_rank(
+                            &[
+                                socket.remote_ip.is_some(),
+                                socket.local_port.is_some() || socket.local_port_range.is_some(),
+                                socket.remote_port.is_some() || socket.remote_port_range.is_some(),
+                                socket.protocol != IpProtocol::Default,
+                            ],
+                            PolicyList::PortRange,
+                        );
 
-                    let mut value: IcmpPolicyValue = std::mem::zeroed();
+                        let mut key = PortRangePolicyKey {
+                            container_id,
+                            comm: communication.process(),
+                            remote_ip: [0; IPV6_LEN],
+                        };
 
-                    value.type_ = icmp.type_;
-                    value.code = icmp.code.unwrap_or(0);
+                        if let Some(remote_ip) = socket.remote_ip {
+                            let remote_prefix_len =
+                                socket.remote_prefix_len.unwrap_or(match remote_ip {
+                                    IpAddr::V4(_) => 32,
+                                    IpAddr::V6(_) => 128,
+                                });
 
-                    if icmp.version.is_v4() || icmp.version.is_v6() {
-                        key.version = icmp.version;
-                        value.version = icmp.version;
-                    } else {
-                        return Err(anyhow!("Please specify icmp version in the policy"));
+                            range_policy.remote_ip = cidr_ip(remote_ip);
+                            range_policy.remote_ip_mask = cidr_mask(remote_ip, remote_prefix_len);
+
+                            // Each address of a host has an entry of its own, so however many
+                            // addresses it resolves to, the rule takes one place in each.
+                            if socket.remote_prefix_len.is_none() {
+                                key.remote_ip = cidr_ip(remote_ip);
+                            }
+                        }
+
+                        let range_policies = self
+                            .port_range_policy_list
+                            .entry(key)
+                            .or_insert_with(|| std::mem::zeroed::<PortRangePolicies>());
+
+                        if range_policies.len as usize >= PORT_RANGE_POLICIES_MAX {
+                            return Err(anyhow!(
+                                "Too many port ranges for {} (max {})",
+                                policy.selector,
+                                PORT_RANGE_POLICIES_MAX
+                            ));
+                        }
+
+                        range_policies.policies[range_policies.len as usize] = range_policy;
+                        range_policies.len += 1;
                     }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The rules naming a domain also apply to the addresses the container is answered for it,
+    /// which the classifiers learn from the DNS responses.
+    unsafe fn add_fqdn_policy_list(&mut self, policies: &domain::Policies) -> anyhow::Result<()> {
+        // The rules of the names are kept by the ID of the name, so two names with the same ID
+        // would share their rules.
+        let mut fqdn_names: StdHashMap<u32, String> = StdHashMap::new();
+
+        for policy in &policies.policies {
+            for container_id in policy.container_ids() {
+                for communication in &policy.communications {
+                    for socket in &communication.sockets {
+                        let domain = match &socket.remote_host {
+                            Some(domain::RemoteHost::Domain(domain)) => domain,
+                            _ => continue,
+                        };
 
-                    match icmp.remote_ip {
-                        Some(IpAddr::V4(ip)) => {
-                            key.remote_ip = ip.into();
-                            value.remote_ip = ip.into();
+                        // Both are problems of the policies, unless the name was that of a
+                        // container which has stopped since.
+                        if socket.has_port_range() {
+                            warn!(
+                                "port ranges cannot be used with a domain name: {}, the rule only matches the addresses it is looked up to",
+                                domain
+                            );
+                            continue;
                         }
-                        Some(IpAddr::V6(ip)) => {
-                            key.remote_ipv6 = ip.octets();
-                            value.remote_ipv6 = ip.octets();
+
+                        let name = match encode_fqdn(domain) {
+                            Some(name) => name,
+                            None => {
+                                warn!("invalid domain name: {}, the rule is left out", domain);
+                                continue;
+                            }
+                        };
+
+                        let fqdn_name = domain.trim_end_matches('.').to_ascii_lowercase();
+                        match fqdn_names.get(&fqdn_id(domain)) {
+                            Some(other) if *other != fqdn_name => {
+                                return Err(anyhow!(
+                                    "the domain names {} and {} have the same ID, their rules cannot be told apart",
+                                    other,
+                                    fqdn_name
+                                ));
+                            }
+                            _ => {
+                                fqdn_names.insert(fqdn_id(domain), fqdn_name);
+                            }
+                        }
+
+                        self.fqdn_list
+                            .insert(FqdnKey { container_id, name }, fqdn_id(domain));
+
+                        let mut key: FqdnPolicyKey = std::mem::zeroed();
+
+                        key.container_id = container_id;
+                        key.comm = communication.process();
+                        key.fqdn_id = fqdn_id(domain);
+                        key.local_port = socket.local_port.unwrap_or(0);
+                        key.remote_port = socket.remote_port.unwrap_or(0);
+                        key.protocol = socket.protocol;
+
+                        let mut value: PolicyValue = std::mem::zeroed();
+
+                        value.comm = communication.process();
+                        value.local_port = socket.local_port.unwrap_or(0);
+                        value.remote_port = socket.remote_port.unwrap_or(0);
+                        value.protocol = socket.protocol;
+                        value.action = socket.action;
+
+                        // A deny takes precedence over an allow written for the same key.
+                        if is_denied(self.fqdn_policy_list.get(&key).map(|value| value.action)) {
+                            continue;
                         }
-                        None => {}
+
+                        self.fqdn_policy_list.insert(key, value);
                     }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    unsafe fn add_icmp_policy_list(&mut self, policies: &domain::Policies) -> anyhow::Result<()> {
+        for policy in &policies.policies {
+            for container_id in policy.container_ids() {
+                for communication in &policy.communications {
+                    for icmp in &communication.resolved_icmp() {
+                        if icmp.remote_prefix_len.is_some() {
+                            continue;
+                        }
+
+                        let mut key: IcmpPolicyKey = std::mem::zeroed();
+
+                        key.container_id = container_id;
+                        key.type_ = icmp.type_;
+                        key.code = icmp.code.unwrap_or(0);
+
+                        let mut value: IcmpPolicyValue = std::mem::zeroed();
 
-                    icmp_policy_list.insert(key, value, 0)?;
+                        value.type_ = icmp.type_;
+                        value.code = icmp.code.unwrap_or(0);
+
+                        if icmp.version.is_v4() || icmp.version.is_v6() {
+                            key.version = icmp.version;
+                            value.version = icmp.version;
+                        } else {
+                            return Err(anyhow!("Please specify icmp version in the policy"));
+                        }
+
+                        value.action = icmp.action;
+
+                        match icmp.remote_ip {
+                            Some(IpAddr::V4(ip)) => {
+                                key.remote_ip = ip.into();
+                                value.remote_ip = ip.into();
+                            }
+                            Some(IpAddr::V6(ip)) => {
+                                key.remote_ipv6 = ip.octets();
+                                value.remote_ipv6 = ip.octets();
+                            }
+                            None => {}
+                        }
+
+                        // A deny takes precedence over an allow written for the same key.
+                        if is_denied(self.icmp_policy_list.get(&key).map(|value| value.action)) {
+                            continue;
+                        }
+
+                        self.icmp_policy_list.insert(key, value);
+                    }
                 }
             }
         }
@@ -128,44 +540,508 @@ impl PolicyMap {
         Ok(())
     }
 
-    pub async fn remove(&self) -> anyhow::Result<()> {
-        unsafe {
-            self.remove_policy_list().await?;
-            self.remove_icmp_policy_list().await?;
+    unsafe fn add_icmp_policy_cidr_list(
+        &mut self,
+        policies: &domain::Policies,
+    ) -> anyhow::Result<()> {
+        for policy in &policies.policies {
+            for container_id in policy.container_ids() {
+                for communication in &policy.communications {
+                    for icmp in &communication.resolved_icmp() {
+                        let (remote_ip, remote_prefix_len) =
+                            match (icmp.remote_ip, icmp.remote_prefix_len) {
+                                (Some(remote_ip), Some(remote_prefix_len)) => {
+                                    (remote_ip, remote_prefix_len)
+                                }
+                                _ => continue,
+                            };
+
+                        if !icmp.version.is_v4() && !icmp.version.is_v6() {
+                            return Err(anyhow!("Please specify icmp version in the policy"));
+                        }
+
+                        let mut key: IcmpPolicyCidrKey = std::mem::zeroed();
+
+                        key.container_id = container_id;
+                        key.version = icmp.version;
+                        key.type_ = icmp.type_;
+                        key.code = icmp.code.unwrap_or(0);
+                        key.remote_ip = cidr_ip(remote_ip);
+
+                        let mut value: IcmpPolicyValue = std::mem::zeroed();
+
+                        value.version = icmp.version;
+                        value.type_ = icmp.type_;
+                        value.code = icmp.code.unwrap_or(0);
+                        value.action = icmp.action;
+
+                        match remote_ip {
+                            IpAddr::V4(ip) => value.remote_ip = ip.into(),
+                            IpAddr::V6(ip) => value.remote_ipv6 = ip.octets(),
+                        }
+
+                        let key = (
+                            IcmpPolicyCidrKey::prefix_len(remote_ip, remote_prefix_len),
+                            key,
+                        );
+
+                        // A deny takes precedence over an allow written for the same key.
+                        if is_denied(
+                            self.icmp_policy_cidr_list
+                                .get(&key)
+                                .map(|value| value.action),
+                        ) {
+                            continue;
+                        }
+
+                        self.icmp_policy_cidr_list.insert(key, value);
+                    }
+                }
+            }
         }
 
         Ok(())
     }
 
-    async unsafe fn remove_policy_list(&self) -> anyhow::Result<()> {
-        let mut bpf = self.bpf.lock().await;
-        let mut policy_list: HashMap<_, PolicyKey, PolicyValue> =
-            HashMap::try_from(bpf.map_mut("POLICY_LIST").unwrap())?;
+    fn add_audit_list(&mut self, policies: &domain::Policies) {
+        for policy in &policies.policies {
+            if !policy.audit {
+                continue;
+            }
 
-        let mut policy_keys = vec![];
-        for key in policy_list.keys() {
-            policy_keys.push(key.unwrap());
+            for container_id in policy.container_ids() {
+                self.audit_list.insert(ContainerID::new(container_id), 1u8);
+            }
         }
-        for policy_key in policy_keys {
-            policy_list.remove(&policy_key)?;
+
+        for container_id in &policies.audit_container_ids {
+            self.audit_list.insert(
+                ContainerID::new(domain::string_to_c_char_bytes(container_id.clone())),
+                1u8,
+            );
         }
+    }
 
-        Ok(())
+    fn add_default_actions(&mut self, policies: &domain::Policies) {
+        // Containers without an entry of their own are not managed by any policy.
+        self.default_actions
+            .insert(ALL_CONTAINERS, policies.default_action);
+
+        for policy in &policies.policies {
+            for container_id in policy.container_ids() {
+                self.default_actions
+                    .insert(ContainerID::new(container_id), policy.default_action);
+            }
+        }
     }
+}
 
-    async unsafe fn remove_icmp_policy_list(&self) -> anyhow::Result<()> {
-        let mut bpf = self.bpf.lock().await;
-        let mut icmp_policy_list: HashMap<_, IcmpPolicyKey, IcmpPolicyValue> =
-            HashMap::try_from(bpf.map_mut("ICMP_POLICY_LIST").unwrap())?;
+/// Inserts the new and changed entries before removing the stale ones, so that an entry which
+/// only moved to another key never leaves a gap.
+///
+/// Without `old_entries` the stale keys are read from the map and removed first, so that the new
+/// entries fit into it.
+fn update_hash_map<K: Pod + Eq + Hash, V: Pod + PartialEq>(
+    mut map: HashMap<&mut MapData, K, V>,
+    old_entries: Option<&StdHashMap<K, V>>,
+    new_entries: &StdHashMap<K, V>,
+) -> anyhow::Result<()> {
+    let old_entries = match old_entries {
+        Some(old_entries) => old_entries,
+        None => {
+            let mut stale = vec![];
+            for key in map.keys() {
+                let key = key?;
+                if !new_entries.contains_key(&key) {
+                    stale.push(key);
+                }
+            }
+
+            for key in stale {
+                ignore_not_found(map.remove(&key))?;
+            }
 
-        let mut policy_keys = vec![];
-        for key in icmp_policy_list.keys() {
-            policy_keys.push(key.unwrap());
+            for (key, value) in new_entries {
+                map.insert(key, value, 0)?;
+            }
+
+            return Ok(());
         }
-        for policy_key in policy_keys {
-            icmp_policy_list.remove(&policy_key)?;
+    };
+
+    let (written, removed) = changes(old_entries, new_entries);
+
+    for (key, value) in written {
+        map.insert(key, value, 0)?;
+    }
+
+    for key in removed {
+        ignore_not_found(map.remove(key))?;
+    }
+
+    Ok(())
+}
+
+/// Same as `update_hash_map` for the LPM tries.
+fn update_lpm_trie<K: Pod + Eq + Hash, V: Pod + PartialEq>(
+    mut map: LpmTrie<&mut MapData, K, V>,
+    old_entries: Option<&StdHashMap<(u32, K), V>>,
+    new_entries: &StdHashMap<(u32, K), V>,
+) -> anyhow::Result<()> {
+    let old_entries = match old_entries {
+        Some(old_entries) => old_entries,
+        None => {
+            let mut stale = vec![];
+            for key in map.keys() {
+                let key = key?;
+                if !new_entries.contains_key(&(key.prefix_len(), key.data())) {
+                    stale.push(key);
+                }
+            }
+
+            for key in stale {
+                ignore_not_found(map.remove(&key))?;
+            }
+
+            for ((prefix_len, key), value) in new_entries {
+                map.insert(&Key::new(*prefix_len, *key), value, 0)?;
+            }
+
+            return Ok(());
         }
+    };
 
-        Ok(())
+    let (written, removed) = changes(old_entries, new_entries);
+
+    for ((prefix_len, key), value) in written {
+        map.insert(&Key::new(*prefix_len, *key), value, 0)?;
+    }
+
+    for (prefix_len, key) in removed {
+        ignore_not_found(map.remove(&Key::new(*prefix_len, *key)))?;
+    }
+
+    Ok(())
+}
+
+/// A key that is already gone is not an error: after a failed write the maps may not hold every
+/// entry the old policies put into them.
+fn ignore_not_found(removed: Result<(), MapError>) -> Result<(), MapError> {
+    match &removed {
+        Err(MapError::KeyNotFound) => Ok(()),
+        Err(MapError::SyscallError(SyscallError { io_error, .. }))
+            if io_error.kind() == io::ErrorKind::NotFound =>
+        {
+            Ok(())
+        }
+        _ => removed,
+    }
+}
+
+/// The entries to write and the keys to remove to turn the old entries into the new ones, leaving
+/// out the entries that did not change.
+fn changes<'a, K: Eq + Hash, V: PartialEq>(
+    old_entries: &'a StdHashMap<K, V>,
+    new_entries: &'a StdHashMap<K, V>,
+) -> (Vec<(&'a K, &'a V)>, Vec<&'a K>) {
+    let written = new_entries
+        .iter()
+        .filter(|(key, value)| old_entries.get(*key) != Some(*value))
+        .collect();
+    let removed = old_entries
+        .keys()
+        .filter(|key| !new_entries.contains_key(*key))
+        .collect();
+
+    (written, removed)
+}
+
+/// Returns the inclusive bounds of a port policy, where no port at all means any port.
+fn port_bounds(port: Option<u16>, port_range: &Option<RangeInclusive<u16>>) -> (u16, u16) {
+    match (port, port_range) {
+        (_, Some(port_range)) => (*port_range.start(), *port_range.end()),
+        (Some(port), None) => (port, port),
+        (None, None) => (u16::MIN, u16::MAX),
+    }
+}
+
+fn is_denied(saved_action: Option<TcAction>) -> bool {
+    matches!(saved_action, Some(TcAction::Drop))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        domain::{Container, Containers},
+        parse_policies::{Format, ParsePolicies},
+    };
+
+    async fn policies(contents: &str) -> domain::Policies {
+        let containers = Containers::new();
+        containers.lock().await.add(Container {
+            name: "/web".to_string(),
+            ..Container::new("web_id".to_string())
+        });
+
+        let policies = ParsePolicies::parse(contents, Format::Yaml)
+            .unwrap_or_else(|(_, err)| panic!("{}", err))
+            .to_policies(containers)
+            .await
+            .unwrap();
+
+        let policies = policies.lock().await.clone();
+        policies
+    }
+
+    async fn entries(contents: &str) -> Entries {
+        unsafe { Entries::new(&policies(contents).await).unwrap() }
+    }
+
+    #[test]
+    fn changes_leave_out_the_unchanged_entries() {
+        let old_entries = StdHashMap::from([(1, 1), (2, 2), (3, 3)]);
+        let new_entries = StdHashMap::from([(1, 1), (2, 20), (4, 4)]);
+
+        let (mut written, removed) = changes(&old_entries, &new_entries);
+        written.sort();
+
+        assert_eq!(written, vec![(&2, &20), (&4, &4)]);
+        assert_eq!(removed, vec![&3]);
+    }
+
+    #[test]
+    fn removing_a_key_that_is_gone_is_not_an_error() {
+        let syscall_error = |kind| {
+            Err(MapError::SyscallError(SyscallError {
+                call: "bpf_map_delete_elem",
+                io_error: io::Error::from(kind),
+            }))
+        };
+
+        assert!(ignore_not_found(Ok(())).is_ok());
+        assert!(ignore_not_found(Err(MapError::KeyNotFound)).is_ok());
+        assert!(ignore_not_found(syscall_error(io::ErrorKind::NotFound)).is_ok());
+        assert!(ignore_not_found(syscall_error(io::ErrorKind::PermissionDenied)).is_err());
+    }
+
+    #[tokio::test]
+    async fn network_goes_into_the_cidr_list() {
+        let entries = entries(
+            r#"
+policies:
+  - container:
+      name: web
+    communications:
+      - executable: nginx
+        sockets:
+          - protocol: tcp
+            remote_host: 10.1.0.0/16
+            remote_port: 443
+"#,
+        )
+        .await;
+
+        assert!(entries.policy_list.is_empty());
+        assert_eq!(entries.policy_cidr_list.len(), 1);
+
+        let network = IpAddr::from([10, 1, 0, 0]);
+        let ((prefix_len, key), value) = entries.policy_cidr_list.iter().next().unwrap();
+        assert_eq!(*prefix_len, PolicyCidrKey::prefix_len(network, 16));
+        assert_eq!(key.remote_ip, cidr_ip(network));
+        assert_eq!(key.remote_port, 443);
+        assert_eq!(value.remote_port, 443);
+    }
+
+    #[tokio::test]
+    async fn host_takes_one_place_in_the_port_ranges_of_each_address() {
+        let mut contents = r#"
+policies:
+  - container:
+      name: db
+  - container:
+      name: web
+    communications:
+      - sockets:
+"#
+        .to_string();
+        for i in 0..PORT_RANGE_POLICIES_MAX {
+            contents.push_str(&format!(
+                "          - remote_host: db\n            remote_port_range: {}-{}\n",
+                8000 + i * 10,
+                8009 + i * 10
+            ));
+        }
+
+        let mut policies = policies(&contents).await;
+        let addrs = [
+            IpAddr::from([10, 0, 0, 2]),
+            IpAddr::from([10, 0, 0, 3]),
+            "fd00::2".parse().unwrap(),
+        ];
+        for policy in &mut policies.policies {
+            for communication in &mut policy.communications {
+                for socket in &mut communication.sockets {
+                    socket.remote_ips = addrs.to_vec();
+                }
+            }
+        }
+
+        let entries = unsafe { Entries::new(&policies).unwrap() };
+
+        assert_eq!(entries.port_range_policy_list.len(), addrs.len());
+        for addr in addrs {
+            let key = PortRangePolicyKey {
+                container_id: domain::string_to_c_char_bytes("web_id".to_string()),
+                comm: [0; TASK_COMM_LEN],
+                remote_ip: cidr_ip(addr),
+            };
+
+            let range_policies = entries.port_range_policy_list[&key];
+            assert_eq!(range_policies.len as usize, PORT_RANGE_POLICIES_MAX);
+            assert_eq!(range_policies.policies[0].remote_ip, cidr_ip(addr));
+        }
+    }
+
+    #[tokio::test]
+    async fn domain_goes_into_the_fqdn_lists() {
+        let entries = entries(
+            r#"
+policies:
+  - container:
+      name: web
+    communications:
+      - executable: php-fpm
+        sockets:
+          - protocol: tcp
+            remote_host: WordPress.org
+            remote_port: 443
+"#,
+        )
+        .await;
+
+        let web_id = domain::string_to_c_char_bytes("web_id".to_string());
+        let name = encode_fqdn("wordpress.org").unwrap();
+
+        assert_eq!(entries.fqdn_list.len(), 1);
+        assert_eq!(
+            entries.fqdn_list.get(&FqdnKey {
+                container_id: web_id,
+                name
+            }),
+            Some(&fqdn_id("wordpress.org"))
+        );
+
+        assert_eq!(entries.fqdn_policy_list.len(), 1);
+        let (key, value) = entries.fqdn_policy_list.iter().next().unwrap();
+        assert_eq!(key.fqdn_id, fqdn_id("wordpress.org"));
+        assert_eq!(key.remote_port, 443);
+        assert_eq!(value.action, TcAction::Pass);
+    }
+
+    #[tokio::test]
+    async fn domains_with_the_same_id_fail_the_load() {
+        assert_eq!(
+            fqdn_id("host53866.example.com"),
+            fqdn_id("host1018390.example.com")
+        );
+
+        let policies = policies(
+            r#"
+policies:
+  - container:
+      name: web
+    communications:
+      - sockets:
+          - protocol: tcp
+            remote_host: host53866.example.com
+          - protocol: tcp
+            remote_host: host1018390.example.com
+"#,
+        )
+        .await;
+
+        let err = unsafe { Entries::new(&policies) }.err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "the domain names host53866.example.com and host1018390.example.com have the same ID, their rules cannot be told apart"
+        );
+    }
+
+    #[tokio::test]
+    async fn audited_policy_puts_its_containers_into_the_audit_list() {
+        let audited = entries(
+            r#"
+policies:
+  - container:
+      name: web
+    mode: audit
+"#,
+        )
+        .await;
+        let enforced = entries(
+            r#"
+policies:
+  - container:
+      name: web
+"#,
+        )
+        .await;
+
+        let web_id = ContainerID::new(domain::string_to_c_char_bytes("web_id".to_string()));
+
+        assert_eq!(audited.audit_list.len(), 1);
+        assert_eq!(audited.audit_list.get(&web_id), Some(&1));
+        assert!(enforced.audit_list.is_empty());
+    }
+
+    #[tokio::test]
+    async fn reload_changes_only_the_edited_socket() {
+        let old_entries = entries(
+            r#"
+policies:
+  - container:
+      name: web
+    communications:
+      - executable: nginx
+        sockets:
+          - protocol: tcp
+            remote_host: 10.0.0.1
+            remote_port: 443
+          - protocol: tcp
+            remote_host: 10.0.0.2
+            remote_port: 5432
+"#,
+        )
+        .await;
+        let new_entries = entries(
+            r#"
+policies:
+  - container:
+      name: web
+    communications:
+      - executable: nginx
+        sockets:
+          - protocol: tcp
+            remote_host: 10.0.0.1
+            remote_port: 443
+          - protocol: tcp
+            remote_host: 10.0.0.2
+            remote_port: 5433
+"#,
+        )
+        .await;
+
+        let (written, removed) = changes(&old_entries.policy_list, &new_entries.policy_list);
+
+        assert_eq!(written.len(), 1);
+        assert_eq!(written[0].0.remote_port, 5433);
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].remote_port, 5432);
+
+        let (written, removed) =
+            changes(&old_entries.default_actions, &new_entries.default_actions);
+
+        assert!(written.is_empty() && removed.is_empty());
     }
 }
@@ -29,6 +29,10 @@ impl ProcessMap {
             let mut value: PortVal = std::mem::zeroed();
 
             value.comm = process.executable();
+            value.exe_ino = process.exe_ino;
+            value.exe_dev = process.exe_dev;
+            value.pid = process.pid;
+            value.cgroup_id = process.cgroup_id;
 
             proc_ports.insert(key, value, 0)?;
         }
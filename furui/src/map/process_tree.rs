@@ -0,0 +1,115 @@
+use std::{collections::HashMap as StdHashMap, sync::Arc};
+
+use aya::Ebpf;
+use furui_common::ProcessTreeKey;
+use tokio::sync::Mutex;
+
+use crate::{
+    domain::{self, Policies},
+    map::seen,
+    process,
+};
+
+/// Saves the name of the matching rule for every process seen in the containers that runs an
+/// executable named by a `process_tree` rule, or descends from a process that does. As with the
+/// cgroups, the processes are kept so that the names can be saved again.
+pub struct ProcessTreeMap {
+    bpf: Arc<Mutex<Ebpf>>,
+    processes: Mutex<StdHashMap<ProcessTreeKey, SeenProcess>>,
+}
+
+struct SeenProcess {
+    container_id: String,
+    /// The executables of the process and of its ancestors in the container, the process first.
+    ancestry: Vec<String>,
+}
+
+impl ProcessTreeMap {
+    pub fn new(bpf: Arc<Mutex<Ebpf>>) -> ProcessTreeMap {
+        ProcessTreeMap {
+            bpf,
+            processes: Mutex::new(StdHashMap::new()),
+        }
+    }
+
+    /// Looks at the ancestors of a process the first time it binds or connects. They are only
+    /// looked at once, so an ancestor that exits before then no longer counts, as its children
+    /// are handed to another process.
+    pub async fn save(
+        &self,
+        container_id: String,
+        pid: u32,
+        policies: &Policies,
+    ) -> anyhow::Result<()> {
+        if pid == 0 || container_id.is_empty() {
+            return Ok(());
+        }
+
+        let key = ProcessTreeKey {
+            container_id: domain::string_to_c_char_bytes(container_id.clone()),
+            pid,
+        };
+
+        let mut processes = self.processes.lock().await;
+        if processes.contains_key(&key) {
+            return Ok(());
+        }
+
+        let process = processes.entry(key).or_insert(SeenProcess {
+            container_id,
+            ancestry: process::get_ancestry(pid),
+        });
+
+        self.write(&key, process, policies).await
+    }
+
+    /// Saves the names of all the processes seen again, after the policies or the containers
+    /// changed.
+    pub async fn update(&self, policies: &Policies) -> anyhow::Result<()> {
+        let processes = self.processes.lock().await;
+
+        for (key, process) in processes.iter() {
+            self.write(key, process, policies).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Forgets a process that exited, as its pid may be taken by another one.
+    pub async fn remove_process(&self, pid: u32) -> anyhow::Result<()> {
+        self.remove(|key, _| key.pid == pid).await
+    }
+
+    pub async fn remove_container(&self, container_id: &str) -> anyhow::Result<()> {
+        self.remove(|_, process| process.container_id == container_id)
+            .await
+    }
+
+    async fn remove<F>(&self, f: F) -> anyhow::Result<()>
+    where
+        F: Fn(&ProcessTreeKey, &SeenProcess) -> bool,
+    {
+        let mut processes = self.processes.lock().await;
+
+        // Every process that exits is passed here, most of them never seen, which leaves the map
+        // alone.
+        seen::remove(&self.bpf, "PROCESS_TREE_LIST", &mut processes, f).await
+    }
+
+    /// The rule of the closest ancestor wins, the process itself first.
+    async fn write(
+        &self,
+        key: &ProcessTreeKey,
+        process: &SeenProcess,
+        policies: &Policies,
+    ) -> anyhow::Result<()> {
+        let rules = policies.process_trees(&process.container_id);
+        let comm = process
+            .ancestry
+            .iter()
+            .find_map(|path| rules.iter().find(|rule| rule.path == *path))
+            .map(|rule| rule.comm());
+
+        seen::write(&self.bpf, "PROCESS_TREE_LIST", key, comm).await
+    }
+}
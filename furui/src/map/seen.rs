@@ -0,0 +1,59 @@
+use std::{collections::HashMap as StdHashMap, convert::TryFrom, hash::Hash};
+
+use aya::{maps::HashMap, Ebpf, Pod};
+use furui_common::{ExeValue, TASK_COMM_LEN};
+use tokio::sync::Mutex;
+
+/// Saves the name of the rule matching a key seen in the containers into the map `name`, or
+/// takes the key out of it when no rule matches.
+pub(super) async fn write<K: Pod>(
+    bpf: &Mutex<Ebpf>,
+    name: &str,
+    key: &K,
+    comm: Option<[u8; TASK_COMM_LEN]>,
+) -> anyhow::Result<()> {
+    let mut bpf = bpf.lock().await;
+    let mut map: HashMap<_, K, ExeValue> = HashMap::try_from(bpf.map_mut(name).unwrap())?;
+
+    match comm {
+        Some(comm) => map.insert(key, ExeValue { comm }, 0)?,
+        // Not there unless a rule matched before.
+        None => {
+            let _ = map.remove(key);
+        }
+    }
+
+    Ok(())
+}
+
+/// Forgets the keys seen for which `f` holds, and takes them out of the map `name`.
+pub(super) async fn remove<K, V, F>(
+    bpf: &Mutex<Ebpf>,
+    name: &str,
+    seen: &mut StdHashMap<K, V>,
+    f: F,
+) -> anyhow::Result<()>
+where
+    K: Pod + Eq + Hash,
+    F: Fn(&K, &V) -> bool,
+{
+    let keys: Vec<_> = seen
+        .iter()
+        .filter(|(key, value)| f(key, value))
+        .map(|(key, _)| *key)
+        .collect();
+
+    if keys.is_empty() {
+        return Ok(());
+    }
+
+    let mut bpf = bpf.lock().await;
+    let mut map: HashMap<_, K, ExeValue> = HashMap::try_from(bpf.map_mut(name).unwrap())?;
+
+    for key in keys {
+        seen.remove(&key);
+        let _ = map.remove(&key);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,199 @@
+use std::{
+    fmt::{self, Write},
+    net::SocketAddr,
+    sync::Arc,
+};
+
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use furui_common::{PacketCount, PacketCountKey};
+use tokio::{net::TcpListener, sync::Mutex, task};
+use tracing::warn;
+
+use crate::{
+    domain::{self, Containers},
+    map::Maps,
+};
+
+type MetricsState = (Arc<Maps>, Arc<Mutex<Containers>>);
+
+/// Serves the counters of the classifiers on `/metrics`, in the Prometheus text format.
+pub async fn serve(
+    addr: SocketAddr,
+    maps: Arc<Maps>,
+    containers: Arc<Mutex<Containers>>,
+) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+
+    let app = Router::new()
+        .route("/metrics", get(metrics))
+        .with_state((maps, containers));
+
+    task::spawn(async move {
+        axum::serve(listener, app)
+            .await
+            .unwrap_or_else(|e| warn!("the metrics endpoint stopped: {}", e));
+    });
+
+    Ok(())
+}
+
+async fn metrics(State((maps, containers)): State<MetricsState>) -> Response {
+    match render(&maps, &containers).await {
+        Ok(body) => ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+async fn render(maps: &Maps, containers: &Mutex<Containers>) -> anyhow::Result<String> {
+    let counts = maps.metrics.packet_counts().await?;
+    let events_lost = maps.metrics.events_lost().await?;
+    let containers = containers.lock().await;
+
+    Ok(text(counts, &containers, events_lost)?)
+}
+
+fn text(
+    counts: Vec<(PacketCountKey, PacketCount)>,
+    containers: &Containers,
+    events_lost: u64,
+) -> Result<String, fmt::Error> {
+    let mut packets = String::new();
+    let mut bytes = String::new();
+
+    for (key, count) in counts {
+        let container_id = domain::c_char_bytes_to_string(key.container_id);
+
+        // Empty for the moment between the removal of a container and that of its counters.
+        let container_name = containers
+            .get(container_id.clone())
+            .map(|container| container.name.trim_start_matches("/").to_string())
+            .unwrap_or_default();
+
+        let action = if key.would_drop {
+            "would-drop"
+        } else {
+            key.action.to_string()
+        };
+
+        let labels = format!(
+            "container_id=\"{}\",container_name=\"{}\",direction=\"{}\",protocol=\"{}\",action=\"{}\"",
+            escape(&container_id),
+            escape(&container_name),
+            key.direction.to_string(),
+            key.protocol.to_string().to_lowercase(),
+            action,
+        );
+
+        writeln!(
+            packets,
+            "furui_packets_total{{{}}} {}",
+            labels, count.packets
+        )?;
+        writeln!(bytes, "furui_bytes_total{{{}}} {}", labels, count.bytes)?;
+    }
+
+    Ok(format!(
+        "# HELP furui_packets_total Packets of the containers, by the action taken on them.\n\
+         # TYPE furui_packets_total counter\n\
+         {}\
+         # HELP furui_bytes_total Bytes of the packets counted by furui_packets_total.\n\
+         # TYPE furui_bytes_total counter\n\
+         {}\
+         # HELP furui_events_lost_total Events that were not logged, as the ring buffer was full.\n\
+         # TYPE furui_events_lost_total counter\n\
+         furui_events_lost_total {}\n",
+        packets, bytes, events_lost
+    ))
+}
+
+/// Escapes a label value of the text format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use furui_common::{Direction, IpProtocol, TcAction};
+
+    use super::*;
+    use crate::domain::Container;
+
+    fn key(action: TcAction, would_drop: bool) -> PacketCountKey {
+        PacketCountKey {
+            container_id: domain::string_to_c_char_bytes("3f4e8a9b2c1d".to_string()),
+            direction: Direction::Egress,
+            protocol: IpProtocol::TCP,
+            action,
+            would_drop,
+        }
+    }
+
+    #[tokio::test]
+    async fn text_has_a_line_per_counter_and_container() {
+        let containers = Containers::new();
+        containers.lock().await.add(Container {
+            name: "/web".to_string(),
+            ..Container::new("3f4e8a9b2c1d".to_string())
+        });
+
+        let counts = vec![
+            (
+                key(TcAction::Drop, false),
+                PacketCount {
+                    packets: 12,
+                    bytes: 888,
+                },
+            ),
+            (
+                key(TcAction::Pass, true),
+                PacketCount {
+                    packets: 3,
+                    bytes: 180,
+                },
+            ),
+        ];
+
+        let text = text(counts, &*containers.lock().await, 5).unwrap();
+
+        assert_eq!(
+            text,
+            "# HELP furui_packets_total Packets of the containers, by the action taken on them.\n\
+             # TYPE furui_packets_total counter\n\
+             furui_packets_total{container_id=\"3f4e8a9b2c1d\",container_name=\"web\",direction=\"egress\",protocol=\"tcp\",action=\"drop\"} 12\n\
+             furui_packets_total{container_id=\"3f4e8a9b2c1d\",container_name=\"web\",direction=\"egress\",protocol=\"tcp\",action=\"would-drop\"} 3\n\
+             # HELP furui_bytes_total Bytes of the packets counted by furui_packets_total.\n\
+             # TYPE furui_bytes_total counter\n\
+             furui_bytes_total{container_id=\"3f4e8a9b2c1d\",container_name=\"web\",direction=\"egress\",protocol=\"tcp\",action=\"drop\"} 888\n\
+             furui_bytes_total{container_id=\"3f4e8a9b2c1d\",container_name=\"web\",direction=\"egress\",protocol=\"tcp\",action=\"would-drop\"} 180\n\
+             # HELP furui_events_lost_total Events that were not logged, as the ring buffer was full.\n\
+             # TYPE furui_events_lost_total counter\n\
+             furui_events_lost_total 5\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn text_of_a_removed_container_has_no_name() {
+        let counts = vec![(key(TcAction::Pass, false), PacketCount::default())];
+
+        let text = text(counts, &*Containers::new().lock().await, 0).unwrap();
+
+        assert!(text.contains(
+            "furui_packets_total{container_id=\"3f4e8a9b2c1d\",container_name=\"\",direction=\"egress\",protocol=\"tcp\",action=\"pass\"} 0\n"
+        ));
+    }
+
+    #[test]
+    fn escape_quotes_backslashes_and_newlines() {
+        assert_eq!(escape("web"), "web");
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
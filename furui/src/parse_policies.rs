@@ -1,50 +1,330 @@
-use std::{fs::File, io::Read, net::IpAddr, path::PathBuf, sync::Arc};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::TryFrom,
+    fs::{self, File},
+    io::Read,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
+use anyhow::anyhow;
 use dns_lookup::lookup_host;
-use furui_common::IpProtocol;
-use serde_derive::Deserialize;
-use serde_yaml;
+use furui_common::{
+    encode_fqdn, search_rank, IpProtocol, PolicyList, TcAction, FQDN_LEN, PORT_RANGE_POLICIES_MAX,
+    TASK_COMM_LEN,
+};
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{InstanceType, Schema, SchemaObject, StringValidation},
+    schema_for, JsonSchema,
+};
+use serde_derive::{Deserialize, Serialize};
 use tokio::sync::Mutex;
 use tracing::warn;
 
-use crate::domain::{self, Policies};
+use crate::{
+    domain::{self, Policies, RemoteHost},
+    validate::{Locations, Position},
+};
 
-#[derive(Debug, Deserialize)]
+const ICMPV4_TYPES: &[RangeInclusive<u8>] = &[0..=43];
+const ICMPV6_TYPES: &[RangeInclusive<u8>] = &[1..=4, 128..=161];
+const ICMP_MAX_CODE: u8 = 15;
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields)]
 pub struct ParsePolicies {
+    /// What happens to traffic that no rule matches, `deny` when omitted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_action: Option<Action>,
+    /// Named sets of rules, which communications in the same file take in with `include`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub groups: BTreeMap<String, Group>,
     pub policies: Vec<Policy>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Group {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sockets: Vec<Socket>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub icmp: Vec<ICMP>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields)]
 pub struct Policy {
     pub container: Container,
+    /// `audit` only logs the packets that would be dropped.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub mode: Mode,
+    /// Overrides the top-level `default_action` for these containers, which they take when
+    /// this is omitted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_action: Option<Action>,
+    #[serde(default)]
     pub communications: Vec<Communication>,
+    /// The file the policy comes from, told in the warnings about it. Set when the policy files
+    /// are merged.
+    #[serde(skip)]
+    pub file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    Enforce,
+    Audit,
 }
 
-#[derive(Debug, Deserialize)]
+impl Default for Mode {
+    fn default() -> Self {
+        Mode::Enforce
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields)]
 pub struct Container {
-    pub name: String,
+    /// The container name, which may contain the wildcards `*` and `?`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// A regular expression the whole container name has to match.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name_regex: Option<String>,
+    /// Labels the container has to have, including the pod labels with Kubernetes.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+}
+
+impl Communication {
+    /// Whether both communications are about the same processes.
+    fn same_process(&self, other: &Communication) -> bool {
+        self.executable == other.executable
+            && self.executable_path == other.executable_path
+            && self.executable_sha256 == other.executable_sha256
+            && self.cgroup == other.cgroup
+            && self.process_tree == other.process_tree
+    }
+
+    /// What the rules of the communication are kept under in the maps, the same for every
+    /// communication whose rules share an entry, see `domain::Communication::process`.
+    fn process_key(&self) -> (&str, Option<&String>, Option<&String>) {
+        if let Some(executable_path) = &self.executable_path {
+            return (
+                "executable_path",
+                Some(executable_path),
+                self.executable_sha256.as_ref(),
+            );
+        }
+
+        if let Some(cgroup) = &self.cgroup {
+            return ("cgroup", Some(cgroup), None);
+        }
+
+        if let Some(process_tree) = &self.process_tree {
+            return ("process_tree", Some(process_tree), None);
+        }
+
+        ("executable", self.executable.as_ref(), None)
+    }
 }
 
-#[derive(Debug, Deserialize)]
+impl Policy {
+    /// Parses a policy in YAML or JSON that leaves out its container selector, for the container
+    /// named `name`.
+    pub fn parse_for(name: &str, contents: &str) -> anyhow::Result<Policy> {
+        let mut value: serde_yaml::Value = serde_yaml::from_str(contents)?;
+
+        let mapping = value
+            .as_mapping_mut()
+            .ok_or_else(|| anyhow!("the policy has to be a mapping"))?;
+        if mapping.contains_key("container") {
+            return Err(anyhow!("the container is selected by its name"));
+        }
+        mapping.insert(
+            "container".into(),
+            serde_yaml::to_value(Container::named(name))?,
+        );
+
+        let mut parsed_policies = ParsePolicies {
+            default_action: None,
+            groups: BTreeMap::new(),
+            policies: vec![serde_yaml::from_value(value)
+                .map_err(|err| anyhow!("{}", without_position(err.to_string())))?],
+        };
+
+        if let Some(problem) = parsed_policies
+            .problems()
+            .into_iter()
+            .find(|problem| problem.severity == Severity::Error)
+        {
+            let path = problem.path.trim_start_matches("policies[0].");
+            return Err(anyhow!("{}: {}", path, problem.message));
+        }
+
+        Ok(parsed_policies.policies.remove(0))
+    }
+}
+
+impl Container {
+    /// Selects the container with exactly this name.
+    pub fn named(name: &str) -> Container {
+        Container {
+            name: Some(name.to_string()),
+            name_regex: None,
+            labels: BTreeMap::new(),
+            image: None,
+        }
+    }
+
+    pub fn to_selector(&self) -> domain::ContainerSelector {
+        domain::ContainerSelector {
+            name: self.name.clone(),
+            name_regex: self.name_regex.as_deref().map(domain::NameRegex::new),
+            labels: self.labels.clone(),
+            image: self.image.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields)]
 pub struct Communication {
+    /// The process name, of which the kernel keeps the first 15 bytes.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub executable: Option<String>,
-    #[serde(default)]
+    /// The path of the executable in the container. Unlike the process name, the process cannot
+    /// change it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub executable_path: Option<String>,
+    /// The SHA-256 of the executable at `executable_path`, in hex.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub executable_sha256: Option<String>,
+    /// The path of a cgroup below the cgroup of the container, such as `/worker`. It covers the
+    /// cgroups below it and every process started in them. Needs cgroup v2.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cgroup: Option<String>,
+    /// The path of an executable in the container. It covers every process running it and all
+    /// the processes they start, whatever they run.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub process_tree: Option<String>,
+    /// Groups whose rules are added to those of this communication.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub sockets: Vec<Socket>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub icmp: Vec<ICMP>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields)]
 pub struct Socket {
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "is_default")]
     pub protocol: Protocol,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub local_port: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local_port_range: Option<PortRange>,
+    /// An address, a CIDR network, a host name, a `*.` wildcard domain or a container name.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub remote_host: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub remote_port: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote_port_range: Option<PortRange>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub action: Action,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Allow,
+    Deny,
+}
+
+impl Default for Action {
+    fn default() -> Self {
+        Action::Allow
+    }
+}
+
+impl Action {
+    fn to_tc_action(&self) -> TcAction {
+        match self {
+            Action::Allow => TcAction::Pass,
+            Action::Deny => TcAction::Drop,
+        }
+    }
+}
+
+/// An inclusive port range written as `"30000-30100"`.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl TryFrom<String> for PortRange {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("invalid port range: {}", value);
+
+        let (start, end) = value.split_once('-').ok_or_else(invalid)?;
+        let start = start.trim().parse::<u16>().map_err(|_| invalid())?;
+        let end = end.trim().parse::<u16>().map_err(|_| invalid())?;
+
+        if start > end {
+            return Err(invalid());
+        }
+
+        Ok(PortRange { start, end })
+    }
+}
+
+impl From<PortRange> for String {
+    fn from(value: PortRange) -> Self {
+        format!("{}-{}", value.start, value.end)
+    }
+}
+
+impl JsonSchema for PortRange {
+    fn schema_name() -> String {
+        "PortRange".to_string()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            string: Some(Box::new(StringValidation {
+                pattern: Some(r"^\s*\d+\s*-\s*\d+\s*$".to_string()),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+impl PortRange {
+    fn to_range(&self) -> RangeInclusive<u16> {
+        self.start..=self.end
+    }
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all(deserialize = "lowercase"))]
+#[derive(
+    Debug, Clone, Copy, Deserialize, Serialize, JsonSchema, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "lowercase")]
 pub enum Protocol {
     TCP,
     UDP,
@@ -57,18 +337,26 @@ impl Default for Protocol {
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema, PartialEq)]
+#[serde(deny_unknown_fields)]
 pub struct ICMP {
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "is_default")]
     pub version: IcmpVersion,
     #[serde(rename = "type")]
     pub type_: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub code: Option<u8>,
+    /// An address, a CIDR network, a host name or a container name.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub remote_host: Option<String>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub action: Action,
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all(deserialize = "lowercase"))]
+#[derive(
+    Debug, Clone, Copy, Deserialize, Serialize, JsonSchema, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "lowercase")]
 pub enum IcmpVersion {
     V4,
     V6,
@@ -81,51 +369,1132 @@ impl Default for IcmpVersion {
     }
 }
 
+/// A socket or an ICMP rule, as compared with the other rules of the same processes.
+trait Rule {
+    fn action(&self) -> &Action;
+
+    /// The fields of the key in the order of the tables of `furui-ebpf/src/README.md`, followed
+    /// by any other field a packet has to match.
+    fn fields(&self) -> Vec<Option<RuleField<'_>>>;
+
+    /// The rank of the rule among those matching a packet, see `search_rank`.
+    fn rank(&self) -> u32;
+}
+
+impl Rule for Socket {
+    fn action(&self) -> &Action {
+        &self.action
+    }
+
+    fn fields(&self) -> Vec<Option<RuleField<'_>>> {
+        let port = |port: Option<u16>, port_range: &Option<PortRange>| match port_range {
+            Some(port_range) => Some(RuleField::Range(port_range.to_range())),
+            None => port.map(|port| RuleField::Range(port..=port)),
+        };
+
+        vec![
+            self.remote_host.as_deref().map(RuleField::Host),
+            port(self.local_port, &self.local_port_range),
+            port(self.remote_port, &self.remote_port_range),
+            match self.protocol {
+                Protocol::None => None,
+                protocol => Some(RuleField::Range(protocol as u16..=protocol as u16)),
+            },
+        ]
+    }
+
+    fn rank(&self) -> u32 {
+        let list = match &self.remote_host {
+            _ if self.local_port_range.is_some() || self.remote_port_range.is_some() => {
+                PolicyList::PortRange
+            }
+            Some(remote_host) if remote_host.contains('/') => PolicyList::Network,
+            _ => PolicyList::Address,
+        };
+
+        let used: Vec<bool> = self.fields().iter().map(Option::is_some).collect();
+        search_rank(&used, list)
+    }
+}
+
+impl Rule for ICMP {
+    fn action(&self) -> &Action {
+        &self.action
+    }
+
+    fn fields(&self) -> Vec<Option<RuleField<'_>>> {
+        let value = |value: u8| Some(RuleField::Range(value as u16..=value as u16));
+
+        vec![
+            self.remote_host.as_deref().map(RuleField::Host),
+            value(self.type_),
+            self.code.and_then(value),
+            value(self.version as u8),
+        ]
+    }
+
+    fn rank(&self) -> u32 {
+        let list = match &self.remote_host {
+            Some(remote_host) if remote_host.contains('/') => PolicyList::Network,
+            _ => PolicyList::Address,
+        };
+
+        let used: Vec<bool> = self.fields()[..3].iter().map(Option::is_some).collect();
+        search_rank(&used, list)
+    }
+}
+
+/// What a field of a rule matches, for comparing rules. A field left out matches anything.
+#[derive(Clone)]
+enum RuleField<'a> {
+    Host(&'a str),
+    Range(RangeInclusive<u16>),
+}
+
+impl RuleField<'_> {
+    /// Whether everything this matches is matched by `other` as well. Names are only compared
+    /// with names, as what they resolve to is not known here.
+    fn within(&self, other: &RuleField) -> bool {
+        match (self, other) {
+            (RuleField::Host(host), RuleField::Host(other)) => {
+                match (host_network(host), host_network(other)) {
+                    (Some((addr, prefix_len)), Some((network, network_prefix_len))) => {
+                        prefix_len >= network_prefix_len
+                            && network_contains(network, network_prefix_len, addr)
+                    }
+                    (None, None) => {
+                        let (host, other) = (host.to_lowercase(), other.to_lowercase());
+
+                        host.trim_end_matches('.') == other.trim_end_matches('.')
+                            || other
+                                .strip_prefix('*')
+                                .is_some_and(|parent| host.ends_with(parent))
+                    }
+                    _ => false,
+                }
+            }
+            (RuleField::Range(range), RuleField::Range(other)) => {
+                other.start() <= range.start() && range.end() <= other.end()
+            }
+            _ => false,
+        }
+    }
+
+    fn overlaps(&self, other: &RuleField) -> bool {
+        match (self, other) {
+            (RuleField::Range(range), RuleField::Range(other)) => {
+                range.start() <= other.end() && other.start() <= range.end()
+            }
+            _ => self.within(other) || other.within(self),
+        }
+    }
+}
+
+/// The network of an address or a CIDR, `None` for a name.
+fn host_network(host: &str) -> Option<(IpAddr, u8)> {
+    match host.split_once('/') {
+        Some((addr, prefix_len)) => ParsePolicies::parse_cidr(addr, prefix_len),
+        None => match host.parse::<IpAddr>().ok()? {
+            addr @ IpAddr::V4(_) => Some((addr, 32)),
+            addr @ IpAddr::V6(_) => Some((addr, 128)),
+        },
+    }
+}
+
+/// Whether `addr` is in the network, whose host bits are clear.
+fn network_contains(network: IpAddr, prefix_len: u8, addr: IpAddr) -> bool {
+    match (network, addr) {
+        (IpAddr::V4(network), IpAddr::V4(addr)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+            u32::from(addr) & mask == u32::from(network)
+        }
+        (IpAddr::V6(network), IpAddr::V6(addr)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0);
+            u128::from(addr) & mask == u128::from(network)
+        }
+        _ => false,
+    }
+}
+
+/// Whether an allow passes packets a deny was written for: it outranks the deny and matches some
+/// of the same packets, without being an exception carved out of the deny, which only matches
+/// packets the deny matches.
+fn allow_outranks_deny(allow: &impl Rule, deny: &impl Rule) -> bool {
+    let fields = allow.fields().into_iter().zip(deny.fields());
+
+    let overlaps = fields.clone().all(|fields| match fields {
+        (Some(allow), Some(deny)) => allow.overlaps(&deny),
+        _ => true,
+    });
+    let within = fields.into_iter().all(|fields| match fields {
+        (_, None) => true,
+        (Some(allow), Some(deny)) => allow.within(&deny),
+        (None, Some(_)) => false,
+    });
+
+    allow.rank() < deny.rank() && overlaps && !within
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+/// serde_yaml and serde_json end their messages with the position, which is printed in front of
+/// the message instead.
+fn without_position(message: String) -> String {
+    match message.rsplit_once(" at line ") {
+        Some((message, _)) => message.to_string(),
+        None => message,
+    }
+}
+
+/// The format of a policy file, told by its extension.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Yaml,
+    Json,
+    Toml,
+}
+
+impl Format {
+    pub fn from_path(path: &Path) -> Option<Format> {
+        match path.extension()?.to_str()? {
+            "yaml" | "yml" => Some(Format::Yaml),
+            "json" => Some(Format::Json),
+            "toml" => Some(Format::Toml),
+            _ => None,
+        }
+    }
+}
+
+/// A mistake found in a policy file. `path` is where the value is in the file, such as
+/// `policies[0].communications[1].icmp[0].code`.
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub path: String,
+    pub severity: Severity,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Problem {
+    fn error(path: impl Into<String>, message: impl Into<String>) -> Problem {
+        Problem {
+            path: path.into(),
+            severity: Severity::Error,
+            message: message.into(),
+        }
+    }
+
+    fn warning(path: impl Into<String>, message: impl Into<String>) -> Problem {
+        Problem {
+            path: path.into(),
+            severity: Severity::Warning,
+            message: message.into(),
+        }
+    }
+}
+
 impl ParsePolicies {
-    pub fn new(path: PathBuf) -> anyhow::Result<ParsePolicies> {
-        let mut f = File::open(path.as_path()).expect("file not found");
+    pub fn load_file(path: &Path) -> anyhow::Result<ParsePolicies> {
+        let mut f = File::open(path)
+            .map_err(|err| anyhow!("failed to open {}: {}", path.display(), err))?;
         let mut contents = String::new();
 
         f.read_to_string(&mut contents)?;
 
-        let config = serde_yaml::from_str::<ParsePolicies>(&contents)?;
+        let format = Format::from_path(path).unwrap_or(Format::Yaml);
+
+        let config = ParsePolicies::parse(&contents, format).map_err(|(position, message)| {
+            anyhow!("{}:{}: {}", path.display(), position, message)
+        })?;
+
+        if let Some(problem) = config
+            .problems()
+            .into_iter()
+            .find(|problem| problem.severity == Severity::Error)
+        {
+            return Err(anyhow!(
+                "{}:{}: {}",
+                path.display(),
+                Locations::new(&contents, format).position(&problem.path),
+                problem.message
+            ));
+        }
 
         Ok(config)
     }
 
-    async fn lookup_host(
-        containers: Arc<Mutex<domain::Containers>>,
-        remote_host: &str,
-    ) -> Vec<IpAddr> {
-        match lookup_host(remote_host) {
-            Ok(ips) => ips,
-            Err(err) => match containers.lock().await.get_container_by_name(remote_host) {
-                Some(container) => container.ip_addresses.unwrap(),
+    /// Parses the contents of a policy file. A value that does not fit the format is returned
+    /// with its position.
+    pub fn parse(contents: &str, format: Format) -> Result<ParsePolicies, (Position, String)> {
+        match format {
+            Format::Yaml => serde_yaml::from_str::<ParsePolicies>(contents).map_err(|err| {
+                let position = err.location().map_or(Position::START, |location| Position {
+                    line: location.line(),
+                    column: location.column(),
+                });
+
+                (position, without_position(err.to_string()))
+            }),
+            Format::Json => serde_json::from_str::<ParsePolicies>(contents).map_err(|err| {
+                let position = Position {
+                    line: err.line(),
+                    column: err.column(),
+                };
+
+                (position, without_position(err.to_string()))
+            }),
+            Format::Toml => toml::from_str::<ParsePolicies>(contents).map_err(|err| {
+                let position = err.span().map_or(Position::START, |span| {
+                    Position::from_offset(contents, span.start)
+                });
+
+                (position, err.message().to_string())
+            }),
+        }
+    }
+
+    pub fn to_string(&self, format: Format) -> anyhow::Result<String> {
+        Ok(match format {
+            Format::Yaml => serde_yaml::to_string(self)?,
+            Format::Json => serde_json::to_string_pretty(self)?,
+            Format::Toml => toml::to_string(self)?,
+        })
+    }
+
+    /// The JSON Schema of the policy format.
+    pub fn schema() -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(&schema_for!(ParsePolicies))?)
+    }
+
+    /// Loads every policy file in a directory, keyed by its path.
+    pub fn load_dir(path: &Path) -> anyhow::Result<BTreeMap<PathBuf, ParsePolicies>> {
+        let mut files = BTreeMap::new();
+
+        for file in ParsePolicies::policy_files(path)? {
+            let parsed_policies = ParsePolicies::load_file(&file)?;
+            files.insert(file, parsed_policies);
+        }
+
+        Ok(files)
+    }
+
+    /// The `.yaml`, `.yml`, `.json` and `.toml` files in a directory. Hidden files are left out, which also skips
+    /// the `..data` links Kubernetes puts in mounted config maps.
+    pub fn policy_files(path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+        let mut files = vec![];
+
+        for entry in fs::read_dir(path)
+            .map_err(|err| anyhow!("failed to open {}: {}", path.display(), err))?
+        {
+            let file = entry?.path();
+            if ParsePolicies::is_policy_file(&file) && file.is_file() {
+                files.push(file);
+            }
+        }
+
+        files.sort();
+
+        Ok(files)
+    }
+
+    pub fn is_policy_file(path: &Path) -> bool {
+        let hidden = path
+            .file_name()
+            .map_or(true, |name| name.to_string_lossy().starts_with('.'));
+
+        !hidden && Format::from_path(path).is_some()
+    }
+
+    /// Merges the files of a policy directory. Each container selector may appear in only one
+    /// file, and the files that set the top-level `default_action` have to agree on it. Groups
+    /// belong to their file, so they are written out in the merged policies.
+    pub fn merge(files: &BTreeMap<PathBuf, ParsePolicies>) -> anyhow::Result<ParsePolicies> {
+        let mut merged = ParsePolicies {
+            default_action: None,
+            groups: BTreeMap::new(),
+            policies: vec![],
+        };
+        let mut default_action_file: Option<&PathBuf> = None;
+        let mut container_files: Vec<(&Container, &PathBuf)> = vec![];
+
+        for (file, parsed_policies) in files {
+            if let Some(default_action) = &parsed_policies.default_action {
+                match (&merged.default_action, default_action_file) {
+                    (Some(merged_default_action), Some(default_action_file))
+                        if merged_default_action != default_action =>
+                    {
+                        return Err(anyhow!(
+                            "conflicting default_action in {} and {}",
+                            default_action_file.display(),
+                            file.display()
+                        ));
+                    }
+                    _ => {
+                        merged.default_action = Some(default_action.clone());
+                        default_action_file = Some(file);
+                    }
+                }
+            }
+
+            for (policy, expanded_policy) in parsed_policies
+                .policies
+                .iter()
+                .zip(parsed_policies.expand_groups())
+            {
+                if let Some((_, other_file)) = container_files
+                    .iter()
+                    .find(|(container, _)| *container == &policy.container)
+                {
+                    return Err(match *other_file == file {
+                        true => anyhow!(
+                            "duplicate container {} in {}",
+                            policy.container.to_selector(),
+                            file.display()
+                        ),
+                        false => anyhow!(
+                            "duplicate container {} in {} and {}",
+                            policy.container.to_selector(),
+                            other_file.display(),
+                            file.display()
+                        ),
+                    });
+                }
+
+                container_files.push((&policy.container, file));
+                merged.policies.push(Policy {
+                    file: Some(file.clone()),
+                    ..expanded_policy
+                });
+            }
+        }
+
+        Ok(merged)
+    }
+
+    /// Every mistake in the policies, at the path of the value it is about. Loading fails on the
+    /// first error, while `furui validate` reports them all.
+    pub fn problems(&self) -> Vec<Problem> {
+        let mut problems = vec![];
+        let container_names = self.container_names();
+
+        for (name, group) in &self.groups {
+            let group_path = format!("groups.{}", name);
+
+            for (k, socket) in group.sockets.iter().enumerate() {
+                problems.extend(ParsePolicies::socket_problems(
+                    socket,
+                    &format!("{}.sockets[{}]", group_path, k),
+                    &container_names,
+                ));
+            }
+
+            for (k, icmp) in group.icmp.iter().enumerate() {
+                problems.extend(ParsePolicies::icmp_problems(
+                    icmp,
+                    &format!("{}.icmp[{}]", group_path, k),
+                ));
+            }
+        }
+
+        for (i, policy) in self.policies.iter().enumerate() {
+            let policy_path = format!("policies[{}]", i);
+
+            if policy.container.to_selector().is_empty() {
+                problems.push(Problem::error(
+                    format!("{}.container", policy_path),
+                    "Please specify the name, labels or image of the container in the policy",
+                ));
+            }
+
+            if let Some(j) = self.policies[..i]
+                .iter()
+                .position(|other| other.container == policy.container)
+            {
+                problems.push(Problem::error(
+                    format!("{}.container", policy_path),
+                    format!(
+                        "duplicate container {}, the same as policies[{}].container",
+                        policy.container.to_selector(),
+                        j
+                    ),
+                ));
+            }
+
+            if let Some(name_regex) = &policy.container.name_regex {
+                if let Err(err) = domain::NameRegex::compile(name_regex) {
+                    problems.push(Problem::error(
+                        format!("{}.container.name_regex", policy_path),
+                        format!("invalid name_regex: {}", err),
+                    ));
+                }
+            }
+
+            // Rules already seen in this policy, with the communication they belong to.
+            let mut sockets: Vec<(&Communication, &Socket, String)> = vec![];
+            let mut icmps: Vec<(&Communication, &ICMP, String)> = vec![];
+
+            for (j, communication) in policy.communications.iter().enumerate() {
+                let communication_path = format!("{}.communications[{}]", policy_path, j);
+
+                if let Some(executable) = &communication.executable {
+                    // The kernel keeps the first TASK_COMM_LEN - 1 bytes of the name.
+                    if executable.len() >= TASK_COMM_LEN {
+                        problems.push(Problem::error(
+                            format!("{}.executable", communication_path),
+                            format!(
+                                "executable name {} is longer than {} bytes and would never match",
+                                executable,
+                                TASK_COMM_LEN - 1
+                            ),
+                        ));
+                    }
+                }
+
+                problems.extend(ParsePolicies::executable_problems(
+                    communication,
+                    &communication_path,
+                ));
+
+                for (k, group) in communication.include.iter().enumerate() {
+                    if !self.groups.contains_key(group) {
+                        problems.push(Problem::error(
+                            format!("{}.include[{}]", communication_path, k),
+                            format!("unknown group: {}", group),
+                        ));
+                    }
+                }
+
+                for (k, socket) in communication.sockets.iter().enumerate() {
+                    problems.extend(ParsePolicies::socket_problems(
+                        socket,
+                        &format!("{}.sockets[{}]", communication_path, k),
+                        &container_names,
+                    ));
+                }
+
+                for (k, icmp) in communication.icmp.iter().enumerate() {
+                    problems.extend(ParsePolicies::icmp_problems(
+                        icmp,
+                        &format!("{}.icmp[{}]", communication_path, k),
+                    ));
+                }
+
+                for (socket_path, socket) in self.sockets(communication, &communication_path) {
+                    for (other_communication, other, other_path) in &sockets {
+                        if other_communication.same_process(communication) {
+                            problems.extend(ParsePolicies::outranked_deny(
+                                (socket, &socket_path),
+                                (*other, other_path),
+                            ));
+                        }
+                    }
+
+                    match sockets.iter().find(|(other_communication, other, _)| {
+                        other_communication.same_process(communication) && *other == socket
+                    }) {
+                        Some((_, _, other_path)) => problems.push(Problem::warning(
+                            socket_path,
+                            format!("duplicate rule, the same as {}", other_path),
+                        )),
+                        None => sockets.push((communication, socket, socket_path)),
+                    }
+                }
+
+                for (icmp_path, icmp) in self.icmp(communication, &communication_path) {
+                    for (other_communication, other, other_path) in &icmps {
+                        if other_communication.same_process(communication) {
+                            problems.extend(ParsePolicies::outranked_deny(
+                                (icmp, &icmp_path),
+                                (*other, other_path),
+                            ));
+                        }
+                    }
+
+                    match icmps.iter().find(|(other_communication, other, _)| {
+                        other_communication.same_process(communication) && *other == icmp
+                    }) {
+                        Some((_, _, other_path)) => problems.push(Problem::warning(
+                            icmp_path,
+                            format!("duplicate rule, the same as {}", other_path),
+                        )),
+                        None => icmps.push((communication, icmp, icmp_path)),
+                    }
+                }
+            }
+
+            // The port range rules of a process share an entry of the container, which holds
+            // PORT_RANGE_POLICIES_MAX rules however many addresses their hosts resolve to.
+            for (j, communication) in policy.communications.iter().enumerate() {
+                let key = communication.process_key();
+
+                if policy.communications[..j]
+                    .iter()
+                    .any(|other| other.process_key() == key)
+                {
+                    continue;
+                }
+
+                let port_ranges = policy
+                    .communications
+                    .iter()
+                    .filter(|other| other.process_key() == key)
+                    .flat_map(|other| self.sockets(other, ""))
+                    .filter(|(_, socket)| {
+                        socket.local_port_range.is_some() || socket.remote_port_range.is_some()
+                    })
+                    .count();
+
+                if port_ranges > PORT_RANGE_POLICIES_MAX {
+                    problems.push(Problem::error(
+                        format!("{}.communications[{}]", policy_path, j),
+                        format!(
+                            "too many port ranges for the process, {} (max {})",
+                            port_ranges, PORT_RANGE_POLICIES_MAX
+                        ),
+                    ));
+                }
+            }
+        }
+
+        problems
+    }
+
+    fn executable_problems(
+        communication: &Communication,
+        communication_path: &str,
+    ) -> Vec<Problem> {
+        let mut problems = vec![];
+
+        if let Some(executable_path) = &communication.executable_path {
+            if communication.executable.is_some() {
+                problems.push(Problem::error(
+                    format!("{}.executable_path", communication_path),
+                    "executable and executable_path cannot be used together",
+                ));
+            }
+
+            if !executable_path.starts_with('/') {
+                problems.push(Problem::error(
+                    format!("{}.executable_path", communication_path),
+                    format!("executable_path has to be absolute: {}", executable_path),
+                ));
+            }
+        }
+
+        if let Some(executable_sha256) = &communication.executable_sha256 {
+            if communication.executable_path.is_none() {
+                problems.push(Problem::error(
+                    format!("{}.executable_sha256", communication_path),
+                    "executable_sha256 needs executable_path",
+                ));
+            }
+
+            if executable_sha256.len() != 64
+                || !executable_sha256.chars().all(|c| c.is_ascii_hexdigit())
+            {
+                problems.push(Problem::error(
+                    format!("{}.executable_sha256", communication_path),
+                    format!("invalid executable_sha256: {}", executable_sha256),
+                ));
+            }
+        }
+
+        if let Some(cgroup) = &communication.cgroup {
+            if communication.executable.is_some() || communication.executable_path.is_some() {
+                problems.push(Problem::error(
+                    format!("{}.cgroup", communication_path),
+                    "cgroup cannot be used together with executable or executable_path",
+                ));
+            }
+
+            if !cgroup.starts_with('/') {
+                problems.push(Problem::error(
+                    format!("{}.cgroup", communication_path),
+                    format!("cgroup has to be absolute: {}", cgroup),
+                ));
+            }
+        }
+
+        if let Some(process_tree) = &communication.process_tree {
+            if communication.executable.is_some()
+                || communication.executable_path.is_some()
+                || communication.cgroup.is_some()
+            {
+                problems.push(Problem::error(
+                    format!("{}.process_tree", communication_path),
+                    "process_tree cannot be used together with executable, executable_path or \
+                     cgroup",
+                ));
+            }
+
+            if !process_tree.starts_with('/') {
+                problems.push(Problem::error(
+                    format!("{}.process_tree", communication_path),
+                    format!("process_tree has to be absolute: {}", process_tree),
+                ));
+            }
+        }
+
+        problems
+    }
+
+    /// A warning at the deny when one of the two rules is an allow that outranks the other, a deny,
+    /// and so passes packets the deny was written for.
+    fn outranked_deny<R: Rule>(rule: (&R, &str), other: (&R, &str)) -> Option<Problem> {
+        let ((allow, allow_path), (deny, deny_path)) = match (rule.0.action(), other.0.action()) {
+            (Action::Allow, Action::Deny) => (rule, other),
+            (Action::Deny, Action::Allow) => (other, rule),
+            _ => return None,
+        };
+
+        if !allow_outranks_deny(allow, deny) {
+            return None;
+        }
+
+        Some(Problem::warning(
+            deny_path,
+            format!(
+                "the allow {} is more specific and passes the packets both match, repeat its fields here to deny them",
+                allow_path
+            ),
+        ))
+    }
+
+    fn socket_problems(
+        socket: &Socket,
+        socket_path: &str,
+        container_names: &BTreeSet<String>,
+    ) -> Vec<Problem> {
+        let mut problems = vec![];
+
+        if socket.local_port.is_some() && socket.local_port_range.is_some() {
+            problems.push(Problem::error(
+                format!("{}.local_port_range", socket_path),
+                "local_port and local_port_range cannot be used together",
+            ));
+        }
+
+        if socket.remote_port.is_some() && socket.remote_port_range.is_some() {
+            problems.push(Problem::error(
+                format!("{}.remote_port_range", socket_path),
+                "remote_port and remote_port_range cannot be used together",
+            ));
+        }
+
+        if let Some(remote_host) = &socket.remote_host {
+            let remote_host_path = format!("{}.remote_host", socket_path);
+
+            if let Some(problem) =
+                ParsePolicies::remote_host_problem(remote_host, &remote_host_path)
+            {
+                problems.push(problem);
+            }
+
+            // The rules of a domain name are matched by the addresses the container is answered,
+            // which are kept by exact ports.
+            let is_domain = !remote_host.contains('*')
+                && !container_names.contains(remote_host)
+                && ParsePolicies::is_name(remote_host);
+            let has_port_range =
+                socket.local_port_range.is_some() || socket.remote_port_range.is_some();
+
+            if is_domain && encode_fqdn(remote_host).is_none() {
+                problems.push(Problem::error(
+                    remote_host_path,
+                    format!(
+                        "invalid domain name: {}, each label has to be 1-63 bytes long and the name at most {} bytes",
+                        remote_host,
+                        FQDN_LEN - 2
+                    ),
+                ));
+            } else if remote_host.contains('*') && has_port_range {
+                problems.push(Problem::error(
+                    remote_host_path,
+                    format!(
+                        "port ranges cannot be used with a wildcard domain: {}",
+                        remote_host
+                    ),
+                ));
+            } else if is_domain && has_port_range {
+                problems.push(Problem::error(
+                    remote_host_path,
+                    format!(
+                        "port ranges cannot be used with a domain name: {}, use remote_port or an address",
+                        remote_host
+                    ),
+                ));
+            }
+        }
+
+        problems
+    }
+
+    fn icmp_problems(icmp: &ICMP, icmp_path: &str) -> Vec<Problem> {
+        let mut problems = vec![];
+
+        let (types, type_range) = match icmp.version {
+            IcmpVersion::V4 => (ICMPV4_TYPES, "0-43"),
+            IcmpVersion::V6 => (ICMPV6_TYPES, "1-4 or 128-161"),
+            IcmpVersion::None => {
+                problems.push(Problem::error(
+                    icmp_path,
+                    "Please specify icmp version in the policy, v4 or v6",
+                ));
+                (&[][..], "")
+            }
+        };
+
+        // 255 is what the search for any type or code is done with.
+        if icmp.type_ == u8::MAX {
+            problems.push(Problem::error(
+                format!("{}.type", icmp_path),
+                "icmp type 255 is reserved",
+            ));
+        } else if !types.is_empty() && !types.iter().any(|types| types.contains(&icmp.type_)) {
+            problems.push(Problem::error(
+                format!("{}.type", icmp_path),
+                format!(
+                    "icmp type {} is out of range, expected {}",
+                    icmp.type_, type_range
+                ),
+            ));
+        }
+
+        if let Some(code) = icmp.code {
+            if code > ICMP_MAX_CODE {
+                problems.push(Problem::error(
+                    format!("{}.code", icmp_path),
+                    format!(
+                        "icmp code {} is out of range, expected 0-{}",
+                        code, ICMP_MAX_CODE
+                    ),
+                ));
+            }
+        }
+
+        if let Some(remote_host) = &icmp.remote_host {
+            let remote_host_path = format!("{}.remote_host", icmp_path);
+
+            if remote_host.contains('*') {
+                problems.push(Problem::error(
+                    remote_host_path,
+                    format!("wildcard domains cannot be used with icmp: {}", remote_host),
+                ));
+            } else if let Some(problem) =
+                ParsePolicies::remote_host_problem(remote_host, &remote_host_path)
+            {
+                problems.push(problem);
+            }
+        }
+
+        problems
+    }
+
+    /// The sockets of a communication followed by those of the groups it includes, with their
+    /// paths. Groups that do not exist are left out.
+    fn sockets<'a>(
+        &'a self,
+        communication: &'a Communication,
+        communication_path: &str,
+    ) -> Vec<(String, &'a Socket)> {
+        let mut sockets: Vec<(String, &Socket)> = communication
+            .sockets
+            .iter()
+            .enumerate()
+            .map(|(k, socket)| (format!("{}.sockets[{}]", communication_path, k), socket))
+            .collect();
+
+        for name in &communication.include {
+            if let Some(group) = self.groups.get(name) {
+                sockets.extend(
+                    group
+                        .sockets
+                        .iter()
+                        .enumerate()
+                        .map(|(k, socket)| (format!("groups.{}.sockets[{}]", name, k), socket)),
+                );
+            }
+        }
+
+        sockets
+    }
+
+    /// Like `sockets`, for the ICMP rules.
+    fn icmp<'a>(
+        &'a self,
+        communication: &'a Communication,
+        communication_path: &str,
+    ) -> Vec<(String, &'a ICMP)> {
+        let mut icmp: Vec<(String, &ICMP)> = communication
+            .icmp
+            .iter()
+            .enumerate()
+            .map(|(k, icmp)| (format!("{}.icmp[{}]", communication_path, k), icmp))
+            .collect();
+
+        for name in &communication.include {
+            if let Some(group) = self.groups.get(name) {
+                icmp.extend(
+                    group.icmp.iter().enumerate().map(|(k, group_icmp)| {
+                        (format!("groups.{}.icmp[{}]", name, k), group_icmp)
+                    }),
+                );
+            }
+        }
+
+        icmp
+    }
+
+    /// The policies with the groups written out in the communications that include them.
+    fn expand_groups(&self) -> Vec<Policy> {
+        self.policies
+            .iter()
+            .map(|policy| Policy {
+                communications: policy
+                    .communications
+                    .iter()
+                    .map(|communication| Communication {
+                        include: vec![],
+                        sockets: self
+                            .sockets(communication, "")
+                            .into_iter()
+                            .map(|(_, socket)| socket.clone())
+                            .collect(),
+                        icmp: self
+                            .icmp(communication, "")
+                            .into_iter()
+                            .map(|(_, icmp)| icmp.clone())
+                            .collect(),
+                        ..communication.clone()
+                    })
+                    .collect(),
+                ..policy.clone()
+            })
+            .collect()
+    }
+
+    /// Checks the networks and the wildcard domains in `remote_host`, the names are looked up
+    /// by `unresolvable_hosts`.
+    fn remote_host_problem(remote_host: &str, path: &str) -> Option<Problem> {
+        if remote_host.contains('*') {
+            if !remote_host.starts_with("*.")
+                || remote_host[2..].contains('*')
+                || encode_fqdn(remote_host).is_none()
+            {
+                return Some(Problem::error(
+                    path,
+                    format!(
+                        "invalid wildcard domain: {}, only a leading `*.` is allowed",
+                        remote_host
+                    ),
+                ));
+            }
+
+            return None;
+        }
+
+        match remote_host.split_once('/') {
+            Some((addr, prefix_len)) if ParsePolicies::parse_cidr(addr, prefix_len).is_none() => {
+                Some(Problem::error(
+                    path,
+                    format!("invalid CIDR: {}", remote_host),
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    /// Names in `remote_host` that are neither the name of a container the policies select nor
+    /// known to DNS. They may still be the name of a container started later, or a host that
+    /// resolves later, so these are only warnings.
+    pub fn unresolvable_hosts(&self) -> Vec<Problem> {
+        let mut problems = vec![];
+        let container_names = self.container_names();
+
+        // Each group once, rather than in every communication including it.
+        let mut rule_sets: Vec<(String, &Vec<Socket>, &Vec<ICMP>)> = self
+            .groups
+            .iter()
+            .map(|(name, group)| (format!("groups.{}", name), &group.sockets, &group.icmp))
+            .collect();
+
+        for (i, policy) in self.policies.iter().enumerate() {
+            for (j, communication) in policy.communications.iter().enumerate() {
+                rule_sets.push((
+                    format!("policies[{}].communications[{}]", i, j),
+                    &communication.sockets,
+                    &communication.icmp,
+                ));
+            }
+        }
+
+        for (path, sockets, icmp) in rule_sets {
+            let remote_hosts = sockets
+                .iter()
+                .enumerate()
+                .map(|(k, socket)| (format!("sockets[{}]", k), &socket.remote_host))
+                .chain(
+                    icmp.iter()
+                        .enumerate()
+                        .map(|(k, icmp)| (format!("icmp[{}]", k), &icmp.remote_host)),
+                );
+
+            for (rule, remote_host) in remote_hosts {
+                let remote_host = match remote_host {
+                    Some(remote_host)
+                        if !remote_host.contains('*') && !container_names.contains(remote_host) =>
+                    {
+                        remote_host
+                    }
+                    _ => continue,
+                };
+
+                if ParsePolicies::parse_remote_host(remote_host).is_some() {
+                    continue;
+                }
+
+                if lookup_host(remote_host).is_err() {
+                    problems.push(Problem::warning(
+                        format!("{}.{}.remote_host", path, rule),
+                        format!(
+                            "failed to look up host: {}, it has to be the name of a container or a host that resolves later",
+                            remote_host
+                        ),
+                    ));
+                }
+            }
+        }
+
+        problems
+    }
+
+    /// Whether `remote_host` is the name of a container or a host rather than an address or a
+    /// network.
+    fn is_name(remote_host: &str) -> bool {
+        !remote_host.contains('/') && remote_host.parse::<IpAddr>().is_err()
+    }
+
+    /// Returns `None` when `remote_host` is a name rather than an address or a network.
+    fn parse_remote_host(remote_host: &str) -> Option<Vec<(IpAddr, Option<u8>)>> {
+        match remote_host.split_once('/') {
+            Some((addr, prefix_len)) => match ParsePolicies::parse_cidr(addr, prefix_len) {
+                Some(network) => Some(vec![(network.0, Some(network.1))]),
                 None => {
-                    warn!("failed to look up host: {} err: {}", remote_host, err);
-                    vec![]
+                    warn!("invalid CIDR: {}", remote_host);
+                    Some(vec![])
                 }
             },
+            None => remote_host
+                .parse::<IpAddr>()
+                .ok()
+                .map(|addr| vec![(addr, None)]),
+        }
+    }
+
+    /// Traffic that no rule matches is denied unless `default_action` says otherwise. A policy
+    /// without a `default_action` of its own takes the top-level one, `inherited`.
+    fn default_action(default_action: &Option<Action>, inherited: TcAction) -> TcAction {
+        default_action
+            .as_ref()
+            .map_or(inherited, Action::to_tc_action)
+    }
+
+    /// Parses a network such as `10.0.0.0/8`, clearing the host bits of the address.
+    fn parse_cidr(addr: &str, prefix_len: &str) -> Option<(IpAddr, u8)> {
+        let addr = addr.parse::<IpAddr>().ok()?;
+        let prefix_len = prefix_len.parse::<u8>().ok()?;
+
+        match addr {
+            IpAddr::V4(addr) if prefix_len <= 32 => {
+                let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+                Some((Ipv4Addr::from(u32::from(addr) & mask).into(), prefix_len))
+            }
+            IpAddr::V6(addr) if prefix_len <= 128 => {
+                let mask = u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0);
+                Some((Ipv6Addr::from(u128::from(addr) & mask).into(), prefix_len))
+            }
+            _ => None,
+        }
+    }
+
+    /// The container names the policies select by, leaving out the patterns.
+    fn container_names(&self) -> BTreeSet<String> {
+        self.policies
+            .iter()
+            .filter_map(|policy| policy.container.name.as_ref())
+            .filter(|name| !name.contains(['*', '?']))
+            .cloned()
+            .collect()
+    }
+
+    /// The name of a running container or of a container the policies select is a container,
+    /// whose addresses are resolved every time containers start or stop. Any other name is a host,
+    /// which `dns_events` looks up whenever its records expire, or until it resolves.
+    fn remote_host(container_names: &BTreeSet<String>, remote_host: &str) -> RemoteHost {
+        if container_names.contains(remote_host) {
+            RemoteHost::Container(remote_host.to_string())
+        } else {
+            RemoteHost::Domain(remote_host.to_string())
         }
     }
 
+    /// Converts the policies without looking up any host, which leaves the addresses of the
+    /// hosts empty, see [`Policies::set_domain_ips`].
     pub async fn to_policies(
         &self,
         containers: Arc<Mutex<domain::Containers>>,
     ) -> anyhow::Result<Arc<Mutex<Policies>>> {
-        let mut policies = Policies { policies: vec![] };
+        let mut policies = Policies {
+            default_action: ParsePolicies::default_action(&self.default_action, TcAction::Drop),
+            policies: vec![],
+            ..Default::default()
+        };
+
+        let mut container_names = self.container_names();
+        container_names.extend(
+            containers
+                .lock()
+                .await
+                .list()
+                .into_iter()
+                .map(|container| container.name.trim_start_matches('/').to_string()),
+        );
 
         for parsed_policy in &self.policies {
             let mut communications: Vec<domain::Communication> = vec![];
             for parsed_communication in &parsed_policy.communications {
                 let mut communication = domain::Communication {
                     process: parsed_communication.executable.clone(),
+                    executable: parsed_communication.executable_path.clone().map(|path| {
+                        domain::Executable {
+                            path,
+                            sha256: parsed_communication
+                                .executable_sha256
+                                .as_ref()
+                                .map(|sha256| sha256.to_lowercase()),
+                        }
+                    }),
+                    cgroup: parsed_communication
+                        .cgroup
+                        .clone()
+                        .map(|path| domain::Cgroup { path }),
+                    process_tree: parsed_communication
+                        .process_tree
+                        .clone()
+                        .map(|path| domain::ProcessTree { path }),
                     sockets: vec![],
                     icmp: vec![],
                 };
 
                 // socket
-                for parsed_socket in &parsed_communication.sockets {
+                for (_, parsed_socket) in self.sockets(parsed_communication, "") {
                     let socket = domain::Socket {
                         protocol: match parsed_socket.protocol {
                             Protocol::TCP => IpProtocol::TCP,
@@ -133,29 +1502,47 @@ impl ParsePolicies {
                             Protocol::None => IpProtocol::default(),
                         },
                         local_port: parsed_socket.local_port,
+                        local_port_range: parsed_socket
+                            .local_port_range
+                            .as_ref()
+                            .map(PortRange::to_range),
                         remote_ip: None,
+                        remote_prefix_len: None,
                         remote_port: parsed_socket.remote_port,
+                        remote_port_range: parsed_socket
+                            .remote_port_range
+                            .as_ref()
+                            .map(PortRange::to_range),
+                        remote_host: None,
+                        remote_ips: vec![],
+                        action: parsed_socket.action.to_tc_action(),
                     };
 
                     match &parsed_socket.remote_host {
-                        Some(remote_host) => {
-                            for addr in
-                                ParsePolicies::lookup_host(containers.clone(), remote_host).await
-                            {
-                                communication.sockets.push(domain::Socket {
-                                    protocol: socket.protocol.clone(),
-                                    local_port: socket.local_port,
-                                    remote_ip: Some(addr),
-                                    remote_port: socket.remote_port,
-                                })
+                        Some(remote_host) => match ParsePolicies::parse_remote_host(remote_host) {
+                            Some(addrs) => {
+                                for (addr, prefix_len) in addrs {
+                                    communication.sockets.push(domain::Socket {
+                                        remote_ip: Some(addr),
+                                        remote_prefix_len: prefix_len,
+                                        ..socket.clone()
+                                    })
+                                }
                             }
-                        }
+                            None => communication.sockets.push(domain::Socket {
+                                remote_host: Some(ParsePolicies::remote_host(
+                                    &container_names,
+                                    remote_host,
+                                )),
+                                ..socket
+                            }),
+                        },
                         None => communication.sockets.push(socket),
                     }
                 }
 
                 // icmp
-                for parsed_icmp in &parsed_communication.icmp {
+                for (_, parsed_icmp) in self.icmp(parsed_communication, "") {
                     let icmp_version = match parsed_icmp.version {
                         IcmpVersion::V4 => furui_common::IcmpVersion::V4,
                         IcmpVersion::V6 => furui_common::IcmpVersion::V6,
@@ -166,21 +1553,31 @@ impl ParsePolicies {
                         type_: parsed_icmp.type_,
                         code: parsed_icmp.code,
                         remote_ip: None,
+                        remote_prefix_len: None,
+                        remote_host: None,
+                        remote_ips: vec![],
+                        action: parsed_icmp.action.to_tc_action(),
                     };
 
                     match &parsed_icmp.remote_host {
-                        Some(remote_host) => {
-                            for addr in
-                                ParsePolicies::lookup_host(containers.clone(), remote_host).await
-                            {
-                                communication.icmp.push(domain::ICMP {
-                                    version: icmp_version,
-                                    type_: parsed_icmp.type_,
-                                    code: parsed_icmp.code,
-                                    remote_ip: Some(addr),
-                                })
+                        Some(remote_host) => match ParsePolicies::parse_remote_host(remote_host) {
+                            Some(addrs) => {
+                                for (addr, prefix_len) in addrs {
+                                    communication.icmp.push(domain::ICMP {
+                                        remote_ip: Some(addr),
+                                        remote_prefix_len: prefix_len,
+                                        ..icmp.clone()
+                                    })
+                                }
                             }
-                        }
+                            None => communication.icmp.push(domain::ICMP {
+                                remote_host: Some(ParsePolicies::remote_host(
+                                    &container_names,
+                                    remote_host,
+                                )),
+                                ..icmp
+                            }),
+                        },
                         None => communication.icmp.push(icmp),
                     }
                 }
@@ -189,13 +1586,15 @@ impl ParsePolicies {
             }
 
             policies.policies.push(domain::Policy {
-                container: domain::Container {
-                    id: None,
-                    ip_addresses: None,
-                    name: parsed_policy.container.name.clone(),
-                    pid: 0,
-                },
+                selector: parsed_policy.container.to_selector(),
+                container_ids: vec![],
+                audit: parsed_policy.mode == Mode::Audit,
+                default_action: ParsePolicies::default_action(
+                    &parsed_policy.default_action,
+                    policies.default_action,
+                ),
                 communications,
+                file: parsed_policy.file.clone(),
             })
         }
 
@@ -204,3 +1603,750 @@ impl ParsePolicies {
         Ok(Arc::new(Mutex::new(policies)))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn to_policies(contents: &str) -> Policies {
+        let parsed = ParsePolicies::parse(contents, Format::Yaml)
+            .unwrap_or_else(|(_, err)| panic!("{}", err));
+        let policies = parsed.to_policies(domain::Containers::new()).await.unwrap();
+
+        let policies = policies.lock().await.clone();
+        policies
+    }
+
+    #[tokio::test]
+    async fn default_action_is_inherited_from_the_top_level() {
+        let policies = to_policies(
+            r#"
+default_action: allow
+policies:
+  - container:
+      name: inherits
+  - container:
+      name: denies
+    default_action: deny
+"#,
+        )
+        .await;
+
+        assert_eq!(policies.default_action, TcAction::Pass);
+        assert_eq!(policies.policies[0].default_action, TcAction::Pass);
+        assert_eq!(policies.policies[1].default_action, TcAction::Drop);
+    }
+
+    #[tokio::test]
+    async fn default_action_is_deny_when_never_set() {
+        let policies = to_policies(
+            r#"
+policies:
+  - container:
+      name: nginx_test
+"#,
+        )
+        .await;
+
+        assert_eq!(policies.default_action, TcAction::Drop);
+        assert_eq!(policies.policies[0].default_action, TcAction::Drop);
+    }
+
+    #[tokio::test]
+    async fn remote_host_naming_a_selected_container_is_a_container() {
+        let policies = to_policies(
+            r#"
+policies:
+  - container:
+      name: web
+    communications:
+      - sockets:
+          - protocol: tcp
+            remote_host: db
+  - container:
+      name: db
+"#,
+        )
+        .await;
+
+        let socket = &policies.policies[0].communications[0].sockets[0];
+        assert_eq!(
+            socket.remote_host,
+            Some(RemoteHost::Container("db".to_string()))
+        );
+    }
+
+    fn parse(contents: &str) -> ParsePolicies {
+        ParsePolicies::parse(contents, Format::Yaml).unwrap_or_else(|(_, err)| panic!("{}", err))
+    }
+
+    fn files(files: &[(&str, &str)]) -> BTreeMap<PathBuf, ParsePolicies> {
+        files
+            .iter()
+            .map(|(file, contents)| (PathBuf::from(file), parse(contents)))
+            .collect()
+    }
+
+    const YAML: &str = r#"
+default_action: allow
+groups:
+  dns:
+    sockets:
+      - protocol: udp
+        remote_port: 53
+policies:
+  - container:
+      name: web
+    communications:
+      - executable: nginx
+        include:
+          - dns
+        sockets:
+          - protocol: tcp
+            local_port: 80
+          - protocol: tcp
+            remote_host: 10.0.0.0/8
+            remote_port_range: 8000-8080
+            action: deny
+        icmp:
+          - version: v4
+            type: 8
+"#;
+
+    const JSON: &str = r#"{
+  "default_action": "allow",
+  "groups": {
+    "dns": {
+      "sockets": [{ "protocol": "udp", "remote_port": 53 }]
+    }
+  },
+  "policies": [
+    {
+      "container": { "name": "web" },
+      "communications": [
+        {
+          "executable": "nginx",
+          "include": ["dns"],
+          "sockets": [
+            { "protocol": "tcp", "local_port": 80 },
+            {
+              "protocol": "tcp",
+              "remote_host": "10.0.0.0/8",
+              "remote_port_range": "8000-8080",
+              "action": "deny"
+            }
+          ],
+          "icmp": [{ "version": "v4", "type": 8 }]
+        }
+      ]
+    }
+  ]
+}"#;
+
+    const TOML: &str = r#"
+default_action = "allow"
+
+[groups.dns]
+sockets = [{ protocol = "udp", remote_port = 53 }]
+
+[[policies]]
+container = { name = "web" }
+
+[[policies.communications]]
+executable = "nginx"
+include = ["dns"]
+sockets = [
+  { protocol = "tcp", local_port = 80 },
+  { protocol = "tcp", remote_host = "10.0.0.0/8", remote_port_range = "8000-8080", action = "deny" },
+]
+icmp = [{ version = "v4", type = 8 }]
+"#;
+
+    #[test]
+    fn format_is_told_by_the_extension() {
+        for (path, format) in [
+            ("policy.yaml", Some(Format::Yaml)),
+            ("policy.yml", Some(Format::Yaml)),
+            ("policy.json", Some(Format::Json)),
+            ("policy.toml", Some(Format::Toml)),
+            ("policy.txt", None),
+            ("policy", None),
+        ] {
+            assert_eq!(Format::from_path(Path::new(path)), format, "{}", path);
+        }
+    }
+
+    #[test]
+    fn json_and_toml_are_read_like_yaml() {
+        let yaml = parse(YAML);
+
+        for (contents, format) in [(JSON, Format::Json), (TOML, Format::Toml)] {
+            let parsed = ParsePolicies::parse(contents, format)
+                .unwrap_or_else(|(_, err)| panic!("{:?}: {}", format, err));
+
+            assert_eq!(parsed, yaml, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn every_format_reads_what_it_writes() {
+        let parsed = parse(YAML);
+
+        for format in [Format::Yaml, Format::Json, Format::Toml] {
+            let written = parsed.to_string(format).unwrap();
+            let read = ParsePolicies::parse(&written, format)
+                .unwrap_or_else(|(_, err)| panic!("{:?}: {}\n{}", format, err, written));
+
+            assert_eq!(read, parsed, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn schema_describes_the_policy_format() {
+        let schema: serde_json::Value =
+            serde_json::from_str(&ParsePolicies::schema().unwrap()).unwrap();
+
+        assert_eq!(schema["title"], "ParsePolicies");
+        assert_eq!(schema["properties"]["policies"]["type"], "array");
+        assert_eq!(schema["definitions"]["PortRange"]["type"], "string");
+        assert!(schema["definitions"]["Socket"]["properties"]["remote_port_range"].is_object());
+    }
+
+    #[test]
+    fn port_range_is_start_dash_end() {
+        assert_eq!(
+            PortRange::try_from("30000-30100".to_string()),
+            Ok(PortRange {
+                start: 30000,
+                end: 30100
+            })
+        );
+        assert_eq!(
+            PortRange::try_from(" 80 - 80 ".to_string()),
+            Ok(PortRange { start: 80, end: 80 })
+        );
+        assert_eq!(String::from(PortRange { start: 1, end: 2 }), "1-2");
+    }
+
+    #[test]
+    fn port_range_rejects_anything_else() {
+        for value in ["80", "90-80", "a-b", "80-", "-80", "1-65536", "1-2-3"] {
+            assert_eq!(
+                PortRange::try_from(value.to_string()),
+                Err(format!("invalid port range: {}", value))
+            );
+        }
+    }
+
+    #[test]
+    fn cidr_clears_the_host_bits() {
+        assert_eq!(
+            ParsePolicies::parse_cidr("10.1.2.3", "8"),
+            Some((IpAddr::from([10, 0, 0, 0]), 8))
+        );
+        assert_eq!(
+            ParsePolicies::parse_cidr("192.168.1.1", "32"),
+            Some((IpAddr::from([192, 168, 1, 1]), 32))
+        );
+        assert_eq!(
+            ParsePolicies::parse_cidr("10.1.2.3", "0"),
+            Some((IpAddr::from([0, 0, 0, 0]), 0))
+        );
+        assert_eq!(
+            ParsePolicies::parse_cidr("fd00:1::1", "16"),
+            Some(("fd00::".parse().unwrap(), 16))
+        );
+    }
+
+    #[test]
+    fn cidr_rejects_a_prefix_longer_than_the_address() {
+        for (addr, prefix_len) in [
+            ("10.0.0.0", "33"),
+            ("fd00::", "129"),
+            ("10.0.0.0", "-1"),
+            ("10.0.0.0", "a"),
+            ("example.com", "8"),
+        ] {
+            assert_eq!(ParsePolicies::parse_cidr(addr, prefix_len), None);
+        }
+    }
+
+    #[test]
+    fn remote_host_is_an_address_a_network_or_a_name() {
+        assert_eq!(
+            ParsePolicies::parse_remote_host("10.0.0.1"),
+            Some(vec![(IpAddr::from([10, 0, 0, 1]), None)])
+        );
+        assert_eq!(
+            ParsePolicies::parse_remote_host("10.0.0.0/8"),
+            Some(vec![(IpAddr::from([10, 0, 0, 0]), Some(8))])
+        );
+        assert_eq!(
+            ParsePolicies::parse_remote_host("10.0.0.0/33"),
+            Some(vec![])
+        );
+        assert_eq!(ParsePolicies::parse_remote_host("example.com"), None);
+    }
+
+    #[test]
+    fn invalid_cidr_is_a_problem() {
+        let problems = parse(
+            r#"
+policies:
+  - container:
+      name: web
+    communications:
+      - sockets:
+          - protocol: tcp
+            remote_host: 10.0.0.0/8
+          - protocol: tcp
+            remote_host: 10.0.0.0/33
+        icmp:
+          - version: v6
+            type: 128
+            remote_host: fd00::/129
+"#,
+        )
+        .problems();
+
+        assert_eq!(problems.len(), 2);
+        assert_eq!(
+            problems[0].path,
+            "policies[0].communications[0].sockets[1].remote_host"
+        );
+        assert_eq!(problems[0].message, "invalid CIDR: 10.0.0.0/33");
+        assert_eq!(
+            problems[1].path,
+            "policies[0].communications[0].icmp[0].remote_host"
+        );
+        assert_eq!(problems[1].message, "invalid CIDR: fd00::/129");
+    }
+
+    #[test]
+    fn wildcard_is_only_a_leading_label() {
+        let problems = parse(
+            r#"
+policies:
+  - container:
+      name: web
+    communications:
+      - sockets:
+          - protocol: tcp
+            remote_host: "*.example.com"
+          - protocol: tcp
+            remote_host: "a.*.com"
+          - protocol: tcp
+            remote_host: "*example.com"
+          - protocol: tcp
+            remote_host: "*.*.com"
+"#,
+        )
+        .problems();
+
+        let messages: Vec<&str> = problems
+            .iter()
+            .map(|problem| problem.message.as_str())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "invalid wildcard domain: a.*.com, only a leading `*.` is allowed",
+                "invalid wildcard domain: *example.com, only a leading `*.` is allowed",
+                "invalid wildcard domain: *.*.com, only a leading `*.` is allowed",
+            ]
+        );
+    }
+
+    #[test]
+    fn wildcard_cannot_be_used_with_port_ranges_or_icmp() {
+        let problems = parse(
+            r#"
+policies:
+  - container:
+      name: web
+    communications:
+      - sockets:
+          - protocol: tcp
+            remote_host: "*.example.com"
+            remote_port_range: 8000-8080
+        icmp:
+          - version: v4
+            type: 8
+            remote_host: "*.example.com"
+"#,
+        )
+        .problems();
+
+        assert_eq!(problems.len(), 2);
+        assert_eq!(
+            problems[0].message,
+            "port ranges cannot be used with a wildcard domain: *.example.com"
+        );
+        assert_eq!(
+            problems[1].message,
+            "wildcard domains cannot be used with icmp: *.example.com"
+        );
+    }
+
+    #[test]
+    fn too_many_port_ranges_for_a_process_is_a_problem() {
+        let port_ranges = |ports: std::ops::Range<usize>| {
+            ports
+                .map(|i| {
+                    format!(
+                        "          - protocol: tcp\n            local_port_range: {}-{}\n",
+                        8000 + i * 10,
+                        8009 + i * 10
+                    )
+                })
+                .collect::<String>()
+        };
+
+        let problems = parse(&format!(
+            r#"
+policies:
+  - container:
+      name: web
+    communications:
+      - executable: nginx
+        sockets:
+{}      - executable: php-fpm
+        sockets:
+{}      - executable: nginx
+        sockets:
+{}"#,
+            port_ranges(0..PORT_RANGE_POLICIES_MAX - 1),
+            port_ranges(0..PORT_RANGE_POLICIES_MAX),
+            port_ranges(PORT_RANGE_POLICIES_MAX..PORT_RANGE_POLICIES_MAX + 2)
+        ))
+        .problems();
+
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].path, "policies[0].communications[0]");
+        assert_eq!(
+            problems[0].message,
+            format!(
+                "too many port ranges for the process, {} (max {})",
+                PORT_RANGE_POLICIES_MAX + 1,
+                PORT_RANGE_POLICIES_MAX
+            )
+        );
+    }
+
+    #[test]
+    fn deny_outranked_by_an_allow_is_a_warning() {
+        let problems = parse(
+            r#"
+policies:
+  - container:
+      name: web
+    communications:
+      - executable: curl
+        sockets:
+          - protocol: tcp
+            remote_port: 443
+          - remote_host: 169.254.169.254
+            action: deny
+          - protocol: udp
+            remote_host: 169.254.169.254
+            action: deny
+        icmp:
+          - version: v4
+            type: 8
+            code: 0
+          - version: v4
+            type: 8
+            remote_host: 10.0.0.0/8
+            action: deny
+"#,
+        )
+        .problems();
+
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].severity, Severity::Warning);
+        assert_eq!(problems[0].path, "policies[0].communications[0].sockets[1]");
+        assert_eq!(
+            problems[0].message,
+            "the allow policies[0].communications[0].sockets[0] is more specific and passes the packets both match, repeat its fields here to deny them"
+        );
+    }
+
+    #[test]
+    fn allow_carved_out_of_a_deny_is_not_a_problem() {
+        let problems = parse(
+            r#"
+policies:
+  - container:
+      name: web
+    communications:
+      - executable: psql
+        sockets:
+          - remote_host: 10.0.0.0/8
+            action: deny
+          - protocol: tcp
+            remote_host: 10.1.2.3
+            remote_port: 5432
+          - remote_host: "*.example.com"
+            action: deny
+          - protocol: tcp
+            remote_host: api.example.com
+            remote_port: 443
+"#,
+        )
+        .problems();
+
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn domain_cannot_be_used_with_port_ranges() {
+        let problems = parse(
+            r#"
+policies:
+  - container:
+      name: web
+    communications:
+      - sockets:
+          - protocol: tcp
+            remote_host: example.com
+            remote_port_range: 8000-8080
+          - protocol: tcp
+            remote_host: 10.0.0.0/8
+            remote_port_range: 8000-8080
+          - protocol: tcp
+            remote_host: web
+            local_port_range: 8000-8080
+"#,
+        )
+        .problems();
+
+        assert_eq!(problems.len(), 1);
+        assert_eq!(
+            problems[0].path,
+            "policies[0].communications[0].sockets[0].remote_host"
+        );
+        assert_eq!(
+            problems[0].message,
+            "port ranges cannot be used with a domain name: example.com, use remote_port or an address"
+        );
+    }
+
+    #[test]
+    fn domain_that_cannot_be_encoded_is_a_problem() {
+        let problems = parse(&format!(
+            r#"
+policies:
+  - container:
+      name: web
+    communications:
+      - sockets:
+          - protocol: tcp
+            remote_host: a..example.com
+          - protocol: tcp
+            remote_host: {}.example.com
+          - protocol: tcp
+            remote_host: example.com.
+"#,
+            "a".repeat(64)
+        ))
+        .problems();
+
+        let paths: Vec<&str> = problems
+            .iter()
+            .map(|problem| problem.path.as_str())
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                "policies[0].communications[0].sockets[0].remote_host",
+                "policies[0].communications[0].sockets[1].remote_host",
+            ]
+        );
+        assert_eq!(
+            problems[0].message,
+            "invalid domain name: a..example.com, each label has to be 1-63 bytes long and the name at most 126 bytes"
+        );
+    }
+
+    #[tokio::test]
+    async fn wildcard_is_never_looked_up() {
+        let policies = to_policies(
+            r#"
+policies:
+  - container:
+      name: web
+    communications:
+      - sockets:
+          - protocol: tcp
+            remote_host: "*.wordpress.org"
+            remote_port: 443
+          - protocol: tcp
+            remote_host: api.wordpress.org
+            remote_port: 443
+"#,
+        )
+        .await;
+
+        let sockets = &policies.policies[0].communications[0].sockets;
+        assert!(sockets[0].remote_host.as_ref().unwrap().is_wildcard());
+        assert!(!sockets[1].remote_host.as_ref().unwrap().is_wildcard());
+        assert_eq!(
+            policies.domains(),
+            BTreeSet::from(["api.wordpress.org".to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn socket_to_a_network_keeps_its_prefix_len() {
+        let policies = to_policies(
+            r#"
+policies:
+  - container:
+      name: web
+    communications:
+      - sockets:
+          - protocol: tcp
+            remote_host: 10.1.0.0/16
+            remote_port: 443
+"#,
+        )
+        .await;
+
+        let socket = &policies.policies[0].communications[0].sockets[0];
+        assert_eq!(socket.remote_ip, Some(IpAddr::from([10, 1, 0, 0])));
+        assert_eq!(socket.remote_prefix_len, Some(16));
+        assert_eq!(socket.remote_host, None);
+    }
+
+    #[test]
+    fn invalid_name_regex_is_a_problem() {
+        let problems = parse("policies:\n  - container:\n      name_regex: web-(\n").problems();
+
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].severity, Severity::Error);
+        assert_eq!(problems[0].path, "policies[0].container.name_regex");
+        assert!(problems[0].message.starts_with("invalid name_regex: "));
+    }
+
+    #[test]
+    fn policy_for_a_container_is_selected_by_its_name() {
+        let policy = Policy::parse_for(
+            "web",
+            "communications:\n  - sockets:\n      - protocol: tcp\n        local_port: 80\n",
+        )
+        .unwrap();
+
+        assert_eq!(policy.container, Container::named("web"));
+        assert_eq!(policy.communications.len(), 1);
+    }
+
+    #[test]
+    fn policy_for_a_container_rejects_a_selector_or_a_mistake() {
+        let err = Policy::parse_for("web", "container:\n  name: db\n").unwrap_err();
+        assert_eq!(err.to_string(), "the container is selected by its name");
+
+        let err = Policy::parse_for("web", "- sockets: []\n").unwrap_err();
+        assert_eq!(err.to_string(), "the policy has to be a mapping");
+
+        let err = Policy::parse_for(
+            "web",
+            "communications:\n  - sockets:\n      - protocol: tcp\n        remote_host: 10.0.0.0/33\n",
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "communications[0].sockets[0].remote_host: invalid CIDR: 10.0.0.0/33"
+        );
+    }
+
+    #[test]
+    fn merge_keeps_the_file_of_each_policy() {
+        let merged = ParsePolicies::merge(&files(&[
+            (
+                "a.yaml",
+                "default_action: allow\npolicies:\n  - container:\n      name: web\n",
+            ),
+            (
+                "b.yaml",
+                "default_action: allow\npolicies:\n  - container:\n      name: db\n",
+            ),
+        ]))
+        .unwrap();
+
+        assert_eq!(merged.default_action, Some(Action::Allow));
+        assert_eq!(merged.policies.len(), 2);
+        assert_eq!(merged.policies[0].file, Some(PathBuf::from("a.yaml")));
+        assert_eq!(merged.policies[1].file, Some(PathBuf::from("b.yaml")));
+    }
+
+    #[test]
+    fn merge_rejects_conflicting_default_actions() {
+        let err = ParsePolicies::merge(&files(&[
+            ("a.yaml", "default_action: allow\npolicies: []\n"),
+            ("b.yaml", "policies: []\n"),
+            ("c.yaml", "default_action: deny\npolicies: []\n"),
+        ]))
+        .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "conflicting default_action in a.yaml and c.yaml"
+        );
+    }
+
+    #[test]
+    fn merge_rejects_duplicate_containers() {
+        let web = "policies:\n  - container:\n      name: web\n";
+
+        let err = ParsePolicies::merge(&files(&[("a.yaml", web), ("b.yaml", web)])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "duplicate container web in a.yaml and b.yaml"
+        );
+
+        let err = ParsePolicies::merge(&files(&[(
+            "a.yaml",
+            "policies:\n  - container:\n      name: web\n  - container:\n      name: web\n",
+        )]))
+        .unwrap_err();
+        assert_eq!(err.to_string(), "duplicate container web in a.yaml");
+    }
+
+    #[test]
+    fn groups_are_written_out_after_the_own_rules() {
+        let parsed = parse(
+            r#"
+groups:
+  dns:
+    sockets:
+      - protocol: udp
+        remote_port: 53
+policies:
+  - container:
+      name: web
+    communications:
+      - executable: nginx
+        include: [dns]
+        sockets:
+          - protocol: tcp
+            local_port: 80
+"#,
+        );
+
+        let communication = &parsed.expand_groups()[0].communications[0];
+
+        assert!(communication.include.is_empty());
+        assert_eq!(communication.executable, Some("nginx".to_string()));
+        assert_eq!(
+            communication
+                .sockets
+                .iter()
+                .map(|socket| (socket.protocol, socket.local_port, socket.remote_port))
+                .collect::<Vec<_>>(),
+            vec![
+                (Protocol::TCP, Some(80), None),
+                (Protocol::UDP, None, Some(53)),
+            ]
+        );
+    }
+}
@@ -1,16 +1,21 @@
 use std::{
-    fs::File,
+    fs::{self, File},
     io::{BufRead, BufReader, Read},
+    os::unix::fs::MetadataExt,
     path::Path,
     str::FromStr,
     sync::Arc,
 };
 
+use anyhow::anyhow;
 use furui_common::IpProtocol;
 use tokio::sync::Mutex;
 use tracing::error;
 
-use crate::domain::{Containers, Process};
+use crate::domain::{Container, Containers, Process};
+
+/// How many ancestors of a process are looked at, the process included.
+const ANCESTORS_MAX: usize = 32;
 
 static SUPPORTED_PROTOCOLS: [(&str, IpProtocol); 4] = [
     ("tcp", IpProtocol::TCP),
@@ -23,47 +28,61 @@ pub async fn get_all(containers: Arc<Mutex<Containers>>) -> Vec<Process> {
     let mut processes = vec![];
 
     for container in containers.lock().await.list() {
-        for (supported_protocol, proto) in &SUPPORTED_PROTOCOLS {
-            let net_file_path = Path::new("/proc")
-                .join(format!("{}", container.pid))
-                .join("net")
-                .join(supported_protocol);
-
-            let net_file = match File::open(net_file_path) {
-                Ok(file) => file,
+        processes.extend(get(&container));
+    }
+
+    processes
+}
+
+/// The processes of a container with the ports they bound.
+pub fn get(container: &Container) -> Vec<Process> {
+    let mut processes = vec![];
+
+    for (supported_protocol, proto) in &SUPPORTED_PROTOCOLS {
+        let net_file_path = Path::new("/proc")
+            .join(format!("{}", container.pid))
+            .join("net")
+            .join(supported_protocol);
+
+        let net_file = match File::open(net_file_path) {
+            Ok(file) => file,
+            Err(_) => continue,
+        };
+
+        let mut net_file_buf = BufReader::new(net_file).lines();
+        net_file_buf.next();
+
+        while let Some(Ok(line)) = net_file_buf.next() {
+            let row = line.split_whitespace().collect::<Vec<&str>>();
+
+            let port = match u16::from_str_radix(row[1].split(":").collect::<Vec<&str>>()[1], 16) {
+                Ok(port) => port,
                 Err(_) => continue,
             };
 
-            let mut net_file_buf = BufReader::new(net_file).lines();
-            net_file_buf.next();
-
-            while let Some(Ok(line)) = net_file_buf.next() {
-                let row = line.split_whitespace().collect::<Vec<&str>>();
-
-                let port =
-                    match u16::from_str_radix(row[1].split(":").collect::<Vec<&str>>()[1], 16) {
-                        Ok(port) => port,
-                        Err(_) => continue,
-                    };
-
-                let inode: u64 = match row[9].parse() {
-                    Ok(port) => port,
-                    Err(_) => continue,
-                };
-
-                let (executable, pid) = match search_process_from_inode(container.pid, inode) {
-                    Some(value) => value,
-                    None => continue,
-                };
-
-                processes.push(Process {
-                    container_id: container.id.clone().unwrap(),
-                    executable,
-                    protocol: *proto,
-                    port,
-                    pid,
-                })
-            }
+            let inode: u64 = match row[9].parse() {
+                Ok(port) => port,
+                Err(_) => continue,
+            };
+
+            let (executable, pid) = match search_process_from_inode(container.pid, inode) {
+                Some(value) => value,
+                None => continue,
+            };
+
+            let (exe_ino, exe_dev) = get_exe(pid);
+            let cgroup_id = get_cgroup_id(pid);
+
+            processes.push(Process {
+                container_id: container.id.clone().unwrap(),
+                executable,
+                protocol: *proto,
+                port,
+                pid,
+                exe_ino,
+                exe_dev,
+                cgroup_id,
+            })
         }
     }
 
@@ -84,24 +103,136 @@ fn search_process_from_inode(pid: u32, inode: u64) -> Option<(String, u32)> {
     None
 }
 
+/// The inode and device of the executable of a process, with the device numbered the way the
+/// kernel does, where the minor number takes the low 20 bits.
+fn get_exe(pid: u32) -> (u64, u32) {
+    let path = Path::new("/proc").join(format!("{}", pid)).join("exe");
+
+    match fs::metadata(path) {
+        Ok(metadata) => {
+            let dev = metadata.dev();
+            (metadata.ino(), (libc::major(dev) << 20) | libc::minor(dev))
+        }
+        Err(_) => (0, 0),
+    }
+}
+
+/// Fails unless cgroup v2 alone is mounted on `/sys/fs/cgroup`. With cgroup v1, or with the
+/// hybrid layout where the containers are only told apart in the v1 hierarchies, the kprobes
+/// cannot find the container of a process by its cgroup.
+pub fn check_cgroup_v2() -> anyhow::Result<()> {
+    check_cgroup_v2_at(Path::new("/sys/fs/cgroup"), get_cgroup(std::process::id()))
+}
+
+fn check_cgroup_v2_at(cgroup_root: &Path, own_cgroup: Option<String>) -> anyhow::Result<()> {
+    if !cgroup_root.join("cgroup.controllers").exists() {
+        return Err(anyhow!(
+            "cgroup v2 is not mounted on /sys/fs/cgroup, furui needs the unified cgroup hierarchy \
+             (systemd.unified_cgroup_hierarchy=1)"
+        ));
+    }
+
+    if own_cgroup.is_none() {
+        return Err(anyhow!(
+            "the cgroup v2 path of furui is not in /proc/self/cgroup"
+        ));
+    }
+
+    Ok(())
+}
+
+/// The cgroup v2 path of a process, or `None` without cgroup v2.
+pub fn get_cgroup(pid: u32) -> Option<String> {
+    let path = Path::new("/proc").join(format!("{}", pid)).join("cgroup");
+
+    parse_cgroup(&fs::read_to_string(path).ok()?)
+}
+
+/// The cgroup v2 path in the contents of `/proc/<pid>/cgroup`, which is on the line of hierarchy 0.
+fn parse_cgroup(contents: &str) -> Option<String> {
+    contents
+        .lines()
+        .find_map(|line| line.strip_prefix("0::").map(|path| path.to_string()))
+}
+
+/// The cgroup v2 ID of a process, which is the inode of its cgroup directory.
+pub fn get_cgroup_id(pid: u32) -> u64 {
+    let path = match get_cgroup(pid) {
+        Some(path) => Path::new("/sys/fs/cgroup").join(path.trim_start_matches('/')),
+        None => return 0,
+    };
+
+    fs::metadata(path)
+        .map(|metadata| metadata.ino())
+        .unwrap_or(0)
+}
+
+/// The parent of a process, or 0 when it is gone.
 fn get_ppid(pid: u32) -> u32 {
     let path = Path::new("/proc").join(format!("{}", pid)).join("stat");
 
-    let file = match File::open(path) {
-        Ok(file) => file,
-        Err(_) => return 0,
-    };
+    match fs::read_to_string(path) {
+        Ok(stat) => parse_ppid(&stat),
+        Err(_) => 0,
+    }
+}
+
+/// The parent in the contents of `/proc/<pid>/stat`. The fields are read after the process name,
+/// which may contain spaces and parentheses.
+fn parse_ppid(stat: &str) -> u32 {
+    stat.rsplit_once(')')
+        .and_then(|(_, fields)| fields.split_whitespace().nth(1))
+        .and_then(|ppid| u32::from_str(ppid).ok())
+        .unwrap_or(0)
+}
 
-    let mut file_buf = BufReader::new(file).lines();
-    u32::from_str(
-        file_buf
-            .next()
-            .unwrap()
-            .unwrap()
-            .split_whitespace()
-            .collect::<Vec<&str>>()[3],
+/// The executables of a process and of its ancestors in the same pid namespace, so in the same
+/// container, the process first.
+pub fn get_ancestry(pid: u32) -> Vec<String> {
+    let proc_path = |pid: u32| Path::new("/proc").join(pid.to_string());
+
+    ancestry(
+        pid,
+        |pid| {
+            fs::read_link(proc_path(pid).join("exe"))
+                .ok()
+                .map(|path| path.to_string_lossy().to_string())
+        },
+        get_ppid,
+        |pid| {
+            fs::metadata(proc_path(pid).join("ns/pid"))
+                .ok()
+                .map(|ns| ns.ino())
+        },
     )
-    .unwrap_or(0)
+}
+
+fn ancestry(
+    pid: u32,
+    exe: impl Fn(u32) -> Option<String>,
+    ppid: impl Fn(u32) -> u32,
+    pid_ns: impl Fn(u32) -> Option<u64>,
+) -> Vec<String> {
+    let mut ancestry = vec![];
+    let own_pid_ns = match pid_ns(pid) {
+        Some(own_pid_ns) => own_pid_ns,
+        None => return ancestry,
+    };
+
+    let mut pid = pid;
+    for _ in 0..ANCESTORS_MAX {
+        match exe(pid) {
+            Some(path) => ancestry.push(path),
+            None => break,
+        }
+
+        pid = ppid(pid);
+        if pid == 0 || pid_ns(pid) != Some(own_pid_ns) {
+            break;
+        }
+    }
+
+    ancestry
 }
 
 fn get_child_pids(pid: u32) -> Vec<u32> {
@@ -189,3 +320,112 @@ fn get_process_name(pid: u32) -> String {
 
     name.trim().to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, env, os::unix::process::parent_id, process};
+
+    use super::*;
+
+    #[test]
+    fn cgroup_is_the_path_of_hierarchy_0() {
+        assert_eq!(
+            parse_cgroup("0::/system.slice/docker-3f4e8a9b2c1d.scope\n"),
+            Some("/system.slice/docker-3f4e8a9b2c1d.scope".to_string())
+        );
+        assert_eq!(
+            parse_cgroup("12:pids:/docker/3f4e\n1:name=systemd:/docker/3f4e\n0::/docker/3f4e\n"),
+            Some("/docker/3f4e".to_string())
+        );
+        assert_eq!(
+            parse_cgroup("12:pids:/docker/3f4e\n1:name=systemd:/docker/3f4e\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn cgroup_v2_has_to_be_mounted_alone() {
+        let cgroup_root = env::temp_dir().join(format!("furui-cgroup-{}", process::id()));
+        fs::create_dir_all(&cgroup_root).unwrap();
+
+        // cgroup v1, or the hybrid layout that mounts cgroup v2 under `unified`.
+        let err = check_cgroup_v2_at(&cgroup_root, Some("/".to_string())).unwrap_err();
+        assert!(err.to_string().starts_with("cgroup v2 is not mounted"));
+
+        fs::write(cgroup_root.join("cgroup.controllers"), "cpu memory pids\n").unwrap();
+        assert!(check_cgroup_v2_at(&cgroup_root, Some("/".to_string())).is_ok());
+
+        let err = check_cgroup_v2_at(&cgroup_root, None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the cgroup v2 path of furui is not in /proc/self/cgroup"
+        );
+
+        fs::remove_dir_all(&cgroup_root).unwrap();
+    }
+
+    #[test]
+    fn ppid_is_read_after_the_process_name() {
+        assert_eq!(parse_ppid("1234 (nginx) S 1200 1234 1234 0 -1"), 1200);
+        assert_eq!(parse_ppid("1234 (a) b (c) S 1200 1234 1234 0 -1"), 1200);
+        assert_eq!(parse_ppid("1234 (my server) R 7 1234 1234 0 -1"), 7);
+        assert_eq!(parse_ppid("1234 (nginx)"), 0);
+        assert_eq!(parse_ppid(""), 0);
+    }
+
+    #[test]
+    fn ppid_of_this_process_is_its_parent() {
+        assert_eq!(get_ppid(process::id()), parent_id());
+    }
+
+    /// A container with nginx started by a shell under `tini`, whose parent is the shim outside.
+    fn processes() -> HashMap<u32, (&'static str, u32, u64)> {
+        HashMap::from([
+            (1, ("/usr/bin/containerd-shim", 0, 1)),
+            (100, ("/sbin/tini", 1, 2)),
+            (101, ("/bin/sh", 100, 2)),
+            (102, ("/usr/sbin/nginx", 101, 2)),
+            (103, ("/usr/sbin/nginx", 102, 2)),
+        ])
+    }
+
+    fn ancestry_of(pid: u32) -> Vec<String> {
+        let processes = processes();
+
+        ancestry(
+            pid,
+            |pid| processes.get(&pid).map(|(exe, _, _)| exe.to_string()),
+            |pid| processes.get(&pid).map_or(0, |(_, ppid, _)| *ppid),
+            |pid| processes.get(&pid).map(|(_, _, pid_ns)| *pid_ns),
+        )
+    }
+
+    #[test]
+    fn ancestry_stops_at_the_pid_namespace_of_the_process() {
+        assert_eq!(
+            ancestry_of(103),
+            vec![
+                "/usr/sbin/nginx",
+                "/usr/sbin/nginx",
+                "/bin/sh",
+                "/sbin/tini"
+            ]
+        );
+        assert_eq!(ancestry_of(100), vec!["/sbin/tini"]);
+    }
+
+    #[test]
+    fn ancestry_of_a_process_that_is_gone_is_empty() {
+        assert!(ancestry_of(200).is_empty());
+    }
+
+    #[test]
+    fn ancestry_of_this_process_starts_with_its_executable() {
+        let ancestry = get_ancestry(process::id());
+
+        assert_eq!(
+            ancestry.first().map(String::as_str),
+            env::current_exe().unwrap().to_str()
+        );
+    }
+}
@@ -13,11 +13,14 @@ use serde_yaml::Value;
 use tokio::{net::UnixStream, sync::Mutex};
 use tonic::transport::{Channel, Endpoint};
 use tower::service_fn;
+use tracing::warn;
 
 use crate::{
     domain::{Container, Containers},
+    process,
     runtime::k8s_cri::{
         ContainerStateValue, ContainerStatusRequest, ExecSyncRequest, GetEventsRequest,
+        PodSandboxStatusRequest,
     },
     ContainerRuntime, Options,
 };
@@ -90,16 +93,27 @@ impl Runtime {
                     .as_ref()
                     .unwrap()
                     .set_container_inspect(container)
-                    .await
+                    .await?
             }
             ContainerRuntime::KubernetesCri => {
                 self.kubernetes_cri
                     .as_ref()
                     .unwrap()
                     .set_container_inspect(container)
-                    .await
+                    .await?
             }
         }
+
+        // The kprobes know the processes of the container by its cgroup.
+        container.cgroup_id = process::get_cgroup_id(container.pid);
+        if container.cgroup_id == 0 {
+            warn!(
+                "the cgroup of container {} is not known, the ports its processes bind are not seen",
+                container.name
+            );
+        }
+
+        Ok(())
     }
 
     pub async fn add_running_containers_inspect(
@@ -179,9 +193,13 @@ impl RuntimeDocker {
             };
         }
 
+        let config = inspect.config.unwrap();
+
         container.ip_addresses = Some(addrs);
         container.name = inspect.name.unwrap();
         container.pid = inspect.state.unwrap().pid.unwrap() as u32;
+        container.labels = config.labels.unwrap_or_default();
+        container.image = config.image.unwrap_or_default();
 
         Ok(())
     }
@@ -259,11 +277,26 @@ impl RuntimeKubernetesCri {
         });
         let inspect = self.cri.clone().container_status(request).await?;
 
-        let metadata = inspect.get_ref().status.clone().unwrap().metadata.unwrap();
+        let status = inspect.get_ref().status.clone().unwrap();
+        let metadata = status.metadata.unwrap();
         let raw_info = &inspect.get_ref().info.get("info").unwrap();
 
         let info: Value = serde_json::from_str(raw_info).unwrap();
 
+        // Selectors are usually written against the pod labels, so those are merged in.
+        let mut labels = status.labels;
+        if let Some(pod_sandbox_id) = info["sandboxID"].as_str() {
+            let request = tonic::Request::new(PodSandboxStatusRequest {
+                pod_sandbox_id: pod_sandbox_id.to_string(),
+                verbose: false,
+            });
+            let pod_sandbox = self.cri.clone().pod_sandbox_status(request).await?;
+
+            if let Some(pod_sandbox_status) = &pod_sandbox.get_ref().status {
+                labels.extend(pod_sandbox_status.labels.clone());
+            }
+        }
+
         let mut addrs: Vec<IpAddr> = vec![];
         addrs.extend(self.get_ipv4_addresses(container_id.clone()).await?);
         addrs.extend(self.get_ipv6_addresses(container_id).await?);
@@ -271,6 +304,8 @@ impl RuntimeKubernetesCri {
         container.ip_addresses = Some(addrs);
         container.name = metadata.name;
         container.pid = info["pid"].as_u64().unwrap() as u32;
+        container.labels = labels;
+        container.image = status.image.map(|image| image.image).unwrap_or_default();
 
         Ok(())
     }
@@ -7,10 +7,14 @@ mod common;
 #[tokio::test]
 async fn nginx() {
     let opt = Options {
+        command: None,
         container_engine: ContainerRuntime::Docker,
-        policy_path: PathBuf::from("../example/nginx.yaml"),
+        policy_path: Some(PathBuf::from("../example/nginx.yaml")),
         log_level: LogLevel::Warn,
         log_fmt: LogFormat::Text,
+        audit: false,
+        control_socket: PathBuf::from("/run/furui/furui.sock"),
+        metrics_addr: None,
     };
 
     unsafe { furui::start(opt).await.unwrap() };
@@ -0,0 +1,366 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use toml_edit::{ImDocument, Item, Key, Table, Value};
+use yaml_rust2::{
+    parser::{Event, MarkedEventReceiver, Parser},
+    scanner::Marker,
+};
+
+use crate::parse_policies::{Format, ParsePolicies, Problem, Severity};
+
+/// A line and a column in a policy file, both starting at 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    pub const START: Position = Position { line: 1, column: 1 };
+
+    /// The position of a byte offset into `contents`.
+    pub fn from_offset(contents: &str, offset: usize) -> Position {
+        let before = &contents[..offset.min(contents.len())];
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+
+        Position {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// Where each value of a policy file starts, by paths such as
+/// `policies[0].communications[1].sockets[0].remote_host`. JSON is read as YAML.
+pub struct Locations {
+    positions: HashMap<String, Position>,
+    stack: Vec<Node>,
+}
+
+enum Node {
+    Mapping { path: String, key: Option<String> },
+    Sequence { path: String, index: usize },
+}
+
+impl Locations {
+    pub fn new(contents: &str, format: Format) -> Locations {
+        let mut locations = Locations {
+            positions: HashMap::new(),
+            stack: vec![],
+        };
+
+        // The file has already been parsed, so an error only leaves some positions out.
+        match format {
+            Format::Yaml | Format::Json => {
+                let _ = Parser::new_from_str(contents).load(&mut locations, false);
+            }
+            Format::Toml => {
+                if let Ok(document) = ImDocument::parse(contents) {
+                    locations.insert_toml_table(contents, "", document.as_table());
+                }
+            }
+        }
+
+        locations
+    }
+
+    /// The position of the value at `path`, or of the closest value containing it when the
+    /// value itself is missing from the file.
+    pub fn position(&self, path: &str) -> Position {
+        let mut path = path;
+
+        loop {
+            if let Some(position) = self.positions.get(path) {
+                return *position;
+            }
+
+            match path.rfind(['.', '[']) {
+                Some(end) => path = &path[..end],
+                None => return self.positions.get("").copied().unwrap_or(Position::START),
+            }
+        }
+    }
+
+    /// The path of the next value in the current mapping or sequence.
+    fn next_path(&mut self) -> String {
+        match self.stack.last_mut() {
+            Some(Node::Mapping { path, key }) => join(path, &key.take().unwrap_or_default()),
+            Some(Node::Sequence { path, index }) => {
+                let path = format!("{}[{}]", path, index);
+                *index += 1;
+                path
+            }
+            None => String::new(),
+        }
+    }
+
+    fn insert_toml_table(&mut self, contents: &str, path: &str, table: &Table) {
+        self.insert_span(contents, path, table.span());
+
+        for (key, item) in table.iter() {
+            let item_path = join(path, key);
+
+            // Tables made implicitly by a dotted key only have the span of the key.
+            self.insert_span(contents, &item_path, table.key(key).and_then(Key::span));
+
+            match item {
+                Item::Value(value) => self.insert_toml_value(contents, &item_path, value),
+                Item::Table(table) => self.insert_toml_table(contents, &item_path, table),
+                Item::ArrayOfTables(tables) => {
+                    for (i, table) in tables.iter().enumerate() {
+                        let table_path = format!("{}[{}]", item_path, i);
+                        self.insert_toml_table(contents, &table_path, table);
+                    }
+                }
+                Item::None => {}
+            }
+        }
+    }
+
+    fn insert_toml_value(&mut self, contents: &str, path: &str, value: &Value) {
+        // Overrides the position of the key, the value is more precise.
+        if let Some(span) = value.span() {
+            self.positions.insert(
+                path.to_string(),
+                Position::from_offset(contents, span.start),
+            );
+        }
+
+        match value {
+            Value::Array(array) => {
+                for (i, value) in array.iter().enumerate() {
+                    self.insert_toml_value(contents, &format!("{}[{}]", path, i), value);
+                }
+            }
+            Value::InlineTable(table) => {
+                for (key, value) in table.iter() {
+                    self.insert_toml_value(contents, &join(path, key), value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn insert_span(&mut self, contents: &str, path: &str, span: Option<Range<usize>>) {
+        if let Some(span) = span {
+            self.positions
+                .entry(path.to_string())
+                .or_insert_with(|| Position::from_offset(contents, span.start));
+        }
+    }
+
+    fn insert(&mut self, path: &str, mark: Marker) {
+        self.positions.entry(path.to_string()).or_insert(Position {
+            line: mark.line(),
+            column: mark.col() + 1,
+        });
+    }
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+impl MarkedEventReceiver for Locations {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        match ev {
+            Event::Scalar(value, ..) => {
+                if let Some(Node::Mapping {
+                    key: key @ None, ..
+                }) = self.stack.last_mut()
+                {
+                    *key = Some(value);
+                    return;
+                }
+
+                let path = self.next_path();
+                self.insert(&path, mark);
+            }
+            Event::Alias(_) => {
+                let path = self.next_path();
+                self.insert(&path, mark);
+            }
+            Event::MappingStart(..) => {
+                let path = self.next_path();
+                self.insert(&path, mark);
+                self.stack.push(Node::Mapping { path, key: None });
+            }
+            Event::SequenceStart(..) => {
+                let path = self.next_path();
+                self.insert(&path, mark);
+                self.stack.push(Node::Sequence { path, index: 0 });
+            }
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Checks a policy file, or every policy file in a directory and how they merge, and prints
+/// each problem as `file:line:column: severity: message`.
+pub fn validate(path: &Path) -> anyhow::Result<()> {
+    let files = if path.is_dir() {
+        ParsePolicies::policy_files(path)?
+    } else {
+        vec![path.to_path_buf()]
+    };
+
+    let mut errors = 0;
+    let mut warnings = 0;
+    let mut parsed_files: Option<BTreeMap<PathBuf, ParsePolicies>> = Some(BTreeMap::new());
+
+    for file in &files {
+        let contents = fs::read_to_string(file)
+            .map_err(|err| anyhow!("failed to open {}: {}", file.display(), err))?;
+
+        let format = Format::from_path(file).unwrap_or(Format::Yaml);
+
+        let parsed_policies = match ParsePolicies::parse(&contents, format) {
+            Ok(parsed_policies) => parsed_policies,
+            Err((position, message)) => {
+                println!("{}:{}: error: {}", file.display(), position, message);
+                errors += 1;
+                parsed_files = None;
+                continue;
+            }
+        };
+
+        let locations = Locations::new(&contents, format);
+        let problems: Vec<Problem> = parsed_policies
+            .problems()
+            .into_iter()
+            .chain(parsed_policies.unresolvable_hosts())
+            .collect();
+
+        for problem in problems {
+            let severity = match problem.severity {
+                Severity::Error => {
+                    errors += 1;
+                    "error"
+                }
+                Severity::Warning => {
+                    warnings += 1;
+                    "warning"
+                }
+            };
+
+            println!(
+                "{}:{}: {}: {}",
+                file.display(),
+                locations.position(&problem.path),
+                severity,
+                problem.message
+            );
+        }
+
+        if let Some(parsed_files) = &mut parsed_files {
+            parsed_files.insert(file.clone(), parsed_policies);
+        }
+    }
+
+    // Files that do not parse would only make the merge fail for the wrong reason.
+    if let Some(parsed_files) = &parsed_files {
+        if let Err(err) = ParsePolicies::merge(parsed_files) {
+            println!("{}: error: {}", path.display(), err);
+            errors += 1;
+        }
+    }
+
+    if errors > 0 {
+        return Err(anyhow!(
+            "{} error(s) and {} warning(s) in {}",
+            errors,
+            warnings,
+            path.display()
+        ));
+    }
+
+    println!("{} is valid, {} warning(s)", path.display(), warnings);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(line: usize, column: usize) -> Position {
+        Position { line, column }
+    }
+
+    #[test]
+    fn yaml_positions_point_at_the_values() {
+        let contents = r#"policies:
+  - container:
+      name: web
+    communications:
+      - sockets:
+          - protocol: tcp
+            remote_port: 443
+"#;
+        let locations = Locations::new(contents, Format::Yaml);
+
+        assert_eq!(
+            locations.position("policies[0].container.name"),
+            position(3, 13)
+        );
+        assert_eq!(
+            locations.position("policies[0].communications[0].sockets[0].remote_port"),
+            position(7, 26)
+        );
+        // A value missing from the file points at the closest value containing it.
+        assert_eq!(
+            locations.position("policies[0].communications[0].sockets[0].local_port"),
+            locations.position("policies[0].communications[0].sockets[0]")
+        );
+    }
+
+    #[test]
+    fn toml_positions_point_at_the_values() {
+        let contents = r#"[[policies]]
+container = { name = "web" }
+
+[[policies.communications]]
+sockets = [{ protocol = "tcp", remote_port = 443 }]
+"#;
+        let locations = Locations::new(contents, Format::Toml);
+
+        assert_eq!(
+            locations.position("policies[0].container.name"),
+            position(2, 22)
+        );
+        assert_eq!(
+            locations.position("policies[0].communications[0].sockets[0].remote_port"),
+            position(5, 46)
+        );
+        assert_eq!(
+            locations.position("policies[0].communications[0].sockets[0].local_port"),
+            position(5, 12)
+        );
+        assert_eq!(locations.position("default_action"), position(1, 1));
+    }
+
+    #[test]
+    fn offsets_count_characters_from_the_line_start() {
+        assert_eq!(Position::from_offset("a: é\nb: 1", 5), position(1, 5));
+        assert_eq!(Position::from_offset("a: é\nb: 1", 9), position(2, 4));
+    }
+}